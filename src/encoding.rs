@@ -0,0 +1,94 @@
+use clap::ValueEnum;
+use encoding_rs::{UTF_16BE, UTF_16LE, WINDOWS_1252};
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use std::io::Read;
+
+/// The character encoding of an input, which is decoded to UTF-8 before its records are read.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// UTF-16, least significant byte first.
+    Utf16le,
+    /// UTF-16, most significant byte first.
+    Utf16be,
+    /// ISO-8859-1, decoded as windows-1252 like text labelled ISO-8859-1 on the web, which gives
+    /// each byte the character of the same code point outside of 0x80 to 0x9F.
+    Latin1,
+}
+
+impl Encoding {
+    /// Reads the text of `inner` in this encoding, as UTF-8. A byte order mark at the start of
+    /// `inner` is removed, and overrides the encoding unless it's `Latin1`, where it would be two
+    /// ordinary characters. Invalid UTF-16 is decoded as U+FFFD replacement characters; UTF-8 is
+    /// passed through as it is, to be checked as records are read.
+    pub fn decode<R: Read>(self, inner: R) -> DecodeReaderBytes<R, Vec<u8>> {
+        let encoding = match self {
+            Encoding::Utf8 => None,
+            Encoding::Utf16le => Some(UTF_16LE),
+            Encoding::Utf16be => Some(UTF_16BE),
+            Encoding::Latin1 => Some(WINDOWS_1252),
+        };
+        let bom = self != Encoding::Latin1;
+        DecodeReaderBytesBuilder::new()
+            .encoding(encoding)
+            .bom_override(bom)
+            .strip_bom(bom)
+            .utf8_passthru(true)
+            .build(inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8], encoding: Encoding) -> String {
+        let mut text = String::new();
+        encoding.decode(bytes).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_utf16() {
+        let le: Vec<u8> = "a\né\n😀\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let be: Vec<u8> = "a\né\n😀\n"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        assert_eq!("a\né\n😀\n", decode(&le, Encoding::Utf16le));
+        assert_eq!("a\né\n😀\n", decode(&be, Encoding::Utf16be));
+        // The byte order mark is removed, and says which order the bytes are in.
+        let with_bom: Vec<u8> = [0xFE, 0xFF].iter().chain(&be).copied().collect();
+        assert_eq!("a\né\n😀\n", decode(&with_bom, Encoding::Utf16le));
+        assert_eq!("a\né\n😀\n", decode(&with_bom, Encoding::Utf8));
+        assert!(decode(&[b'a', 0, 0x00, 0xD8, 1], Encoding::Utf16le).starts_with("a\u{FFFD}"));
+    }
+
+    #[test]
+    fn test_surrogate_pair_across_reads() {
+        let le: Vec<u8> = "😀"
+            .repeat(100_000)
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        // A unit before the pairs puts one across the end of some read.
+        let shifted: Vec<u8> = [b'x', 0].into_iter().chain(le).collect();
+        let text = decode(&shifted, Encoding::Utf16le);
+        assert_eq!(format!("x{}", "😀".repeat(100_000)), text);
+        // Cut short in the last pair, it's invalid.
+        let text = decode(&shifted[..shifted.len() - 1], Encoding::Utf16le);
+        assert!(text.ends_with("😀\u{FFFD}"));
+    }
+
+    #[test]
+    fn test_utf8_and_latin1() {
+        assert_eq!("a\nb", decode(b"\xEF\xBB\xBFa\nb", Encoding::Utf8));
+        assert_eq!("a\nb", decode(b"a\nb", Encoding::Utf8));
+        assert_eq!("ÿþcafé", decode(b"\xFF\xFEcaf\xE9", Encoding::Latin1));
+        assert_eq!("", decode(b"", Encoding::Utf16le));
+    }
+}
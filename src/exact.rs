@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Exact counts of every distinct item added, for inputs small enough that sampling isn't needed.
+/// Unlike a `Reservoir`, memory grows with the number of distinct items.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: Deserialize<'de> + Eq + Hash"))]
+pub struct ExactCounts<T> {
+    counts: HashMap<T, u64>,
+    total: u64,
+}
+
+impl<T: Eq + Hash> Default for ExactCounts<T> {
+    fn default() -> Self {
+        ExactCounts::new()
+    }
+}
+
+impl<T: Eq + Hash> ExactCounts<T> {
+    pub fn new() -> ExactCounts<T> {
+        ExactCounts {
+            counts: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    pub fn add(&mut self, item: T) {
+        *self.counts.entry(item).or_insert(0) += 1;
+        self.total += 1;
+    }
+
+    /// Adds a borrowed item, only making an owned copy of it the first time it's seen.
+    pub fn add_borrowed<Q>(&mut self, item: &Q)
+    where
+        T: Borrow<Q>,
+        Q: ToOwned<Owned = T> + Eq + Hash + ?Sized,
+    {
+        match self.counts.get_mut(item) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(item.to_owned(), 1);
+            }
+        }
+        self.total += 1;
+    }
+
+    /// The number of items added.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Iterates over each distinct item and the number of times it was added.
+    pub fn iter(&self) -> impl Iterator<Item = (&T, u64)> {
+        self.counts.iter().map(|(item, count)| (item, *count))
+    }
+
+    /// The count of each distinct item, and the number of items added, which the counts are a
+    /// share of.
+    pub fn to_counts(&self) -> (HashMap<&T, u64>, u64) {
+        (self.iter().collect(), self.total)
+    }
+
+    pub fn to_histogram(&self) -> HashMap<&T, f32> {
+        self.counts
+            .iter()
+            .map(|(item, count)| (item, *count as f32 / self.total as f32))
+            .collect()
+    }
+}
+
+impl<T: Eq + Hash + Clone> ExactCounts<T> {
+    /// Combines two sets of counts by summing the counts of each item.
+    pub fn merge(c1: &ExactCounts<T>, c2: &ExactCounts<T>) -> ExactCounts<T> {
+        ExactCounts::merge_many([c1, c2])
+    }
+
+    /// Combines any number of sets of counts by summing the counts of each item.
+    pub fn merge_many<'a, I>(counts: I) -> ExactCounts<T>
+    where
+        I: IntoIterator<Item = &'a ExactCounts<T>>,
+        T: 'a,
+    {
+        let mut merged = ExactCounts::new();
+        for c in counts {
+            for (item, count) in &c.counts {
+                *merged.counts.entry(item.clone()).or_insert(0) += count;
+            }
+            merged.total += c.total;
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty() {
+        let c: ExactCounts<String> = ExactCounts::new();
+        assert_eq!(HashMap::new(), c.to_histogram());
+    }
+
+    #[test]
+    fn test_frequencies() {
+        let mut c = ExactCounts::new();
+        for item in ["a", "b", "a", "a"] {
+            c.add(item);
+        }
+        let mut v = HashMap::new();
+        v.insert(&"a", 0.75f32);
+        v.insert(&"b", 0.25f32);
+        assert_eq!(v, c.to_histogram());
+    }
+
+    #[test]
+    fn test_add_borrowed() {
+        let mut c: ExactCounts<String> = ExactCounts::new();
+        for item in ["a", "b", "a"] {
+            c.add_borrowed(item);
+        }
+        c.add("a".to_string());
+        assert_eq!(4, c.total());
+        let mut counts: Vec<(&String, u64)> = c.iter().collect();
+        counts.sort();
+        assert_eq!(vec![(&"a".to_string(), 3), (&"b".to_string(), 1)], counts);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut c1 = ExactCounts::new();
+        let mut c2 = ExactCounts::new();
+        c1.add("a");
+        c2.add("a");
+        c2.add("b");
+        c2.add("c");
+        let c3 = ExactCounts::merge(&c1, &c2);
+        assert_eq!(4, c3.total);
+        let mut counts: Vec<(&&str, u64)> = c3.iter().collect();
+        counts.sort();
+        assert_eq!(vec![(&"a", 2), (&"b", 1), (&"c", 1)], counts);
+    }
+}
@@ -1,24 +1,261 @@
 use std::io::{self, prelude::*, SeekFrom};
 use std::ops::Range;
 
-/// Splits the given `src` on newlines roughly in chunks of `split_size` bytes.
-pub fn get_splits<R: BufRead + Seek>(mut src: R, split_size: u64) -> io::Result<Vec<Range<u64>>> {
+/// Splits the given `src` on the `separator` byte (usually a newline) roughly in chunks of
+/// `split_size` bytes.
+///
+/// The returned ranges partition `src`: they are contiguous, cover every byte exactly once, and
+/// each one except the last ends just after a separator. An empty `src` yields a single empty
+/// range. Every split holds at least one record, however small `split_size` is, so a split size
+/// of 0 is treated as 1.
+pub fn get_splits<R: BufRead + Seek>(
+    src: R,
+    split_size: u64,
+    separator: u8,
+) -> io::Result<Vec<Range<u64>>> {
+    split_at(src, split_size, |src| {
+        read_until_capped(src, separator, &mut Vec::new(), 0)?;
+        Ok(())
+    })
+}
+
+/// Splits the given `src` roughly in chunks of `split_size` bytes, like `get_splits`, but for
+/// `--paragraph-mode`: each range except the last ends just after a blank line, one of only
+/// whitespace, so that the paragraphs between blank lines are never divided between splits.
+pub fn get_paragraph_splits<R: BufRead + Seek>(
+    src: R,
+    split_size: u64,
+) -> io::Result<Vec<Range<u64>>> {
+    split_at(src, split_size, |src| {
+        // The rest of the line the split size ended in, which can't be known to be blank.
+        read_until_capped(src, b'\n', &mut Vec::new(), 0)?;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if src.read_until(b'\n', &mut line)? == 0 || line.iter().all(u8::is_ascii_whitespace) {
+                return Ok(());
+            }
+        }
+    })
+}
+
+/// Reads from `src` up to and including the next `separator`, like `BufRead::read_until`, but
+/// appends at most `max` bytes of the record before the separator to `buf`, passing over the rest
+/// without holding it in memory. When the separator is a newline, a `\r` just before it is kept
+/// even if it's past `max`, so that a truncated CRLF line still ends in `\r\n`.
+///
+/// Returns the number of bytes read from `src`, and whether any of the record was passed over,
+/// not counting such a `\r`.
+pub(crate) fn read_until_capped<R: BufRead + ?Sized>(
+    src: &mut R,
+    separator: u8,
+    buf: &mut Vec<u8>,
+    max: usize,
+) -> io::Result<(usize, bool)> {
+    let mut bytes_read = 0;
+    let mut kept = 0;
+    let mut passed_over = 0;
+    let mut last_passed_over = None;
+    loop {
+        let available = match src.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            break;
+        }
+        let end = available.iter().position(|&byte| byte == separator);
+        let record = &available[..end.unwrap_or(available.len())];
+        let keep = record.len().min(max - kept);
+        buf.extend_from_slice(&record[..keep]);
+        kept += keep;
+        if keep < record.len() {
+            passed_over += record.len() - keep;
+            last_passed_over = record.last().copied();
+        }
+        if end.is_some() {
+            if separator == b'\n' && last_passed_over == Some(b'\r') {
+                buf.push(b'\r');
+                passed_over -= 1;
+            }
+            buf.push(separator);
+        }
+        let used = record.len() + end.is_some() as usize;
+        src.consume(used);
+        bytes_read += used;
+        if end.is_some() {
+            break;
+        }
+    }
+    Ok((bytes_read, passed_over > 0))
+}
+
+/// Splits `src` into ranges of about `split_size` bytes, calling `to_boundary` to move on from
+/// the end of each to the next record boundary.
+fn split_at<R, F>(mut src: R, split_size: u64, mut to_boundary: F) -> io::Result<Vec<Range<u64>>>
+where
+    R: BufRead + Seek,
+    F: FnMut(&mut R) -> io::Result<()>,
+{
     let mut splits: Vec<Range<u64>> = Vec::new();
-    let mut buf: String = String::new();
     let end_pos = src.seek(SeekFrom::End(0))?;
     src.seek(SeekFrom::Start(0))?;
     loop {
         let split_start_pos = src.stream_position()?;
-        let split_end_pos = src.seek(SeekFrom::Current(split_size as i64))?;
-        let split_end_pos = u64::min(split_end_pos, end_pos);
+        // Moving on by at least one byte means each split ends after a separator found past its
+        // start, so it can never be empty.
+        let split_end_pos = u64::min(split_start_pos.saturating_add(split_size.max(1)), end_pos);
+        src.seek(SeekFrom::Start(split_end_pos))?;
         if split_end_pos == end_pos {
             splits.push(split_start_pos..end_pos);
             break;
         } else {
-            src.read_line(&mut buf)?;
-            buf.clear();
-            splits.push(split_start_pos..src.stream_position()?);
+            to_boundary(&mut src)?;
+            let split_end_pos = src.stream_position()?;
+            splits.push(split_start_pos..split_end_pos);
+            if split_end_pos == end_pos {
+                // The last record had no trailing separator, so reading it reached the end of
+                // `src`.
+                break;
+            }
         }
     }
     Ok(splits)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Checks that `splits` partition `src` and end on line boundaries.
+    fn assert_partition(src: &str, splits: &[Range<u64>]) {
+        assert_eq!(0, splits[0].start);
+        assert_eq!(src.len() as u64, splits[splits.len() - 1].end);
+        for pair in splits.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start, "{splits:?}");
+            assert!(pair[0].start < pair[0].end, "{splits:?}");
+            assert_eq!(
+                b'\n',
+                src.as_bytes()[pair[0].end as usize - 1],
+                "{splits:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(vec![0..0], get_splits(Cursor::new(""), 4, b'\n').unwrap());
+    }
+
+    #[test]
+    fn test_single_line_no_newline() {
+        assert_eq!(
+            vec![0..3],
+            get_splits(Cursor::new("abc"), 2, b'\n').unwrap()
+        );
+    }
+
+    #[test]
+    fn test_split_mid_second_line() {
+        assert_eq!(
+            vec![0..7],
+            get_splits(Cursor::new("ab\ncdef"), 4, b'\n').unwrap()
+        );
+        assert_eq!(
+            vec![0..3, 3..8],
+            get_splits(Cursor::new("ab\ncdef\n"), 1, b'\n').unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ends_on_newline() {
+        assert_eq!(
+            vec![0..6],
+            get_splits(Cursor::new("ab\ncd\n"), 3, b'\n').unwrap()
+        );
+        assert_eq!(
+            vec![0..3, 3..6],
+            get_splits(Cursor::new("ab\ncd\n"), 1, b'\n').unwrap()
+        );
+    }
+
+    #[test]
+    fn test_all_split_sizes_partition() {
+        for src in ["a\nbb\nccc\ndddd\n", "a\nbb\nccc\ndddd", "\n\n\nx\n\n"] {
+            for split_size in 1..=src.len() as u64 + 1 {
+                let splits = get_splits(Cursor::new(src), split_size, b'\n').unwrap();
+                assert_partition(src, &splits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tiny_split_sizes() {
+        let src = "a\nbb\n\nccc\n";
+        for split_size in [0, 1] {
+            assert_eq!(
+                vec![0..2, 2..5, 5..10],
+                get_splits(Cursor::new(src), split_size, b'\n').unwrap()
+            );
+        }
+        assert_eq!(
+            vec![0..2],
+            get_splits(Cursor::new("a\n"), u64::MAX, b'\n').unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_until_capped() {
+        // A small buffer makes the long record span several reads from it.
+        let mut src = io::BufReader::with_capacity(4, Cursor::new("abcdefghij\r\nxy\nz"));
+        let mut buf = Vec::new();
+        assert_eq!(
+            (12, true),
+            read_until_capped(&mut src, b'\n', &mut buf, 3).unwrap()
+        );
+        assert_eq!(b"abc\r\n", buf.as_slice());
+        buf.clear();
+        assert_eq!(
+            (3, false),
+            read_until_capped(&mut src, b'\n', &mut buf, 3).unwrap()
+        );
+        assert_eq!(b"xy\n", buf.as_slice());
+        buf.clear();
+        assert_eq!(
+            (1, false),
+            read_until_capped(&mut src, b'\n', &mut buf, 3).unwrap()
+        );
+        assert_eq!(b"z", buf.as_slice());
+        assert_eq!(
+            (0, false),
+            read_until_capped(&mut src, b'\n', &mut buf, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_paragraph_splits() {
+        let src = "a\nb\n\nc\n \nd\ne\n";
+        assert_eq!(
+            vec![0..5, 5..9, 9..13],
+            get_paragraph_splits(Cursor::new(src), 1).unwrap()
+        );
+        assert_eq!(
+            vec![0..13],
+            get_paragraph_splits(Cursor::new(src), 11).unwrap()
+        );
+        for split_size in 1..=src.len() as u64 + 1 {
+            let splits = get_paragraph_splits(Cursor::new(src), split_size).unwrap();
+            assert_partition(src, &splits);
+        }
+    }
+
+    #[test]
+    fn test_nul_separator() {
+        assert_eq!(
+            vec![0..3, 3..6],
+            get_splits(Cursor::new("ab\0c\nd"), 1, b'\0').unwrap()
+        );
+    }
+}
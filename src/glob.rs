@@ -0,0 +1,186 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Returns true if `s` contains any of the wildcards understood by `glob`.
+pub fn is_glob_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Expands a glob pattern into the paths of the files and directories matching it, in sorted
+/// order. Each component of the pattern may use `*` for any run of characters, `?` for any one
+/// character, and `[...]` for one of a set of characters like `[abc]` or `[a-z]`, negated by a
+/// leading `!` or `^`. Wildcards don't match `/`, nor a leading `.` unless the pattern's component
+/// starts with one.
+pub fn glob(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let part = match component {
+            Component::Normal(part) => part.to_str().unwrap_or_default(),
+            _ => {
+                // The root, `.` or `..`, which are never patterns.
+                paths.iter_mut().for_each(|path| path.push(component));
+                continue;
+            }
+        };
+        if !is_glob_pattern(part) {
+            paths.iter_mut().for_each(|path| path.push(part));
+            continue;
+        }
+        let pattern: Vec<char> = part.chars().collect();
+        let mut matches = Vec::new();
+        for dir in &paths {
+            let entries = match fs::read_dir(if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }) {
+                Ok(entries) => entries,
+                // Not a directory, or not one that can be read, so nothing in it matches.
+                Err(_) => continue,
+            };
+            for entry in entries {
+                let name = entry?.file_name();
+                let Some(name) = name.to_str() else {
+                    continue;
+                };
+                if name.starts_with('.') && !part.starts_with('.') {
+                    continue;
+                }
+                if matches_pattern(&pattern, &name.chars().collect::<Vec<_>>()) {
+                    matches.push(dir.join(name));
+                }
+            }
+        }
+        paths = matches;
+    }
+    paths.retain(|path| path.exists());
+    paths.sort();
+    Ok(paths)
+}
+
+/// Matches a name against one component of a glob pattern. When a character doesn't match, only
+/// the last `*` seen is made to match one more character, since any earlier one could only match
+/// less of what the last can, so the time taken is at most the product of the two lengths.
+fn matches_pattern(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The position in the pattern after the last `*`, and that in the name it matches up to.
+    let mut last_star = None;
+    while n < name.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            last_star = Some((p, n));
+        } else if let Some(len) = matches_one(&pattern[p..], name[n]) {
+            p += len;
+            n += 1;
+        } else if let Some((star_p, star_n)) = last_star {
+            p = star_p;
+            n = star_n + 1;
+            last_star = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The length of the wildcard or character that `pattern` starts with if it matches `c`, which
+/// `*` never does.
+fn matches_one(pattern: &[char], c: char) -> Option<usize> {
+    match pattern.first()? {
+        '*' => None,
+        '?' => Some(1),
+        '[' => match class_end(pattern) {
+            Some(end) => matches_class(&pattern[1..end], c).then_some(end + 1),
+            // An unclosed `[` matches itself.
+            None => (c == '[').then_some(1),
+        },
+        &p => (p == c).then_some(1),
+    }
+}
+
+/// The index of the `]` closing the character class that `pattern` starts with. A `]` straight
+/// after the opening `[`, or after its negation, is part of the class.
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let start = match pattern.get(1) {
+        Some('!' | '^') => 3,
+        _ => 2,
+    };
+    (start..pattern.len()).find(|&i| pattern[i] == ']')
+}
+
+/// Whether `c` is in a character class, given without its brackets.
+fn matches_class(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!' | '^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut i = 0;
+    let mut found = false;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        matches_pattern(&pattern, &name)
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches("*.txt", "a.txt"));
+        assert!(matches("*.txt", ".txt"));
+        assert!(!matches("*.txt", "a.txt.gz"));
+        assert!(matches("a*b*c", "aXbYbc"));
+        assert!(matches("log-?.txt", "log-1.txt"));
+        assert!(!matches("log-?.txt", "log-10.txt"));
+        assert!(matches("log-[0-9].txt", "log-7.txt"));
+        assert!(!matches("log-[!0-9].txt", "log-7.txt"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("a[b", "a[b"));
+        assert!(matches("*a*[ab]", "aab"));
+        assert!(!matches("a*", ""));
+        // Failing to match many stars against a long name doesn't try every way of splitting it.
+        assert!(!matches(
+            &format!("{}b", "a*".repeat(20)),
+            &"a".repeat(1000)
+        ));
+    }
+
+    #[test]
+    fn test_glob() {
+        let dir = std::env::temp_dir().join(format!("rs-tool-{}-glob", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["a.txt", "b.txt", "c.log", ".hidden.txt", "sub/d.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let glob_in_dir = |pattern: &str| {
+            glob(&format!("{}/{pattern}", dir.display()))
+                .unwrap()
+                .into_iter()
+                .map(|path| path.strip_prefix(&dir).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")],
+            glob_in_dir("*.txt")
+        );
+        assert_eq!(vec![PathBuf::from("sub/d.txt")], glob_in_dir("s*/*.txt"));
+        assert_eq!(vec![PathBuf::from(".hidden.txt")], glob_in_dir(".*.txt"));
+        assert!(glob_in_dir("*.csv").is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+
+/// The number of hash bits used to pick a register.
+const PRECISION: u32 = 14;
+const NUM_REGISTERS: usize = 1 << PRECISION;
+
+/// A HyperLogLog sketch, estimating the number of distinct values added to it to within about 1%
+/// using a fixed 16KB of memory.
+#[derive(Debug, Clone, Serialize)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog::new()
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> HyperLogLog {
+        HyperLogLog {
+            registers: vec![0; NUM_REGISTERS],
+        }
+    }
+
+    pub fn add(&mut self, value: &[u8]) {
+        let hash = hash(value);
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // The sentinel bit caps the rank at the number of bits left after the index.
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() + 1;
+        self.registers[index] = max(self.registers[index], rank as u8);
+    }
+
+    /// Combines two sketches into one that estimates the number of distinct values added to
+    /// either of them.
+    pub fn merge(h1: &HyperLogLog, h2: &HyperLogLog) -> HyperLogLog {
+        HyperLogLog {
+            registers: h1
+                .registers
+                .iter()
+                .zip(h2.registers.iter())
+                .map(|(r1, r2)| max(*r1, *r2))
+                .collect(),
+        }
+    }
+
+    /// Estimates the number of distinct values added to the sketch.
+    pub fn estimate(&self) -> f64 {
+        let m = NUM_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&register| 2f64.powi(-(register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|&&register| register == 0)
+            .count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate while many registers are still empty.
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// The saved form of a `HyperLogLog`, checked for the right number of registers when it's loaded.
+#[derive(Deserialize)]
+struct HyperLogLogState {
+    registers: Vec<u8>,
+}
+
+impl<'de> Deserialize<'de> for HyperLogLog {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = HyperLogLogState::deserialize(deserializer)?;
+        if state.registers.len() != NUM_REGISTERS {
+            return Err(de::Error::custom(format!(
+                "distinct count sketch has {} registers rather than {NUM_REGISTERS}",
+                state.registers.len()
+            )));
+        }
+        Ok(HyperLogLog {
+            registers: state.registers,
+        })
+    }
+}
+
+/// Hashes `value` with FNV-1a followed by the SplitMix64 finalizer, which spreads FNV's output
+/// over all 64 bits. Unlike `DefaultHasher`, the result is stable across Rust releases, so saved
+/// sketches stay valid.
+pub(crate) fn hash(value: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in value {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(expected: f64, estimate: f64) {
+        let error = (estimate - expected).abs() / expected;
+        assert!(error < 0.03, "expected {expected}, estimated {estimate}");
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(0.0, HyperLogLog::new().estimate());
+    }
+
+    #[test]
+    fn test_duplicates_counted_once() {
+        let mut h = HyperLogLog::new();
+        for _ in 0..1000 {
+            h.add(b"hello");
+            h.add(b"world");
+        }
+        assert_eq!(2, h.estimate().round() as u64);
+    }
+
+    #[test]
+    fn test_estimate() {
+        for n in [1000, 100_000] {
+            let mut h = HyperLogLog::new();
+            for i in 0..n {
+                h.add(i.to_string().as_bytes());
+            }
+            assert_close(n as f64, h.estimate());
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut h1 = HyperLogLog::new();
+        let mut h2 = HyperLogLog::new();
+        for i in 0..60_000 {
+            h1.add(i.to_string().as_bytes());
+        }
+        for i in 40_000..100_000 {
+            h2.add(i.to_string().as_bytes());
+        }
+        assert_close(100_000.0, HyperLogLog::merge(&h1, &h2).estimate());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut h = HyperLogLog::new();
+        for i in 0..1000 {
+            h.add(i.to_string().as_bytes());
+        }
+        let saved = serde_json::to_string(&h).unwrap();
+        let loaded: HyperLogLog = serde_json::from_str(&saved).unwrap();
+        assert_eq!(h.estimate(), loaded.estimate());
+        assert!(serde_json::from_str::<HyperLogLog>(r#"{"registers":[1,2,3]}"#).is_err());
+        assert!(serde_json::from_str::<HyperLogLog>(r#"{"registers":[]}"#).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::reservoir::Reservoir;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+
+/// The fewest distinct strings an `InternedReservoir` holds before compacting its table, so that
+/// a small reservoir isn't compacted every few adds.
+const MIN_COMPACTED_SYMBOLS: usize = 64;
+
+/// A `Reservoir` of strings that holds each distinct string once, however often it's in the
+/// pool: the pool holds `u32` ids into a table of strings. A field with a few distinct values
+/// then takes little more memory than the ids, however large the sample.
+///
+/// The table keeps the strings of items that have since left the pool until it grows to twice
+/// the capacity, when it's compacted to the strings still in the pool. Saved reservoirs are
+/// written with their strings, just like a `Reservoir<String>`.
+#[derive(Debug)]
+pub struct InternedReservoir {
+    reservoir: Reservoir<u32>,
+    /// The string of each id.
+    symbols: Vec<String>,
+    /// The id of each string in `symbols`.
+    ids: HashMap<String, u32>,
+}
+
+impl InternedReservoir {
+    pub fn new(capacity: usize) -> InternedReservoir {
+        InternedReservoir::from_ids(Reservoir::new(capacity))
+    }
+
+    /// Creates a reservoir whose random number generator is seeded with `seed`, like
+    /// `Reservoir::with_seed`.
+    pub fn with_seed(capacity: usize, seed: u64) -> InternedReservoir {
+        InternedReservoir::from_ids(Reservoir::with_seed(capacity, seed))
+    }
+
+    fn from_ids(reservoir: Reservoir<u32>) -> InternedReservoir {
+        InternedReservoir {
+            reservoir,
+            symbols: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    /// Interns every item of a reservoir of strings, which goes on sampling as it would have.
+    pub fn from_reservoir(reservoir: &Reservoir<String>) -> InternedReservoir {
+        let mut symbols = Vec::new();
+        let mut ids = HashMap::new();
+        let reservoir = reservoir.map(|item| intern(&mut symbols, &mut ids, item));
+        InternedReservoir {
+            reservoir,
+            symbols,
+            ids,
+        }
+    }
+
+    /// A reservoir of strings holding a copy of each item, which goes on sampling as this one
+    /// would have.
+    pub fn to_reservoir(&self) -> Reservoir<String> {
+        self.reservoir.map(|&id| self.symbols[id as usize].clone())
+    }
+
+    /// Sets the rate at which earlier items' weights decay, like `Reservoir::set_decay`.
+    pub fn set_decay(&mut self, decay: f64) {
+        self.reservoir.set_decay(decay);
+    }
+
+    pub fn decay(&self) -> f64 {
+        self.reservoir.decay()
+    }
+
+    pub fn add(&mut self, item: &str) {
+        self.add_weighted(item, 1.0);
+    }
+
+    /// Adds an item with a weight, like `Reservoir::add_weighted`, only looking it up in the table
+    /// if it enters the pool.
+    pub fn add_weighted(&mut self, item: &str, weight: f64) {
+        let InternedReservoir {
+            reservoir,
+            symbols,
+            ids,
+        } = self;
+        reservoir.add_weighted_with(weight, || intern(symbols, ids, item));
+        if self.symbols.len() > 2 * self.reservoir.capacity().max(MIN_COMPACTED_SYMBOLS) {
+            self.compact();
+        }
+    }
+
+    /// Drops the strings no longer in the pool from the table, renumbering those that are.
+    fn compact(&mut self) {
+        let mut old_symbols = mem::take(&mut self.symbols);
+        let mut new_ids: Vec<Option<u32>> = vec![None; old_symbols.len()];
+        self.ids.clear();
+        let InternedReservoir {
+            reservoir,
+            symbols,
+            ids,
+        } = self;
+        reservoir.map_in_place(|id| {
+            *id = *new_ids[*id as usize].get_or_insert_with(|| {
+                let symbol = mem::take(&mut old_symbols[*id as usize]);
+                let new_id = symbols.len() as u32;
+                ids.insert(symbol.clone(), new_id);
+                symbols.push(symbol);
+                new_id
+            });
+        });
+    }
+
+    /// The number of items added, including those that weren't sampled.
+    pub fn num_adds(&self) -> u64 {
+        self.reservoir.num_adds()
+    }
+
+    /// The number of items in the pool, which is at most `capacity`.
+    pub fn len(&self) -> usize {
+        self.reservoir.len()
+    }
+
+    /// Whether the pool is empty, like `Reservoir::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.reservoir.is_empty()
+    }
+
+    /// The most items the pool can hold.
+    pub fn capacity(&self) -> usize {
+        self.reservoir.capacity()
+    }
+
+    /// Iterates over the items in the pool, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.reservoir.iter().map(|&id| &self.symbols[id as usize])
+    }
+
+    /// The number of times each distinct item is in the pool, and the number of items in the
+    /// pool, like `Reservoir::to_counts`.
+    pub fn to_counts(&self) -> (HashMap<&String, u64>, u64) {
+        let mut id_counts = vec![0; self.symbols.len()];
+        for &id in self.reservoir.iter() {
+            id_counts[id as usize] += 1;
+        }
+        let counts = id_counts
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, count)| count > 0)
+            .map(|(id, count)| (&self.symbols[id], count))
+            .collect();
+        (counts, self.len() as u64)
+    }
+
+    /// The frequency of each distinct item in the pool, like `Reservoir::to_histogram`.
+    pub fn to_histogram(&self) -> HashMap<&String, f32> {
+        let (counts, pool_size) = self.to_counts();
+        counts
+            .into_iter()
+            .map(|(item, count)| (item, count as f32 / pool_size as f32))
+            .collect()
+    }
+
+    /// Combines any number of reservoirs in a single pass, like `Reservoir::merge_many`, with a
+    /// table of the strings in every pool.
+    pub fn merge_many<'a, I: IntoIterator<Item = &'a InternedReservoir>>(
+        reservoirs: I,
+    ) -> InternedReservoir {
+        let mut symbols = Vec::new();
+        let mut ids = HashMap::new();
+        let renumbered: Vec<Reservoir<u32>> = reservoirs
+            .into_iter()
+            .map(|r| {
+                r.reservoir
+                    .map(|&id| intern(&mut symbols, &mut ids, &r.symbols[id as usize]))
+            })
+            .collect();
+        let mut merged = InternedReservoir {
+            reservoir: Reservoir::merge_many(&renumbered),
+            symbols,
+            ids,
+        };
+        merged.compact();
+        merged
+    }
+
+    /// Writes the reservoir to `w` as a single line of JSON, in the same form as a
+    /// `Reservoir<String>`, which it's loaded back as.
+    pub fn save<W: Write>(&self, w: W) -> io::Result<()> {
+        self.reservoir.map(|&id| &self.symbols[id as usize]).save(w)
+    }
+}
+
+/// The id of `item` in the table of `symbols`, adding it if it isn't there yet.
+fn intern(symbols: &mut Vec<String>, ids: &mut HashMap<String, u32>, item: &str) -> u32 {
+    if let Some(&id) = ids.get(item) {
+        return id;
+    }
+    let id = symbols.len() as u32;
+    symbols.push(item.to_string());
+    ids.insert(item.to_string(), id);
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_reservoir() {
+        let mut interned = InternedReservoir::with_seed(100, 1);
+        let mut plain = Reservoir::with_seed(100, 1);
+        for i in 0..10000 {
+            let item = (i % 7).to_string();
+            interned.add(&item);
+            plain.add(item);
+        }
+        assert_eq!(plain.to_histogram(), interned.to_histogram());
+        assert_eq!(10000, interned.num_adds());
+        assert_eq!(7, interned.symbols.len());
+    }
+
+    #[test]
+    fn test_table_is_compacted() {
+        let mut interned = InternedReservoir::with_seed(10, 1);
+        let mut plain = Reservoir::with_seed(10, 1);
+        for i in 0..100_000 {
+            interned.add(&i.to_string());
+            plain.add(i.to_string());
+        }
+        assert!(interned.symbols.len() <= 2 * MIN_COMPACTED_SYMBOLS);
+        assert_eq!(interned.symbols.len(), interned.ids.len());
+        assert_eq!(plain.to_histogram(), interned.to_histogram());
+    }
+
+    #[test]
+    fn test_merge_and_save() {
+        let mut r1 = InternedReservoir::with_seed(50, 1);
+        let mut r2 = InternedReservoir::with_seed(50, 2);
+        for i in 0..1000 {
+            r1.add(["a", "b"][i % 2]);
+            r2.add(["b", "c", "d"][i % 3]);
+        }
+        let merged = InternedReservoir::merge_many([&r1, &r2]);
+        let plain = Reservoir::merge(&r1.to_reservoir(), &r2.to_reservoir());
+        assert_eq!(plain.to_histogram(), merged.to_histogram());
+        assert_eq!(2000, merged.num_adds());
+        assert!(merged.symbols.len() <= 4);
+
+        let mut saved = Vec::new();
+        merged.save(&mut saved).unwrap();
+        let loaded: Reservoir<String> = Reservoir::load(saved.as_slice()).unwrap();
+        assert_eq!(
+            merged.to_histogram(),
+            InternedReservoir::from_reservoir(&loaded).to_histogram()
+        );
+    }
+}
@@ -0,0 +1,35 @@
+//! Reservoir sampling of line-oriented text, as used by the `rs-tool` command.
+//!
+//! [`process_reader`] samples the records, or selected fields of the records, read from any
+//! buffered reader into a [`SampledFields`], configured by [`SampleOptions`]. Samples of separate
+//! parts of the input, such as the ranges of a file returned by [`get_splits`], can be sampled in
+//! parallel and combined with [`SampledFields::merge`].
+//!
+//! To sample records as they arrive rather than from a reader, push them to a [`Sampler`], whose
+//! top-k values can be queried at any time.
+
+mod exact;
+mod filesplits;
+mod hyperloglog;
+mod interned;
+mod quantiles;
+mod reservoir;
+mod sampler;
+mod sampling;
+mod spacesaving;
+mod unique;
+
+pub use exact::ExactCounts;
+pub use filesplits::{get_paragraph_splits, get_splits};
+pub use hyperloglog::HyperLogLog;
+pub use interned::InternedReservoir;
+pub use quantiles::{percentiles, Percentile, Percentiles};
+pub use reservoir::{RandomSource, Reservoir};
+pub use sampler::Sampler;
+pub use sampling::{
+    process_reader, process_whole, read_preamble, read_rest_of_record, resolve_field_index,
+    Accumulator, FieldIndexes, FixedWidths, LineEnding, MissingFieldReports, SampleOptions,
+    SampledFields, Selection, Subfield, WeightBy, COMPOSITE_SEPARATOR,
+};
+pub use spacesaving::SpaceSaving;
+pub use unique::UniqueSample;
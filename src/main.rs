@@ -1,46 +1,129 @@
-mod filesplits;
-mod reservoir;
+mod encoding;
+mod glob;
 
-use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, ValueEnum};
-use filesplits::get_splits;
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use encoding::Encoding;
+use flate2::read::MultiGzDecoder;
+use glob::{glob, is_glob_pattern};
+use indicatif::{ProgressBar, ProgressStyle};
+use memmap2::Mmap;
 use prettytable::{format, Cell, Row, Table};
 use rayon::prelude::*;
-use reservoir::Reservoir;
+use rs_tool::{
+    get_paragraph_splits, get_splits, percentiles, process_reader, process_whole, read_preamble,
+    read_rest_of_record, resolve_field_index, Accumulator, LineEnding, Percentiles, SampleOptions,
+    SampledFields, Selection, Subfield, COMPOSITE_SEPARATOR,
+};
 use serde::Serialize;
 use serde_json::to_writer_pretty;
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
 use std::fs::File;
-use std::io::{self, stdin, stdout, BufRead, BufReader, Seek};
+use std::io::{
+    self, stdin, stdout, BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, Write,
+};
+use std::mem;
+use std::net::TcpStream;
+use std::ops::Range;
+use std::path::Path;
+use std::process;
+use std::sync::mpsc::{channel, sync_channel, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_SPLIT_SIZE: u64 = 33554432;
+
+/// The smallest `--split-size` used. Smaller chunks hold so few records that merging their
+/// samples costs more than sampling them.
+const MIN_SPLIT_SIZE: u64 = 4096;
+
+/// The version of the structure of `json` and `json-lines` output, raised whenever it changes.
+/// Version 2 added `entropy` and `field_counts`, and values without frequencies for `--no-freq`.
+const JSON_SCHEMA_VERSION: u32 = 2;
+
+/// The first two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The prefix of an input naming a TCP address to read records from, rather than a file.
+const TCP_SCHEME: &str = "tcp://";
+
+/// The input naming stdin, rather than a file.
+const STDIN_INPUT: &str = "-";
+
+/// When to color table output, given with `--color`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum ColorChoice {
+    /// Color the table when writing it to a terminal, unless `NO_COLOR` is set
+    Auto,
+    Always,
+    Never,
+}
 
 #[derive(ValueEnum, Debug, Clone)]
 enum DisplayFormat {
     Table,
     Json,
+    JsonLines,
+    Csv,
+    Tsv,
 }
 
-#[derive(Parser, Debug)]
+impl DisplayFormat {
+    /// The extension of the files `--output-dir` writes in this format.
+    fn extension(&self) -> &'static str {
+        match self {
+            DisplayFormat::Table => "txt",
+            DisplayFormat::Json => "json",
+            DisplayFormat::JsonLines => "jsonl",
+            DisplayFormat::Csv => "csv",
+            DisplayFormat::Tsv => "tsv",
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Reservoir sample size
-    #[arg(short = 'n', long = "num-samples", default_value = "1000")]
-    sample_size: usize,
+    #[command(flatten)]
+    sample: SampleOptions,
 
-    /// Display "top-k" items from sample histogram
-    #[arg(short = 'k', long = "num-results", default_value = "10")]
+    /// Display "top-k" items from sample histogram. `all` or 0 displays every value in the
+    /// histogram.
+    #[arg(
+        short = 'k',
+        long = "num-results",
+        default_value = "10",
+        value_parser = parse_num_results
+    )]
     num_results: u32,
 
-    /// Fields to sample, indexed from 0
-    #[arg(short='f', long="field-index", action=ArgAction::Append)]
-    fields: Vec<usize>,
+    /// Leave out values whose frequency in the sample is below this fraction, before taking the
+    /// top-k of those left.
+    #[arg(
+        long = "min-frequency",
+        value_name = "F",
+        default_value_t = 0.0,
+        value_parser = parse_min_frequency
+    )]
+    min_frequency: f64,
 
-    /// Field separator, if unspecified then `char::is_whitespace` will be used.
-    #[arg(short = 's', long = "field-separator")]
-    field_separator: Option<String>,
+    /// Input file, if unspecified then read from stdin. May be given more than once to sample
+    /// several files together. A path that doesn't exist but has the wildcards `*`, `?` or `[...]`
+    /// is expanded to every file matching it, for when the shell hasn't already done so. An input
+    /// of the form `tcp://host:port` reads records from a connection to that address, and `-`
+    /// reads stdin.
+    #[arg(short = 'i', long = "input-file", action = ArgAction::Append)]
+    input_files: Vec<String>,
 
-    /// Input file, if unspecified then read from stdin.
-    #[arg(short = 'i', long = "input-file")]
-    input_file: Option<String>,
+    /// Read the paths of more input files from this file, one per line, or from stdin if it's
+    /// `-`. Listed files that don't exist are skipped with a warning.
+    #[arg(long = "files-from", value_name = "PATH")]
+    files_from: Option<String>,
 
-    /// Format the output as either a table (the default) or JSON.
+    /// Format the output as a table (the default), JSON, JSON lines (one compact object per field),
+    /// CSV or TSV.
     #[clap(
         value_enum,
         short = 'o',
@@ -49,229 +132,3896 @@ struct Args {
     )]
     output_format: DisplayFormat,
 
-    /// For multi-threaded file processing, approximate size of each input chunk, in bytes.
-    /// Ignored when `-i` is not present.
+    /// Write the output to this file rather than to stdout.
+    #[arg(short = 'O', long = "output-file")]
+    output_file: Option<String>,
+
+    /// Write each field's results to its own file in this directory, which is created if need
+    /// be, rather than all of them to stdout. A file is named by its field's name in the header,
+    /// or else by its index, with the extension of the `--output-format`, like `3.csv`. Whole
+    /// lines are written to `line.<ext>`.
+    #[arg(
+        long = "output-dir",
+        value_name = "DIR",
+        conflicts_with = "output_file"
+    )]
+    output_dir: Option<String>,
+
+    /// For multi-threaded processing, approximate size of each input chunk, in bytes, or with a
+    /// suffix of `K`, `M`, `G` or `T` in KiB, MiB, GiB or TiB, like `32M`. Ignored when `-i`
+    /// names a gzip-compressed file or a stream, or when stdin is read, with no `-i` or with
+    /// `-i -`, and `--parallel-stdin` isn't given. Sizes below 4096 are raised to 4096.
     #[clap(
-        value_enum,
         short = 'c',
         long = "split-size",
-        default_value = "33554432"
+        default_value_t = DEFAULT_SPLIT_SIZE,
+        value_parser = parse_size
     )]
     split_size: u64,
+
+    /// Only sample N of the input files' splits, chosen at random, or reproducibly with `--seed`,
+    /// for a quick estimate over a large input. Each split is sampled uniformly, so the result
+    /// approximates that of the whole input when its splits are alike; values that cluster in
+    /// some part of the input are over- or under-represented. The number of records in the whole
+    /// input is then estimated from the share of its bytes read.
+    #[arg(
+        long = "sample-splits",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    sample_splits: Option<u64>,
+
+    /// Print how the input files would be split to stderr, one `start end size file` line per
+    /// split followed by the number of splits, and exit without sampling them.
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// Seed for the random number generator, so that repeated runs over the same input produce
+    /// the same sample. When reading a file each split derives its own seed from this one.
+    #[arg(long = "seed")]
+    seed: Option<u64>,
+
+    /// File holding a sample saved by a previous run. If it exists, the new input is merged
+    /// into it; the merged sample is always written back to it.
+    #[arg(long = "state-file")]
+    state_file: Option<String>,
+
+    /// Comma-separated percentiles (from 0 to 100) to estimate for each sampled field, e.g.
+    /// `50,90,99`. Values that aren't numbers are left out of the estimates and counted.
+    #[arg(long = "percentiles", value_delimiter = ',', value_parser = parse_percent)]
+    percentiles: Vec<f64>,
+
+    /// Show the Shannon entropy of each sampled field's values, `-Σ p log2 p` in bits over their
+    /// frequencies, in table and JSON output. It's shown normalized too, divided by the log2 of
+    /// the number of distinct values, so that fields of different cardinality can be compared: 1
+    /// when every value is as frequent as any other, nearer 0 the more a few values dominate.
+    #[arg(long = "entropy", conflicts_with = "heavy_hitters")]
+    entropy: bool,
+
+    /// Read stdin in chunks of about `--split-size` bytes and sample the chunks in parallel.
+    /// Records are no longer sampled in input order, and merging the chunks' samples changes
+    /// the sampling statistics slightly.
+    #[arg(long = "parallel-stdin")]
+    parallel_stdin: bool,
+
+    /// Memory-map each input file once and read its splits from the mapping, instead of opening
+    /// the file for every split. The files must not be truncated while they're read, or the
+    /// process is killed by SIGBUS.
+    #[arg(long = "mmap")]
+    mmap: bool,
+
+    /// The character encoding of the input, decoded to UTF-8 before records are read. A byte
+    /// order mark at the start of an input is removed, and says whether it's UTF-8 or which order
+    /// UTF-16 bytes are in. Input that isn't UTF-8, or starts with a byte order mark, is read on
+    /// a single thread, since splits of its bytes could start mid-character.
+    #[arg(value_enum, long = "encoding", default_value = "utf8")]
+    encoding: Encoding,
+
+    /// Label for the counts of missing fields in table output. Defaults to `<missing field>`, or
+    /// `<missing or empty field>` with `--empty-as-missing`.
+    #[arg(long = "missing-label")]
+    missing_label: Option<String>,
+
+    /// Stop after reading this many records. This is exact for stdin; when reading files, each
+    /// split reads at most its share of the records, so fewer may be read in total if some
+    /// splits hold fewer records than others.
+    #[arg(long = "max-records")]
+    max_records: Option<u64>,
+
+    /// Show an estimated count of each value in table and JSON output: its frequency in the
+    /// sample scaled by the number of values seen. The estimate is only meaningful once the
+    /// sample is full; with `--exact` the counts are exact. Counts are always shown with
+    /// `--heavy-hitters`.
+    #[arg(long = "show-counts")]
+    show_counts: bool,
+
+    /// Show the standard error of each value's frequency in table and JSON output,
+    /// `sqrt(p(1-p)/n)` for a frequency `p` in a sample of `n` values. A 95% confidence interval
+    /// spans about two standard errors either side of the frequency. It is 0 with `--exact`.
+    #[arg(long = "show-error")]
+    show_error: bool,
+
+    /// List the distinct values in each field's sample in sorted order, at most `-k` of them,
+    /// instead of the most frequent values and their frequencies.
+    #[arg(
+        long = "distinct",
+        conflicts_with_all = ["show_counts", "show_error", "percentiles", "entropy"]
+    )]
+    distinct: bool,
+
+    /// List the values held in each field's sample as they were sampled, repeats and all, at most
+    /// `-k` of them in no particular order, instead of the most frequent values and their
+    /// frequencies.
+    #[arg(
+        long = "raw",
+        conflicts_with_all = [
+            "exact", "heavy_hitters", "show_counts", "show_error", "percentiles", "distinct",
+            "entropy",
+        ]
+    )]
+    raw: bool,
+
+    /// Only count the records each field is present in and missing from, without sampling any
+    /// values, and write those counts instead of the most frequent values.
+    #[arg(
+        long = "count-only",
+        conflicts_with_all = [
+            "sample_size", "auto_samples", "exact", "heavy_hitters", "unique_sample",
+            "estimate_cardinality", "percentiles", "show_counts", "show_error", "distinct",
+            "state_file", "output_dir", "raw", "entropy",
+        ]
+    )]
+    count_only: bool,
+
+    /// Show progress on stderr: a bar of the bytes read from the input files, or a count of the
+    /// bytes and lines read from stdin.
+    #[arg(long = "progress")]
+    progress: bool,
+
+    /// Every this many seconds, print a table of the values sampled so far to stderr while
+    /// sampling continues. Only the file splits, or with `--parallel-stdin` the chunks of stdin,
+    /// that have been sampled in full are included.
+    #[arg(long = "preview-interval", value_parser = clap::value_parser!(u64).range(1..))]
+    preview_interval: Option<u64>,
+
+    /// The number of digits after the decimal point of frequencies, standard errors and
+    /// percentiles in table, CSV and TSV output. Frequencies and standard errors in JSON output
+    /// are rounded to it too.
+    #[arg(long = "precision", default_value_t = 5)]
+    precision: usize,
+
+    /// Show frequencies and standard errors as percentages, like `12.345%`. JSON output gives
+    /// each value's `percent` rather than its `freq`, with standard errors in percentage points.
+    #[arg(long = "as-percent")]
+    as_percent: bool,
+
+    /// Only list each field's top-k values, still ranked by frequency, without their frequencies:
+    /// table, CSV and TSV output have no frequency column, and JSON output gives each field's
+    /// values as an array of strings.
+    #[arg(
+        long = "no-freq",
+        conflicts_with_all = [
+            "show_counts", "show_error", "as_percent", "heavy_hitters", "distinct", "raw",
+            "count_only",
+        ]
+    )]
+    no_freq: bool,
+
+    /// Whether to color the missing field counts in table output. With `auto`, the table is only
+    /// colored when it's written to a terminal and the `NO_COLOR` environment variable isn't set.
+    #[clap(value_enum, long = "color", default_value = "auto")]
+    color: ColorChoice,
+
+    /// Print the time taken and the rate the input was read at to stderr once the output is
+    /// written, with the number of splits read in parallel.
+    #[arg(long = "time")]
+    time: bool,
+
+    /// Choose `-n` so that the standard error of any value's frequency, including the most
+    /// frequent value's, is at most this much (0.01 if no value is given). The number of records
+    /// in the input files is estimated from their first megabyte. Stdin has no known length, so
+    /// `-n` is used for it instead.
+    #[arg(
+        long = "auto-samples",
+        num_args = 0..=1,
+        default_missing_value = "0.01",
+        value_parser = parse_target_error,
+        conflicts_with = "sample_size"
+    )]
+    auto_samples: Option<f64>,
+
+    /// Don't print the summary of the input read to stderr.
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Allow a field to be given to `-f` more than once, sampling it separately each time.
+    #[arg(long = "allow-duplicate-fields")]
+    allow_duplicate_fields: bool,
 }
 
-#[derive(Debug)]
-struct SampledFields {
-    /// The reservoirs built from reading the input data, one per field
-    reservoirs: Vec<Reservoir<String>>,
+/// The command line: a subcommand, or the options of `sample` given without one.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// The number of fields in the input data that could not be totally processed
-    /// (for example because the record wasn't long enough), counted separately per field.
-    missing_field_counts: Vec<u64>,
+    #[command(flatten)]
+    sample: Args,
 }
 
-impl SampledFields {
-    /// Merges two `SampledFields`, creating a new struct with the combined results. Used to
-    /// `reduce` the output of parallel calls to `process_reader`.
-    fn merge(pr1: &SampledFields, pr2: &SampledFields) -> SampledFields {
-        let reservoirs: Vec<Reservoir<String>> = pr1
-            .reservoirs
-            .iter()
-            .zip(pr2.reservoirs.iter())
-            .map(|(r1, r2)| Reservoir::merge(r1, r2))
-            .collect();
-        let missing_field_counts: Vec<u64> = pr1
-            .missing_field_counts
-            .iter()
-            .zip(pr2.missing_field_counts.iter())
-            .map(|(fc1, fc2)| fc1 + fc2)
-            .collect();
-        SampledFields {
-            reservoirs,
-            missing_field_counts,
-        }
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Sample the input. This is what is done when no subcommand is given
+    Sample(Box<Args>),
+    /// Combine state files written with `--state-file` into one, as if their inputs had been
+    /// sampled together
+    Merge(MergeArgs),
+    /// Print what a state file written with `--state-file` holds, without reading any input
+    Stats(StatsArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeArgs {
+    /// The state files to combine, which must have been written with the same fields and the
+    /// same kind of sample.
+    #[arg(required = true, num_args = 2..)]
+    state_files: Vec<String>,
+
+    /// The state file to write the combined state to. It may be one of those combined.
+    #[arg(long = "into", value_name = "STATE_FILE")]
+    into: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatsArgs {
+    /// The state file to describe.
+    state_file: String,
+}
+
+/// The `--num-results` meaning every value in the histogram.
+const ALL_RESULTS: u32 = u32::MAX;
+
+/// Parses `--num-results`, where `all` and 0 stand for `ALL_RESULTS`.
+fn parse_num_results(s: &str) -> Result<u32, String> {
+    match s {
+        "all" | "0" => Ok(ALL_RESULTS),
+        _ => s
+            .parse()
+            .map_err(|_| format!("`{s}` isn't a number or `all`")),
     }
 }
 
-/// Build one or more reservoirs by reading line-separated records from a buffered reader.
-///
-/// This function is meant to be used with 2 sources:
-/// - stdin, in which case this function should consume the whole stream and `read_limit` should not be specified
-/// - a predetermined chunk of a file, in which case `reader` should be `seek`ed to the starting point and `read_limit` should
-///   indicate the end of the chunk.
-fn process_reader<T: BufRead>(reader: T, read_limit: Option<u64>, args: &Args) -> SampledFields {
-    let mut read_count: u64 = 0;
-    if args.fields.is_empty() {
-        // No fields were specified so just process the whole line in one reservoir.
-        let mut reservoir = Reservoir::new(args.sample_size);
-        for record in reader.lines() {
-            let record = record.unwrap();
-            read_count += record.len() as u64;
-            if read_limit.is_some() && read_count > read_limit.unwrap() {
-                break;
+/// Parses a number of bytes for `--split-size`, optionally followed by `K`, `M`, `G` or `T` in
+/// either case for that many KiB, MiB, GiB or TiB.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 30),
+        Some((i, 't' | 'T')) => (&s[..i], 40),
+        _ => (s, 0),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!(
+            "`{s}` isn't a number of bytes, optionally followed by K, M, G or T"
+        ));
+    }
+    let too_large = || format!("`{s}` is too large");
+    let size: u64 = digits.parse().map_err(|_| too_large())?;
+    size.checked_mul(1 << shift).ok_or_else(too_large)
+}
+
+/// Parses `--min-frequency`, a fraction of the sample.
+fn parse_min_frequency(s: &str) -> Result<f64, String> {
+    let min_frequency: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+    if (0.0..=1.0).contains(&min_frequency) {
+        Ok(min_frequency)
+    } else {
+        Err(format!("{min_frequency} isn't between 0 and 1"))
+    }
+}
+
+/// The first field index given to `-f` more than once, if any.
+fn duplicate_field_index(fields: &[isize]) -> Option<isize> {
+    let mut seen = HashSet::new();
+    fields
+        .iter()
+        .copied()
+        .find(|&field_index| !seen.insert(field_index))
+}
+
+/// Parses one percent for `--percentiles`.
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("`{s}` isn't a number"))?;
+    if (0.0..=100.0).contains(&percent) {
+        Ok(percent)
+    } else {
+        Err(format!("{percent} isn't between 0 and 100"))
+    }
+}
+
+/// Parses the target standard error for `--auto-samples`.
+fn parse_target_error(s: &str) -> Result<f64, String> {
+    let error: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+    if error > 0.0 && error < 0.5 {
+        Ok(error)
+    } else {
+        Err(format!("{error} isn't between 0 and 0.5"))
+    }
+}
+
+/// The most bytes read from the start of each file to estimate its number of records.
+const RECORD_ESTIMATE_PREFIX: u64 = 1 << 20;
+
+/// Estimates the number of records in the input files, by counting the record separators, or the
+/// paragraphs with `--paragraph-mode`, in the first megabyte of each file and scaling by its size.
+/// Returns `None` if a file is compressed, as its uncompressed size isn't known, or if an input is
+/// a stream.
+fn estimate_num_records(args: &Args) -> io::Result<Option<u64>> {
+    let mut num_records = 0;
+    for filename in &args.input_files {
+        if is_stream(filename)? || is_gzip(filename)? {
+            return Ok(None);
+        }
+        let file = File::open(filename)?;
+        let size = file.metadata()?.len();
+        let mut prefix = Vec::new();
+        file.take(RECORD_ESTIMATE_PREFIX).read_to_end(&mut prefix)?;
+        if args.sample.paragraph_mode {
+            // Each non-blank line following a blank one, or starting the file, starts a paragraph.
+            let mut paragraphs = 0;
+            let mut blank = true;
+            for line in prefix.split(|&b| b == b'\n') {
+                let is_blank = line.iter().all(u8::is_ascii_whitespace);
+                if blank && !is_blank {
+                    paragraphs += 1;
+                }
+                blank = is_blank;
             }
-            reservoir.add(record);
+            num_records += match prefix.len() as u64 {
+                0 => 0,
+                len => (paragraphs as f64 * size as f64 / len as f64).round() as u64,
+            };
+            continue;
         }
-        SampledFields {
-            reservoirs: vec![reservoir],
-            missing_field_counts: vec![0],
+        let mut separators = prefix
+            .iter()
+            .filter(|&&b| b == args.sample.record_separator)
+            .count() as u64;
+        if prefix
+            .last()
+            .is_some_and(|&b| b != args.sample.record_separator)
+        {
+            // The record at the end of the prefix, or of the file, hasn't ended yet.
+            separators += 1;
         }
-    } else {
-        let mut reservoirs: Vec<Reservoir<String>> = (0..args.fields.len())
-            .map(|_| Reservoir::new(args.sample_size))
+        num_records += match prefix.len() as u64 {
+            0 => 0,
+            len => (separators as f64 * size as f64 / len as f64).round() as u64,
+        };
+    }
+    Ok(Some(num_records))
+}
+
+/// The sample size for `--auto-samples`: the smallest giving a standard error of at most
+/// `target_error` for a frequency of 0.5, the worst case. Sampling a large part of `num_records`
+/// gives a smaller error, so fewer samples are needed from a short input; without a count of
+/// records, the size for an endless input is used.
+fn auto_sample_size(target_error: f64, num_records: Option<u64>) -> usize {
+    let endless = 0.25 / (target_error * target_error);
+    let size = match num_records {
+        Some(num_records) => endless / (1.0 + (endless - 1.0) / num_records.max(1) as f64),
+        None => endless,
+    };
+    (size.ceil() as usize).max(1)
+}
+
+/// Expands the `-i` paths that don't exist but are glob patterns into the files matching them,
+/// reporting how many matched each pattern unless `--quiet` is given. A pattern matching no files
+/// is an error.
+fn expand_input_globs(args: &Args) -> io::Result<Vec<String>> {
+    let mut input_files = Vec::new();
+    for input_file in &args.input_files {
+        if !is_glob_pattern(input_file)
+            || input_file.starts_with(TCP_SCHEME)
+            || Path::new(input_file).exists()
+        {
+            input_files.push(input_file.clone());
+            continue;
+        }
+        let matches: Vec<String> = glob(input_file)?
+            .into_iter()
+            .filter(|path| path.is_file())
+            .map(|path| path.to_string_lossy().into_owned())
             .collect();
-        let mut missing_field_counts: Vec<u64> = vec![0; args.fields.len()];
-        for record in reader.lines() {
-            let record = record.unwrap();
-            read_count += record.len() as u64;
-            if read_limit.is_some() && read_count > read_limit.unwrap() {
-                break;
-            }
-            let fields: Vec<&str> = match &args.field_separator {
-                None => record.split_whitespace().collect(),
-                Some(separator) => record.split(separator).collect(),
-            };
-            for (reservoir_index, field_index) in args.fields.iter().enumerate() {
-                if *field_index >= fields.len() {
-                    missing_field_counts[reservoir_index] += 1;
-                } else {
-                    reservoirs[reservoir_index].add(fields[*field_index].to_string())
-                }
-            }
+        if matches.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no files match {input_file}"),
+            ));
         }
-        SampledFields {
-            reservoirs,
-            missing_field_counts,
+        if !args.quiet {
+            eprintln!("{input_file} matched {} files", matches.len());
         }
+        input_files.extend(matches);
     }
+    Ok(input_files)
 }
 
-/// Build one or more reservoirs by reading line-separated records from a file.
-/// [Rayon](https://docs.rs/rayon/latest/rayon/) is used to process chunks of the file in parallel.
-fn process_file(args: &Args) -> io::Result<SampledFields> {
-    let filename = args.input_file.clone().unwrap();
-    let src = BufReader::new(File::open(&filename)?);
-    let splits = get_splits(src, args.split_size)?;
-    let result = splits
-        .par_iter()
-        .map(|range| {
-            let mut split_source = BufReader::new(File::open(&filename).unwrap());
-            split_source.seek(io::SeekFrom::Start(range.start)).unwrap();
-            process_reader(split_source, Some(range.end - range.start), args)
-        })
-        .reduce_with(|sr1, sr2| SampledFields::merge(&sr1, &sr2))
-        .unwrap();
-    Result::Ok(result)
+/// Reads the paths listed in `--files-from`, skipping blank lines and, with a warning, paths that
+/// can't be read.
+fn files_from(list: &str) -> io::Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = match list {
+        "-" => Box::new(stdin().lock()),
+        _ => Box::new(BufReader::new(
+            File::open(list).map_err(|e| with_filename(e, list))?,
+        )),
+    };
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let path = line.trim_end_matches('\r');
+        if path.is_empty() {
+            continue;
+        }
+        match std::fs::metadata(path) {
+            Ok(_) => paths.push(path.to_string()),
+            Err(e) => eprintln!("warning: skipping {path}: {e}"),
+        }
+    }
+    Ok(paths)
 }
 
-#[derive(Serialize)]
-struct ValueFrequency<'a> {
-    val: &'a String,
-    freq: f32,
+/// Checks that `saved`, the sample of `selection` loaded from `state_file`, can be merged with
+/// `other`, a sample of `other_selection`: both must be of the same selection and kind of sample,
+/// and have as many accumulators.
+fn check_mergeable(
+    state_file: &str,
+    (selection, saved): (&Selection, &SampledFields),
+    (other_selection, other): (&Selection, &SampledFields),
+) -> io::Result<()> {
+    let invalid = |message| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    if selection != other_selection {
+        return invalid(format!(
+            "{state_file} holds a sample of {selection} rather than {other_selection}"
+        ));
+    }
+    let (kind, other_kind) = (
+        accumulator_kind(&saved.accumulators[0]),
+        accumulator_kind(&other.accumulators[0]),
+    );
+    if kind != other_kind {
+        return invalid(format!(
+            "{state_file} holds {kind} rather than {other_kind}"
+        ));
+    }
+    if saved.accumulators.len() != other.accumulators.len() {
+        return invalid(format!(
+            "{state_file} holds {} accumulators, but a sample of {selection} has {}",
+            saved.accumulators.len(),
+            other.accumulators.len()
+        ));
+    }
+    Ok(())
 }
 
-/// Crop a reservoir to its top-k sampled values.
-fn histogram_top_k(reservoir: &Reservoir<String>, k: u32) -> Vec<ValueFrequency> {
-    let histogram = reservoir.to_histogram();
-    let mut vals = histogram.iter().map(|(k, v)| (*v, *k)).collect::<Vec<_>>();
-    vals.sort_by_cached_key(|&(freq, val)| (freq.to_bits(), val.clone()));
-    vals.reverse();
-    vals[0..usize::min(k as usize, vals.len())]
-        .iter()
-        .map(|(freq, val)| ValueFrequency { val, freq: *freq })
-        .collect()
+/// Merges `pr` with the sample saved in `state_file`, if there is one, and saves the result back
+/// to `state_file`. The saved sample must be `check_mergeable` with `pr`.
+fn update_state_file(
+    state_file: &str,
+    pr: SampledFields,
+    args: &Args,
+) -> io::Result<SampledFields> {
+    let selection = args.sample.selection();
+    let pr = match File::open(state_file) {
+        Ok(file) => {
+            let (saved_selection, saved) = SampledFields::load(BufReader::new(file))?;
+            check_mergeable(state_file, (&saved_selection, &saved), (&selection, &pr))?;
+            SampledFields::merge(&saved, &pr)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => pr,
+        Err(e) => return Err(e),
+    };
+    pr.save(&selection, BufWriter::new(File::create(state_file)?))?;
+    Ok(pr)
 }
 
-fn display_table(pr: &SampledFields, args: &Args) {
-    let top_k_fields: Vec<Vec<ValueFrequency>> = pr
-        .reservoirs
-        .iter()
-        .map(|r| histogram_top_k(r, args.num_results))
-        .collect();
-    let mut table = Table::new();
-    let row_width = top_k_fields.len();
-    if !args.fields.is_empty() {
-        // Header row: field indexes if defined
-        let header_cells: Vec<Cell> = args
-            .fields
+/// Loads each of the `--state-file`s given to `merge`, and writes their combination `--into` a
+/// single state file. Each must be `check_mergeable` with the first.
+fn merge_state_files(args: &MergeArgs) -> io::Result<()> {
+    let mut states: Vec<(Selection, SampledFields)> = Vec::new();
+    for state_file in &args.state_files {
+        let (selection, pr) = File::open(state_file)
+            .and_then(|file| SampledFields::load(BufReader::new(file)))
+            .map_err(|e| with_filename(e, state_file))?;
+        if let Some((first_selection, first)) = states.first() {
+            check_mergeable(state_file, (&selection, &pr), (first_selection, first))?;
+        }
+        states.push((selection, pr));
+    }
+    let merged = SampledFields::merge_many(states.iter().map(|(_, pr)| pr));
+    merged.save(&states[0].0, BufWriter::new(File::create(&args.into)?))
+}
+
+/// The kind of sample an accumulator holds. An interned sample is saved as a plain one, so
+/// they're the same kind.
+fn accumulator_kind(accumulator: &Accumulator) -> &'static str {
+    match accumulator {
+        Accumulator::Sample(_) | Accumulator::Interned(_) => "sample",
+        Accumulator::Exact(_) => "exact counts",
+        Accumulator::HeavyHitters(_) => "heavy hitters",
+        Accumulator::Unique(_) => "unique sample",
+    }
+}
+
+/// A description of the state saved in a state file: what kind of sample it holds of which
+/// fields, how much input went into it, and how many values each accumulator holds.
+fn state_stats(selection: &Selection, pr: &SampledFields) -> String {
+    let kind = accumulator_kind(&pr.accumulators[0]);
+    let fields = match &selection.fields[..] {
+        [] => "whole records".to_string(),
+        fields => fields
             .iter()
-            .map(|field_index| Cell::new(&format!("field {}", field_index)).with_hspan(2))
-            .collect();
-        table.add_row(Row::new(header_cells));
+            .map(|field_index| field_index.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    let mut stats = vec![format!("kind: {kind}"), format!("fields: {fields}")];
+    if let Some(header) = &pr.header {
+        stats.push(format!("header: {}", header.join(", ")));
     }
-    for row_index in 0..args.num_results as usize {
-        // Table body
-        let mut cells = Vec::with_capacity(row_width);
-        for value_list in &top_k_fields {
-            if row_index >= value_list.len() {
-                cells.push(Cell::new(""));
-                cells.push(Cell::new(""));
-            } else {
-                cells.push(Cell::new(&format!("{:.5}", value_list[row_index].freq)));
-                cells.push(Cell::new(value_list[row_index].val));
+    stats.push(format!(
+        "records: {}, bytes: {}",
+        pr.total_records, pr.total_bytes
+    ));
+    stats.push(format!(
+        "skipped records: {}, empty: {}, invalid UTF-8: {}, invalid weights: {}, truncated: {}",
+        pr.skipped_line_count,
+        pr.empty_line_count,
+        pr.invalid_line_count,
+        pr.invalid_weight_count,
+        pr.truncated_line_count
+    ));
+    for (reservoir_index, accumulator) in pr.accumulators.iter().enumerate() {
+        stats.push(format!(
+            "accumulator {reservoir_index}: {} values held of {} added, {} distinct, {} missing",
+            accumulator.value_counts().count(),
+            accumulator.num_adds(),
+            accumulator.to_histogram().len(),
+            pr.missing_field_counts[reservoir_index]
+        ));
+    }
+    stats.join("\n")
+}
+
+/// Prints the `state_stats` of the state file given to `stats`.
+fn print_state_stats(args: &StatsArgs) -> io::Result<()> {
+    let (selection, pr) = File::open(&args.state_file)
+        .and_then(|file| SampledFields::load(BufReader::new(file)))
+        .map_err(|e| with_filename(e, &args.state_file))?;
+    writeln!(stdout(), "{}", state_stats(&selection, &pr))
+}
+
+/// Returns true if `filename` names a gzip-compressed file, judged by its `.gz` extension or by
+/// its first two bytes.
+fn is_gzip(filename: &str) -> io::Result<bool> {
+    if filename.ends_with(".gz") {
+        return Ok(true);
+    }
+    let mut magic = [0u8; 2];
+    match File::open(filename)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == GZIP_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns true if `filename` starts with a byte order mark.
+fn has_bom(filename: &str) -> io::Result<bool> {
+    let mut start = Vec::with_capacity(3);
+    File::open(filename)?.take(3).read_to_end(&mut start)?;
+    Ok(encoding_rs::Encoding::for_bom(&start).is_some())
+}
+
+/// Returns true if `filename` is an input that can only be read through once from the start, and
+/// so can't be split: stdin, a `tcp://` address, or a file that isn't a regular one, such as a
+/// named pipe.
+fn is_stream(filename: &str) -> io::Result<bool> {
+    if filename == STDIN_INPUT || filename.starts_with(TCP_SCHEME) {
+        return Ok(true);
+    }
+    Ok(!std::fs::metadata(filename)?.is_file())
+}
+
+/// Returns true if `src` can be seeked to its end and back to its start, leaving it at its start.
+/// Some regular files can't be, such as those on some network filesystems.
+fn is_seekable<S: Seek>(src: &mut S) -> bool {
+    src.seek(io::SeekFrom::End(0))
+        .and_then(|_| src.seek(io::SeekFrom::Start(0)))
+        .is_ok()
+}
+
+/// Opens an input to read it from the start, connecting to the address of a `tcp://` input.
+fn open_input(filename: &str) -> io::Result<Box<dyn Read>> {
+    if filename == STDIN_INPUT {
+        return Ok(Box::new(stdin()));
+    }
+    match filename.strip_prefix(TCP_SCHEME) {
+        Some(address) => Ok(Box::new(TcpStream::connect(address)?)),
+        None => Ok(Box::new(File::open(filename)?)),
+    }
+}
+
+/// Adds the name of the file being read to an error.
+fn with_filename(e: io::Error, filename: &str) -> io::Error {
+    io::Error::new(e.kind(), format!("{filename}: {e}"))
+}
+
+/// Counts the bytes read through it on a progress bar, and optionally the lines too.
+struct ProgressReader<R> {
+    inner: R,
+    progress: ProgressBar,
+    /// The number of lines read so far, when they are being counted.
+    lines: Option<u64>,
+}
+
+impl<R> ProgressReader<R> {
+    fn new(inner: R, progress: &ProgressBar) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            progress: progress.clone(),
+            lines: None,
+        }
+    }
+
+    /// Also counts the lines read, showing the count as the progress bar's message.
+    fn counting_lines(mut self) -> ProgressReader<R> {
+        self.lines = Some(0);
+        self
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.inc(n as u64);
+        if let Some(lines) = &mut self.lines {
+            *lines += buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+            self.progress.set_message(format!("{lines} lines"));
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Creates the progress bar for `--progress`: a bar when the total number of bytes is known, or
+/// a spinner when it isn't. Without `--progress` the bar is hidden.
+fn progress_bar(args: &Args, total_bytes: Option<u64>) -> ProgressBar {
+    if !args.progress {
+        return ProgressBar::hidden();
+    }
+    match total_bytes {
+        Some(total_bytes) => ProgressBar::new(total_bytes).with_style(
+            ProgressStyle::with_template("{wide_bar} {bytes}/{total_bytes} ({eta})").unwrap(),
+        ),
+        None => ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template("{spinner} {bytes} read, {msg}").unwrap()),
+    }
+}
+
+/// The merged results of the parts of the input sampled so far, for `--preview-interval`.
+struct Preview<'a> {
+    args: &'a Args,
+    /// The merged results, and the number of parts merged into them.
+    partial: Mutex<Option<(SampledFields, usize)>>,
+}
+
+impl<'a> Preview<'a> {
+    fn new(args: &'a Args) -> Preview<'a> {
+        Preview {
+            args,
+            partial: Mutex::new(None),
+        }
+    }
+
+    /// Merges the result of sampling one more part of the input into the preview. Does nothing
+    /// without `--preview-interval`.
+    fn add(&self, part: &SampledFields) {
+        if self.args.preview_interval.is_none() {
+            return;
+        }
+        let mut partial = self.partial.lock().unwrap();
+        *partial = Some(match partial.take() {
+            Some((merged, num_parts)) => (SampledFields::merge(&merged, part), num_parts + 1),
+            None => (SampledFields::merge_many([part]), 1),
+        });
+    }
+
+    /// Prints the merged results so far to stderr as a table, hiding `progress` meanwhile.
+    fn render(&self, progress: &ProgressBar) -> io::Result<()> {
+        let mut out = Vec::new();
+        match &*self.partial.lock().unwrap() {
+            Some((merged, num_parts)) => {
+                writeln!(out, "preview of {num_parts} sampled parts of the input:")?;
+                display_table(merged, self.args, &mut out)?;
             }
+            None => return Ok(()),
         }
-        table.add_row(Row::new(cells));
+        progress.suspend(|| io::stderr().write_all(&out))
     }
-    if pr.missing_field_counts.iter().any(|c| *c > 0) {
-        // Footer row: missing field counts
-        table.add_empty_row();
-        let missing_cells: Vec<Cell> = pr
-            .missing_field_counts
-            .iter()
-            .flat_map(|c| match c {
-                0 => vec![Cell::new(""), Cell::new("")],
-                c => vec![
-                    Cell::new(&c.to_string()).style_spec("bFr"),
-                    Cell::new("<no value>").style_spec("bFr"),
-                ],
+
+    /// Runs `sample`, rendering the preview every `--preview-interval` seconds on a background
+    /// thread until it returns.
+    fn run_while<T>(&self, progress: &ProgressBar, sample: impl FnOnce() -> T) -> T {
+        let Some(interval) = self.args.preview_interval else {
+            return sample();
+        };
+        let (done, wait) = channel::<()>();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) =
+                    wait.recv_timeout(Duration::from_secs(interval))
+                {
+                    if let Err(e) = self.render(progress) {
+                        eprintln!("warning: can't write the preview: {e}");
+                        break;
+                    }
+                }
+            });
+            let result = sample();
+            drop(done);
+            result
+        })
+    }
+}
+
+/// A piece of the input that is sampled on one thread: a range of bytes from a file, or the whole
+/// of a file that can't be split.
+struct InputSplit<'a> {
+    filename: &'a str,
+    gzip: bool,
+    /// Whether the input is a stream, whose size isn't known.
+    stream: bool,
+    range: Option<Range<u64>>,
+    /// Whether the file's `--skip-lines` records and header are read before its range: for the
+    /// range at its start, or with `--sample-splits` the first range chosen of the file.
+    preamble: bool,
+    /// The whole of the file, when it's memory-mapped with `--mmap`.
+    mapping: Option<&'a Mmap>,
+    /// The most records to read from this split, with `--max-records`.
+    record_limit: Option<u64>,
+}
+
+/// An input file about to be split: whether it's gzip-compressed, whether it's a stream that
+/// can't be seeked, whether it has to be read whole on a single thread, and with `--mmap` its
+/// mapping.
+struct InputFile<'a> {
+    filename: &'a str,
+    gzip: bool,
+    stream: bool,
+    whole: bool,
+    mapping: Option<Mmap>,
+}
+
+/// Maps the whole of `filename` into memory for `--mmap`, so that ranges of it can be read from
+/// several threads without opening the file for each of them.
+fn map_file(filename: &str) -> io::Result<Mmap> {
+    let file = File::open(filename)?;
+    // SAFETY: The mapping is only ever read, and reflects later changes to the file. Rust assumes
+    // the bytes behind a `&[u8]` don't change, so `--mmap` is documented as only for files that
+    // won't be written while they're read; one truncated meanwhile kills the process with SIGBUS
+    // when the bytes past its new end are read.
+    unsafe { Mmap::map(&file) }
+}
+
+/// Opens each of the input files to find out how it can be split, mapping it into memory with
+/// `--mmap`. A stream is only judged to be gzip-compressed by its `.gz` extension, since reading
+/// its first bytes would take them from the records. A file that can't be seeked is read whole,
+/// like a stream, with a warning that it isn't split.
+fn open_inputs(args: &Args) -> io::Result<Vec<InputFile<'_>>> {
+    let mut inputs = Vec::new();
+    for filename in &args.input_files {
+        let stream = is_stream(filename).map_err(|e| with_filename(e, filename))?;
+        let gzip = match stream {
+            true => filename.ends_with(".gz"),
+            false => is_gzip(filename).map_err(|e| with_filename(e, filename))?,
+        };
+        let mut whole = stream
+            || gzip
+            || args.encoding != Encoding::Utf8
+            || has_bom(filename).map_err(|e| with_filename(e, filename))?
+            || args.sample.csv_input
+            || args.sample.line_ending == LineEnding::Crlf
+            || args.sample.decay.is_some();
+        if !whole
+            && !is_seekable(&mut File::open(filename).map_err(|e| with_filename(e, filename))?)
+        {
+            eprintln!("warning: {filename} can't be seeked, so it's read on a single thread");
+            whole = true;
+        }
+        let mapping = (args.mmap && !whole)
+            .then(|| map_file(filename))
+            .transpose()
+            .map_err(|e| with_filename(e, filename))?;
+        inputs.push(InputFile {
+            filename,
+            gzip,
+            stream,
+            whole,
+            mapping,
+        });
+    }
+    Ok(inputs)
+}
+
+/// Splits each of the input files into ranges of about `--split-size` bytes, or a single split of
+/// the whole file when it can't be split, keeping only some of them with `--sample-splits`. With
+/// `--max-records`, the records are shared out between the splits, since they're read in
+/// parallel.
+fn input_splits<'a>(inputs: &'a [InputFile], args: &Args) -> io::Result<Vec<InputSplit<'a>>> {
+    let mut splits = Vec::new();
+    // The index of the input of each split.
+    let mut split_inputs = Vec::new();
+    for (input_index, input) in inputs.iter().enumerate() {
+        let filename = input.filename;
+        if input.whole {
+            split_inputs.push(input_index);
+            splits.push(InputSplit {
+                filename,
+                gzip: input.gzip,
+                stream: input.stream,
+                range: None,
+                preamble: true,
+                mapping: None,
+                record_limit: None,
+            });
+            continue;
+        }
+        let ranges = match &input.mapping {
+            Some(mapping) if args.sample.paragraph_mode => {
+                get_paragraph_splits(Cursor::new(&mapping[..]), args.split_size)?
+            }
+            Some(mapping) => get_splits(
+                Cursor::new(&mapping[..]),
+                args.split_size,
+                args.sample.record_separator,
+            )?,
+            None => {
+                let file =
+                    BufReader::new(File::open(filename).map_err(|e| with_filename(e, filename))?);
+                match args.sample.paragraph_mode {
+                    true => get_paragraph_splits(file, args.split_size)?,
+                    false => get_splits(file, args.split_size, args.sample.record_separator)?,
+                }
+            }
+        };
+        split_inputs.extend(std::iter::repeat_n(input_index, ranges.len()));
+        splits.extend(ranges.into_iter().map(|range| InputSplit {
+            filename,
+            gzip: input.gzip,
+            stream: false,
+            preamble: range.start == 0,
+            range: Some(range),
+            mapping: input.mapping.as_ref(),
+            record_limit: None,
+        }));
+    }
+    if let Some(num_splits) = args.sample_splits {
+        let chosen = choose_splits(
+            split_inputs.into_iter().zip(splits).collect(),
+            num_splits as usize,
+            args.seed,
+        );
+        // The range at the start of a file might not be chosen, so the first that is reads the
+        // file's header and skips its --skip-lines records instead.
+        let mut last_input = None;
+        splits = chosen
+            .into_iter()
+            .map(|(input_index, mut split)| {
+                split.preamble |= last_input != Some(input_index);
+                last_input = Some(input_index);
+                split
             })
             .collect();
-        table.add_row(Row::new(missing_cells));
     }
-    table.set_format(*format::consts::FORMAT_CLEAN);
-    table.printstd();
+    if let Some(max_records) = args.max_records {
+        // Share the records out between the splits, since they're read in parallel.
+        let split_limit = max_records.div_ceil(splits.len().max(1) as u64);
+        for split in &mut splits {
+            split.record_limit = Some(split_limit);
+        }
+    }
+    Ok(splits)
 }
 
-#[derive(Serialize)]
-struct JsonOut<'a> {
-    top_k_fields: Vec<Vec<ValueFrequency<'a>>>,
-    missing_field_counts: Vec<u64>,
+/// Chooses `n` of `splits` at random for `--sample-splits`, seeded with `seed` if it's given, and
+/// returns them in their input order.
+fn choose_splits<T>(splits: Vec<T>, n: usize, seed: Option<u64>) -> Vec<T> {
+    let mut rng = seed.map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+    let mut indexes: Vec<usize> = (0..splits.len()).collect();
+    rng.shuffle(&mut indexes);
+    let mut chosen = vec![false; splits.len()];
+    for &index in indexes.iter().take(n) {
+        chosen[index] = true;
+    }
+    splits
+        .into_iter()
+        .zip(chosen)
+        .filter_map(|(split, chosen)| chosen.then_some(split))
+        .collect()
 }
 
-fn display_json(pr: &SampledFields, args: &Args) {
-    let top_k_fields: Vec<Vec<ValueFrequency>> = pr
-        .reservoirs
-        .iter()
-        .map(|r| histogram_top_k(r, args.num_results))
-        .collect();
-    to_writer_pretty(
-        stdout(),
-        &JsonOut {
-            top_k_fields,
-            missing_field_counts: pr.missing_field_counts.clone(),
-        },
-    )
-    .unwrap();
+/// The number of bytes of a file in a split: those of its range, or the whole file.
+fn split_len(split: &InputSplit) -> io::Result<u64> {
+    match &split.range {
+        Some(range) => Ok(range.end - range.start),
+        None => Ok(std::fs::metadata(split.filename)?.len()),
+    }
 }
 
-fn main() {
-    let args = Args::parse();
-    if args.num_results > args.sample_size as u32 {
-        Args::command()
-            .error(
-                ErrorKind::ArgumentConflict,
-                "num-results must be <= num-samples",
-            )
-            .exit();
+/// Writes the splits `--dry-run` reports: the start, end and size in bytes of each, and the file
+/// it's from, then the number of splits. A file read whole is reported as a single split, and a
+/// stream, whose size isn't known, with dashes in place of its bytes.
+fn write_splits<W: Write>(splits: &[InputSplit], mut w: W) -> io::Result<()> {
+    for split in splits {
+        if split.stream {
+            writeln!(w, "- - - {}", split.filename)?;
+            continue;
+        }
+        let range = match &split.range {
+            Some(range) => range.clone(),
+            None => 0..std::fs::metadata(split.filename)?.len(),
+        };
+        writeln!(
+            w,
+            "{} {} {} {}",
+            range.start,
+            range.end,
+            range.end - range.start,
+            split.filename
+        )?;
     }
-    let pr: SampledFields = if args.input_file.is_none() {
-        process_reader(stdin().lock(), None, &args)
-    } else {
-        process_file(&args).unwrap()
+    writeln!(w, "{} splits", splits.len())
+}
+
+/// Reports how the input files would be split, for `--dry-run`, without reading their records.
+fn dry_run(args: &Args) -> io::Result<()> {
+    let inputs = open_inputs(args)?;
+    let splits = input_splits(&inputs, args)?;
+    write_splits(&splits, io::stderr().lock())
+}
+
+/// Build one or more reservoirs from an `InputSplit`, counting the bytes read on `progress`.
+fn process_split(
+    split: &InputSplit,
+    seed: Option<u64>,
+    progress: &ProgressBar,
+    args: &Args,
+) -> io::Result<SampledFields> {
+    match (&split.range, split.mapping) {
+        (None, _) => {
+            let file = ProgressReader::new(open_input(split.filename)?, progress);
+            let record_limit = split.record_limit;
+            match split.gzip {
+                true => process_whole(
+                    BufReader::new(args.encoding.decode(MultiGzDecoder::new(file))),
+                    record_limit,
+                    seed,
+                    &args.sample,
+                ),
+                false => process_whole(
+                    BufReader::new(args.encoding.decode(file)),
+                    record_limit,
+                    seed,
+                    &args.sample,
+                ),
+            }
+        }
+        (Some(range), Some(mapping)) => process_range(
+            ProgressReader::new(Cursor::new(&mapping[..]), progress),
+            range,
+            split.preamble,
+            split.record_limit,
+            seed,
+            args,
+        ),
+        (Some(range), None) => process_range(
+            ProgressReader::new(File::open(split.filename)?, progress),
+            range,
+            split.preamble,
+            split.record_limit,
+            seed,
+            args,
+        ),
+    }
+}
+
+/// Build one or more reservoirs from a range of bytes of a file. With `preamble`, the start of the
+/// file is read first, skipping `--skip-lines` records and then reading the file's header when
+/// `--header` is given, and the range's records start after them.
+fn process_range<R: Read + Seek>(
+    file: R,
+    range: &Range<u64>,
+    preamble: bool,
+    record_limit: Option<u64>,
+    seed: Option<u64>,
+    args: &Args,
+) -> io::Result<SampledFields> {
+    let mut src = BufReader::new(file);
+    let (skipped, header) = match preamble {
+        true => {
+            src.seek(io::SeekFrom::Start(0))?;
+            read_preamble(&mut src, &args.sample)?
+        }
+        false => (0, None),
     };
-    match args.output_format {
-        DisplayFormat::Table => display_table(&pr, &args),
-        DisplayFormat::Json => display_json(&pr, &args),
+    let start = range.start.max(src.stream_position()?);
+    src.seek(io::SeekFrom::Start(start))?;
+    let read_limit = range.end.saturating_sub(start);
+    let mut result = process_reader(src, Some(read_limit), record_limit, seed, &args.sample)?;
+    result.header = header;
+    result.skipped_line_count += skipped;
+    result.total_records += skipped;
+    Ok(result)
+}
+
+/// Build one or more reservoirs by reading line-separated records from one or more files.
+/// [Rayon](https://docs.rs/rayon/latest/rayon/) is used to process chunks of the files in parallel,
+/// and the chunks of every file are merged into a single result.
+///
+/// Gzip-compressed files can't be split because the compressed stream isn't seekable, so each of
+/// them is decompressed and read on a single thread, as is each stream: a `tcp://` connection, or
+/// a named pipe or other file that isn't a regular one. So is a regular file that can't be
+/// seeked, and one that has to be decoded with `--encoding` or starts with a byte order mark.
+/// With `--csv-input` every file is read on a single thread, since a quoted field may span a
+/// split boundary, and likewise with `--line-ending crlf` since files are split on bare newlines,
+/// and with `--decay` since values are decayed in the order they're read. With
+/// `--paragraph-mode`, files are split at blank lines.
+///
+/// With `--mmap`, each file that is split is mapped into memory once, and its splits are read
+/// from the mapping rather than by opening the file again for each of them.
+///
+/// When `--seed` is given, split `i` is sampled with the seed `seed ^ i`, so the result does not
+/// depend on which thread processes which split. The partial results are merged by `merge_tree`
+/// once every split has been sampled.
+///
+/// With `--sample-splits`, only the chosen splits are read, and unless `--quiet` is given the
+/// number of records in the whole input is estimated by scaling up those read by the share of
+/// the input's bytes they were read from.
+fn process_file(args: &Args) -> io::Result<SampledFields> {
+    let inputs = open_inputs(args)?;
+    let splits = input_splits(&inputs, args)?;
+    if args.split_size != DEFAULT_SPLIT_SIZE && splits.iter().any(|split| split.gzip) {
+        eprintln!("warning: --split-size is ignored for gzip-compressed input");
+    }
+    // The total isn't known when any input is a stream.
+    let total_bytes = match inputs.iter().any(|input| input.stream) {
+        true => None,
+        false => Some(
+            args.input_files
+                .iter()
+                .map(|filename| Ok(std::fs::metadata(filename)?.len()))
+                .sum::<io::Result<u64>>()?,
+        ),
+    };
+    // With `--sample-splits`, only the bytes of the chosen splits are read.
+    let read_bytes = match (args.sample_splits, total_bytes) {
+        (Some(_), Some(_)) => Some(splits.iter().map(split_len).sum::<io::Result<u64>>()?),
+        _ => total_bytes,
+    };
+    let progress = progress_bar(args, read_bytes);
+    let preview = Preview::new(args);
+    let results = preview.run_while(&progress, || {
+        splits
+            .par_iter()
+            .enumerate()
+            .map(|(split_index, split)| {
+                let split_seed = args.seed.map(|seed| seed ^ split_index as u64);
+                let result = process_split(split, split_seed, &progress, args)
+                    .map_err(|e| with_filename(e, split.filename))?;
+                preview.add(&result);
+                Ok(result)
+            })
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+    progress.finish_and_clear();
+    if results.is_empty() {
+        return Ok(SampledFields::new(args.seed, &args.sample));
+    }
+    let pr = merge_tree(results);
+    if let (Some(_), Some(total_bytes), Some(read_bytes)) =
+        (args.sample_splits, total_bytes, read_bytes)
+    {
+        if !args.quiet {
+            let estimate = pr.total_records as f64 * total_bytes as f64 / read_bytes.max(1) as f64;
+            eprintln!(
+                "sampled {} splits, {read_bytes} of {total_bytes} bytes; about {} records in all",
+                splits.len(),
+                estimate.round() as u64
+            );
+        }
+    }
+    Ok(pr)
+}
+
+/// How many partial results `merge_tree` merges together at a time.
+const MERGE_FAN_IN: usize = 16;
+
+/// Merges the partial results of sampling the parts of the input in a tree, so that merging many
+/// of them is spread over every thread: each run of `MERGE_FAN_IN` consecutive results is merged
+/// in a single pass by `SampledFields::merge_many`, in parallel, then each run of those, until
+/// one is left. The runs are fixed by the order of `parts` rather than by which thread gets to
+/// them, so the result is the same every time. Panics if `parts` is empty.
+fn merge_tree(mut parts: Vec<SampledFields>) -> SampledFields {
+    while parts.len() > 1 {
+        parts = parts
+            .par_chunks(MERGE_FAN_IN)
+            .map(SampledFields::merge_many)
+            .collect();
+    }
+    parts.pop().unwrap()
+}
+
+/// Cuts `chunk` short after its first `max_records` records, returning the number of records left
+/// in it.
+fn truncate_records(chunk: &mut Vec<u8>, max_records: u64, args: &Args) -> u64 {
+    let mut records = 0;
+    for end in 1..=chunk.len() {
+        let terminated = match args.sample.line_ending {
+            LineEnding::Crlf => chunk[..end].ends_with(b"\r\n"),
+            _ => chunk[end - 1] == args.sample.record_separator,
+        };
+        if terminated {
+            records += 1;
+            if records == max_records {
+                chunk.truncate(end);
+                return records;
+            }
+        }
+    }
+    if chunk
+        .last()
+        .is_some_and(|&b| b != args.sample.record_separator)
+    {
+        // The last record of the input, without a line ending
+        records += 1;
+    }
+    records
+}
+
+/// Build one or more reservoirs by reading line-separated records from stdin.
+///
+/// With `--parallel-stdin`, a reader thread cuts stdin into chunks of about `--split-size` bytes,
+/// each ending on a record boundary, and the chunks are sampled in parallel. A bounded channel
+/// between the reader and the samplers limits how many chunks are held in memory at once.
+fn process_stdin(args: &Args) -> io::Result<SampledFields> {
+    let progress = progress_bar(args, None);
+    let input = BufReader::new(
+        args.encoding
+            .decode(ProgressReader::new(stdin(), &progress).counting_lines()),
+    );
+    let result = if args.parallel_stdin {
+        let preview = Preview::new(args);
+        preview.run_while(&progress, || process_stdin_chunks(input, &preview, args))
+    } else {
+        process_whole(input, args.max_records, args.seed, &args.sample)
+    };
+    progress.finish_and_clear();
+    result
+}
+
+/// Samples chunks of stdin in parallel for `--parallel-stdin`, adding each chunk's sample to
+/// `preview` as it's completed.
+fn process_stdin_chunks<T: BufRead + Send>(
+    mut input: T,
+    preview: &Preview,
+    args: &Args,
+) -> io::Result<SampledFields> {
+    let (sender, receiver) = sync_channel::<Vec<u8>>(rayon::current_num_threads());
+    thread::scope(|scope| {
+        let reader = scope.spawn(move || -> io::Result<(Option<Vec<String>>, u64, u64)> {
+            let (skipped, header) = read_preamble(&mut input, &args.sample)?;
+            let mut records_left = args.max_records;
+            // The bytes of records too long for --max-line-bytes that were never put in a chunk.
+            let mut passed_over = 0;
+            while records_left != Some(0) {
+                let mut chunk = Vec::new();
+                (&mut input).take(args.split_size).read_to_end(&mut chunk)?;
+                let read_whole = chunk.len();
+                let tail_read = read_rest_of_record(&mut input, &mut chunk, &args.sample)?;
+                passed_over += (read_whole + tail_read - chunk.len()) as u64;
+                if let Some(records_left) = &mut records_left {
+                    *records_left -= truncate_records(&mut chunk, *records_left, args);
+                }
+                if chunk.is_empty() || sender.send(chunk).is_err() {
+                    break;
+                }
+            }
+            Ok((header, skipped, passed_over))
+        });
+        let results = receiver
+            .into_iter()
+            .enumerate()
+            .par_bridge()
+            .map(
+                |(chunk_index, chunk)| -> io::Result<(usize, SampledFields)> {
+                    let chunk_seed = args.seed.map(|seed| seed ^ chunk_index as u64);
+                    let result =
+                        process_reader(Cursor::new(chunk), None, None, chunk_seed, &args.sample)?;
+                    preview.add(&result);
+                    Ok((chunk_index, result))
+                },
+            )
+            .collect::<io::Result<Vec<_>>>();
+        let (header, skipped, passed_over) = reader.join().unwrap()?;
+        let mut results = results?;
+        // Chunks are sampled in whatever order the threads get to them, but merged in the order
+        // they were read so that the result doesn't depend on it.
+        results.sort_unstable_by_key(|(chunk_index, _)| *chunk_index);
+        let mut result = match results.is_empty() {
+            true => SampledFields::new(args.seed, &args.sample),
+            false => merge_tree(results.into_iter().map(|(_, result)| result).collect()),
+        };
+        result.header = header;
+        result.skipped_line_count += skipped;
+        result.total_records += skipped;
+        result.total_bytes += passed_over;
+        Ok(result)
+    })
+}
+
+/// The frequency of a value, written in JSON as a fraction named `freq`, or with `--as-percent`
+/// as a percentage named `percent`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+enum Frequency {
+    #[serde(rename = "freq")]
+    Fraction(f64),
+    #[serde(rename = "percent")]
+    Percent(f64),
+}
+
+impl Frequency {
+    /// The frequency as a fraction between 0 and 1.
+    fn fraction(self) -> f64 {
+        match self {
+            Frequency::Fraction(fraction) => fraction,
+            Frequency::Percent(percent) => percent / 100.0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ValueFrequency<'a> {
+    val: &'a String,
+    #[serde(flatten)]
+    freq: Frequency,
+    /// The estimated number of times the value was seen, present when `--show-counts` or
+    /// `--heavy-hitters` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
+    /// The most `count` may exceed the true count by, present with `--heavy-hitters`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_error: Option<u64>,
+    /// The standard error of `freq`, present when `--show-error` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr: Option<f64>,
+}
+
+/// A histogram entry ordered by rank: higher counts, and so higher frequencies, rank higher, and
+/// among equal counts, lower values rank higher.
+#[derive(PartialEq)]
+struct Ranked<'a> {
+    count: u64,
+    val: &'a String,
+}
+
+impl Eq for Ranked<'_> {}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.count
+            .cmp(&other.count)
+            .then_with(|| other.val.cmp(self.val))
+    }
+}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Crop an accumulator to its top-k values, ordered by descending frequency. Values with the same
+/// frequency are ordered by value, so the same pool always gives the same output. Values with a
+/// frequency below `min_frequency` are left out before the top k are taken. With `show_counts`,
+/// each value's count is estimated by scaling its frequency by the number of values added to the
+/// accumulator. With `show_error`, the standard error of each frequency is given. Heavy hitters
+/// always give their estimated counts and the most they may be over by.
+///
+/// Values are ranked by their integer counts in the accumulator, and frequencies are only worked
+/// out, as `f64`, for the values kept, so rare values in a large sample aren't tied or misordered
+/// by rounding.
+///
+/// The top k are kept in a min-heap of at most k entries, whose lowest-ranked entry is evicted
+/// whenever a higher-ranked one arrives, so only k entries are ever sorted.
+fn histogram_top_k(
+    accumulator: &Accumulator,
+    k: u32,
+    min_frequency: f64,
+    show_counts: bool,
+    show_error: bool,
+) -> Vec<ValueFrequency<'_>> {
+    let (counts, total) = accumulator.to_counts();
+    let k = k as usize;
+    let mut heap = BinaryHeap::with_capacity(k.min(counts.len()) + 1);
+    for (val, count) in counts {
+        if (count as f64 / total as f64) < min_frequency {
+            continue;
+        }
+        let entry = Reverse(Ranked { count, val });
+        if heap.len() < k {
+            heap.push(entry);
+        } else if heap.peek().is_some_and(|lowest| entry < *lowest) {
+            heap.pop();
+            heap.push(entry);
+        }
+    }
+    let num_adds = accumulator.num_adds();
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(Ranked { count, val })| {
+            let freq = count as f64 / total as f64;
+            let (count, max_error) = match accumulator {
+                Accumulator::HeavyHitters(counters) => match counters.estimate(val) {
+                    Some((count, max_error)) => (Some(count), Some(max_error)),
+                    None => (None, None),
+                },
+                Accumulator::Unique(_) => (None, None),
+                _ => (
+                    show_counts.then(|| (freq * num_adds as f64).round() as u64),
+                    None,
+                ),
+            };
+            ValueFrequency {
+                val,
+                freq: Frequency::Fraction(freq),
+                count,
+                max_error,
+                stderr: show_error.then(|| accumulator.standard_error(freq)),
+            }
+        })
+        .collect()
+}
+
+/// Estimates the requested `--percentiles` of each sampled field, or returns `None` if there
+/// aren't any.
+fn field_percentiles(pr: &SampledFields, args: &Args) -> Option<Vec<Percentiles>> {
+    if args.percentiles.is_empty() {
+        return None;
+    }
+    Some(
+        pr.accumulators
+            .iter()
+            .map(|a| percentiles(a.value_counts(), &args.percentiles))
+            .collect(),
+    )
+}
+
+/// The Shannon entropy of a field's values, as shown with `--entropy`.
+#[derive(Serialize)]
+struct Entropy {
+    /// `-Σ p log2 p` over the frequency `p` of each distinct value.
+    bits: f64,
+    /// `bits` divided by the log2 of the number of distinct values, or null when there are fewer
+    /// than two, whose entropy is always 0.
+    normalized: Option<f64>,
+}
+
+impl Entropy {
+    /// The entropy of the values in an accumulator, from their exact counts rather than the
+    /// rounded frequencies of its histogram.
+    fn new(accumulator: &Accumulator) -> Entropy {
+        let (counts, total) = accumulator.to_counts();
+        let bits = counts.values().fold(0.0, |bits, &count| {
+            let p = count as f64 / total as f64;
+            bits + p * (1.0 / p).log2()
+        });
+        Entropy {
+            bits,
+            normalized: (counts.len() > 1).then(|| bits / (counts.len() as f64).log2()),
+        }
+    }
+}
+
+/// The entropy of each sampled field, when `--entropy` is given.
+fn field_entropies(pr: &SampledFields, args: &Args) -> Option<Vec<Entropy>> {
+    args.entropy
+        .then(|| pr.accumulators.iter().map(Entropy::new).collect())
+}
+
+/// The estimated number of distinct values of each field, when `--estimate-cardinality` is given.
+fn cardinality_estimates(pr: &SampledFields) -> Option<Vec<u64>> {
+    pr.sketches.as_ref().map(|sketches| {
+        sketches
+            .iter()
+            .map(|sketch| sketch.estimate().round() as u64)
+            .collect()
+    })
+}
+
+/// What is sampled into an accumulator.
+enum Source<'a> {
+    /// Whole records.
+    Records,
+    /// The `-f` fields with these indexes: all of them with `--composite`, otherwise just one.
+    Fields(&'a [isize]),
+    /// A `--subfield`.
+    Subfield(&'a Subfield),
+}
+
+/// What is sampled into the accumulator at `reservoir_index`. The accumulators of `-f` fields
+/// come first, followed by those of subfields, then that of whole records with
+/// `--include-whole-line`.
+fn accumulator_source(args: &Args, reservoir_index: usize) -> Source<'_> {
+    let num_field_accumulators = args.sample.num_field_accumulators();
+    match reservoir_index.checked_sub(num_field_accumulators) {
+        _ if args.sample.whole_record_index() == Some(reservoir_index) => Source::Records,
+        Some(subfield_index) => Source::Subfield(&args.sample.subfields[subfield_index]),
+        None if args.sample.composite => Source::Fields(&args.sample.fields),
+        None => Source::Fields(&args.sample.fields[reservoir_index..reservoir_index + 1]),
+    }
+}
+
+/// Labels the sampled field at `reservoir_index` with its name from the header, if there is one,
+/// or with its index. With `--composite`, every sampled field is listed, and a subfield is
+/// labelled by its field and its index within the field, like `field 3.1`. Whole lines are
+/// labelled `whole line`, or `whole paragraph` with `--paragraph-mode`.
+fn field_label(pr: &SampledFields, reservoir_index: usize, args: &Args) -> String {
+    let label = |field_index: isize| match pr
+        .header
+        .as_ref()
+        .and_then(|names| header_name(names, field_index))
+    {
+        Some(name) => format!("{:?}", name),
+        None => field_index.to_string(),
+    };
+    match accumulator_source(args, reservoir_index) {
+        Source::Records if args.sample.paragraph_mode => "whole paragraph".to_string(),
+        Source::Records => "whole line".to_string(),
+        Source::Fields(&[field_index]) => format!("field {}", label(field_index)),
+        Source::Fields(fields) => {
+            let labels: Vec<String> = fields
+                .iter()
+                .map(|&field_index| label(field_index))
+                .collect();
+            format!("fields {}", labels.join(", "))
+        }
+        Source::Subfield(subfield) => {
+            format!("field {}.{}", label(subfield.field), subfield.index)
+        }
+    }
+}
+
+/// Looks up the name of a field in the header. Negative indexes are resolved against the length of
+/// the header.
+fn header_name(names: &[String], field_index: isize) -> Option<&String> {
+    resolve_field_index(field_index, names.len()).map(|field_index| &names[field_index])
+}
+
+/// The names of the sampled fields from the header, if there is one and fields were selected.
+/// Fields past the end of the header are named by their index. With `--composite`, there is a
+/// single name: those of the sampled fields, joined like their values. Subfields are named like
+/// `name.1`.
+fn field_names(pr: &SampledFields, args: &Args) -> Option<Vec<String>> {
+    if args.sample.samples_whole_records() {
+        return None;
+    }
+    pr.header.as_ref().map(|names| {
+        let name = |field_index: isize| {
+            header_name(names, field_index)
+                .cloned()
+                .unwrap_or_else(|| field_index.to_string())
+        };
+        (0..pr.accumulators.len())
+            .map(
+                |reservoir_index| match accumulator_source(args, reservoir_index) {
+                    Source::Records => String::new(),
+                    Source::Fields(fields) => fields
+                        .iter()
+                        .map(|&field_index| name(field_index))
+                        .collect::<Vec<_>>()
+                        .join(COMPOSITE_SEPARATOR),
+                    Source::Subfield(subfield) => {
+                        format!("{}.{}", name(subfield.field), subfield.index)
+                    }
+                },
+            )
+            .collect()
+    })
+}
+
+/// Formats a percentile estimate for the table, rounded like the frequencies but without trailing
+/// zeros.
+fn format_percentile(value: f64, precision: usize) -> String {
+    let formatted = format!("{value:.precision$}");
+    if !formatted.contains('.') {
+        return formatted;
+    }
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Rounds a frequency or standard error to `precision` digits after the decimal point for JSON
+/// output, where it would otherwise be written with every digit of the `f64`.
+fn round_to_precision(value: f64, precision: usize) -> f64 {
+    let scale = 10f64.powi(precision.min(f64::DIGITS as usize) as i32);
+    (value * scale).round() / scale
+}
+
+/// Formats a frequency or standard error for table, CSV and TSV output, to `--precision` digits
+/// and as a percentage with `--as-percent`.
+fn format_frequency(value: f64, args: &Args) -> String {
+    match args.as_percent {
+        true => format!("{:.*}%", args.precision, value * 100.0),
+        false => format!("{:.*}", args.precision, value),
+    }
+}
+
+/// A top-k value in JSON output: an object with its frequency, or with `--no-freq` just the value.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonValue<'a> {
+    Frequency(ValueFrequency<'a>),
+    Value(&'a String),
+}
+
+/// The top-k values of an accumulator for JSON output, with their frequencies and standard errors
+/// rounded to `--precision`, and given as percentages with `--as-percent`.
+fn json_top_k<'a>(accumulator: &'a Accumulator, args: &Args) -> Vec<JsonValue<'a>> {
+    let mut values = histogram_top_k(
+        accumulator,
+        args.num_results,
+        args.min_frequency,
+        args.show_counts,
+        args.show_error,
+    );
+    let scale = if args.as_percent { 100.0 } else { 1.0 };
+    for value in &mut values {
+        let freq = round_to_precision(value.freq.fraction() * scale, args.precision);
+        value.freq = match args.as_percent {
+            true => Frequency::Percent(freq),
+            false => Frequency::Fraction(freq),
+        };
+        value.stderr = value
+            .stderr
+            .map(|stderr| round_to_precision(stderr * scale, args.precision));
+    }
+    values
+        .into_iter()
+        .map(|value| match args.no_freq {
+            true => JsonValue::Value(value.val),
+            false => JsonValue::Frequency(value),
+        })
+        .collect()
+}
+
+/// The number of table columns for each field: the frequency and the value, with the count
+/// between them when `--show-counts` or `--heavy-hitters` is given and the standard error when
+/// `--show-error` is. With `--no-freq` there's only the value.
+fn cells_per_field(args: &Args) -> usize {
+    if args.no_freq {
+        return 1;
+    }
+    let shows_counts = args.show_counts || args.sample.heavy_hitters.is_some();
+    2 + shows_counts as usize + args.show_error as usize
+}
+
+/// Formats a count for the table. A heavy hitter's count is shown as the range its true count is
+/// in, like `95-100`, unless it's exact.
+fn format_count(count: u64, max_error: Option<u64>) -> String {
+    match max_error {
+        Some(max_error) if max_error > 0 => format!("{}-{count}", count - max_error),
+        _ => count.to_string(),
+    }
+}
+
+/// The cells of one field in a table row below the top-k values, with empty cells in the count
+/// and standard error columns when they're shown. With `--no-freq`, where each field has a single
+/// column, they're joined into one cell.
+fn field_cells(first: &str, last: &str, args: &Args) -> Vec<Cell> {
+    if args.no_freq {
+        return vec![Cell::new(format!("{first} {last}").trim())];
+    }
+    let mut cells = vec![Cell::new(first)];
+    cells.extend((2..cells_per_field(args)).map(|_| Cell::new("")));
+    cells.push(Cell::new(last));
+    cells
+}
+
+/// The label for the counts of missing fields in table output.
+fn missing_label(args: &Args) -> &str {
+    match &args.missing_label {
+        Some(label) => label,
+        None if args.sample.empty_as_missing => "<missing or empty field>",
+        None => "<missing field>",
+    }
+}
+
+/// Whether table output is colored, following `--color`.
+fn colors_table(args: &Args) -> bool {
+    match args.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            args.output_file.is_none()
+                && args.output_dir.is_none()
+                && stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty())
+        }
+    }
+}
+
+/// Prints a table to `out`, with its cells' styles when `colored` is true. Styles need a terminfo
+/// entry for the terminal named by `TERM`; without one, the table is printed without them.
+fn print_table(table: &Table, colored: bool, out: &mut dyn Write) -> io::Result<()> {
+    if colored {
+        if let Some(mut terminal) = term::TerminfoTerminal::new(&mut *out) {
+            table.print_term(&mut terminal)?;
+            return Ok(());
+        }
+    }
+    table.print(out)?;
+    Ok(())
+}
+
+fn display_table(pr: &SampledFields, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    let top_k_fields: Vec<Vec<ValueFrequency>> = pr
+        .accumulators
+        .iter()
+        .map(|a| {
+            histogram_top_k(
+                a,
+                args.num_results,
+                args.min_frequency,
+                args.show_counts,
+                args.show_error,
+            )
+        })
+        .collect();
+    let mut table = Table::new();
+    let cells_per_field = cells_per_field(args);
+    let row_width = top_k_fields.len() * cells_per_field;
+    // Header row: field names or indexes if defined
+    let header_cells: Vec<Cell> = (0..pr.accumulators.len())
+        .map(|reservoir_index| {
+            Cell::new(&field_label(pr, reservoir_index, args)).with_hspan(cells_per_field)
+        })
+        .collect();
+    table.add_row(Row::new(header_cells));
+    let num_rows = top_k_fields.iter().map(Vec::len).max().unwrap_or(0);
+    for row_index in 0..num_rows {
+        // Table body
+        let mut cells = Vec::with_capacity(row_width);
+        for value_list in &top_k_fields {
+            if row_index >= value_list.len() {
+                cells.extend((0..cells_per_field).map(|_| Cell::new("")));
+            } else {
+                let value = &value_list[row_index];
+                if !args.no_freq {
+                    cells.push(Cell::new(&format_frequency(value.freq.fraction(), args)));
+                }
+                if let Some(count) = value.count {
+                    cells.push(Cell::new(&format_count(count, value.max_error)));
+                }
+                if let Some(stderr) = value.stderr {
+                    cells.push(Cell::new(&format!("±{}", format_frequency(stderr, args))));
+                }
+                cells.push(Cell::new(value.val));
+            }
+        }
+        table.add_row(Row::new(cells));
+    }
+    if let Some(field_percentiles) = field_percentiles(pr, args) {
+        // Percentile rows: one per requested percentile, then the non-numeric counts
+        table.add_empty_row();
+        for (percentile_index, percent) in args.percentiles.iter().enumerate() {
+            let cells: Vec<Cell> = field_percentiles
+                .iter()
+                .flat_map(|p| {
+                    let value = p.values[percentile_index]
+                        .value
+                        .map(|value| format_percentile(value, args.precision))
+                        .unwrap_or_default();
+                    field_cells(&format!("p{}", percent), &value, args)
+                })
+                .collect();
+            table.add_row(Row::new(cells));
+        }
+        if field_percentiles.iter().any(|p| p.non_numeric > 0) {
+            let cells: Vec<Cell> = field_percentiles
+                .iter()
+                .flat_map(|p| match p.non_numeric {
+                    0 => field_cells("", "", args),
+                    c => field_cells(&c.to_string(), "<not a number>", args),
+                })
+                .collect();
+            table.add_row(Row::new(cells));
+        }
+    }
+    if let Some(estimates) = cardinality_estimates(pr) {
+        // Cardinality row: estimated distinct values per field
+        table.add_empty_row();
+        let cells: Vec<Cell> = estimates
+            .iter()
+            .flat_map(|estimate| field_cells(&estimate.to_string(), "<distinct values>", args))
+            .collect();
+        table.add_row(Row::new(cells));
+    }
+    if let Some(entropies) = field_entropies(pr, args) {
+        // Entropy rows: the entropy in bits, then normalized
+        table.add_empty_row();
+        let cells: Vec<Cell> = entropies
+            .iter()
+            .flat_map(|entropy| {
+                field_cells(
+                    &format_percentile(entropy.bits, args.precision),
+                    "<entropy bits>",
+                    args,
+                )
+            })
+            .collect();
+        table.add_row(Row::new(cells));
+        let cells: Vec<Cell> = entropies
+            .iter()
+            .flat_map(|entropy| {
+                let normalized = entropy
+                    .normalized
+                    .map(|normalized| format_percentile(normalized, args.precision))
+                    .unwrap_or_default();
+                field_cells(&normalized, "<normalized entropy>", args)
+            })
+            .collect();
+        table.add_row(Row::new(cells));
+    }
+    if pr.missing_field_counts.iter().any(|c| *c > 0) {
+        // Footer row: missing field counts
+        table.add_empty_row();
+        let missing_cells: Vec<Cell> = pr
+            .missing_field_counts
+            .iter()
+            .flat_map(|c| match c {
+                0 => field_cells("", "", args),
+                c => field_cells(&c.to_string(), missing_label(args), args)
+                    .into_iter()
+                    .map(|cell| cell.style_spec("bFr"))
+                    .collect(),
+            })
+            .collect();
+        table.add_row(Row::new(missing_cells));
+    }
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    print_table(&table, colors_table(args), out)?;
+    if let Some(field_counts) = field_count_histogram(pr) {
+        // A second table of the number of records with each number of fields
+        writeln!(out)?;
+        let mut table = Table::new();
+        table.add_row(Row::new(vec![
+            Cell::new("fields"),
+            Cell::new("records"),
+            Cell::new("frequency"),
+        ]));
+        for count in field_counts {
+            table.add_row(Row::new(vec![
+                Cell::new(&count.fields.to_string()),
+                Cell::new(&count.records.to_string()),
+                Cell::new(&format_frequency(count.freq, args)),
+            ]));
+        }
+        table.set_format(*format::consts::FORMAT_CLEAN);
+        table.print(out)?;
+    }
+    Ok(())
+}
+
+/// The number of records with some number of fields, as shown with `--field-count-histogram`.
+#[derive(Serialize)]
+struct FieldCount {
+    fields: usize,
+    records: u64,
+    /// The share of the records counted that have `fields` fields.
+    freq: f64,
+}
+
+/// The number of records with each number of fields that any record has, fewest fields first,
+/// when `--field-count-histogram` is given.
+fn field_count_histogram(pr: &SampledFields) -> Option<Vec<FieldCount>> {
+    let field_counts = pr.field_counts.as_ref()?;
+    let total: u64 = field_counts.iter().sum();
+    Some(
+        field_counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &records)| records > 0)
+            .map(|(fields, &records)| FieldCount {
+                fields,
+                records,
+                freq: records as f64 / total as f64,
+            })
+            .collect(),
+    )
+}
+
+#[derive(Serialize)]
+struct JsonOut<'a> {
+    schema_version: u32,
+    /// The name and version of the program that wrote the output, like `rs-tool 0.1.1`.
+    generated_by: String,
+    /// The names of the sampled fields, present when `--header` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field_names: Option<Vec<String>>,
+    /// The top-k whole lines, present instead of `top_k_fields` and `missing_field_counts` when
+    /// no fields are selected, and as well as them with `--include-whole-line`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    whole_line: Option<Vec<JsonValue<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k_fields: Option<Vec<Vec<JsonValue<'a>>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    missing_field_counts: Option<Vec<u64>>,
+    /// The estimated percentiles of each field, present when `--percentiles` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentiles: Option<Vec<Percentiles>>,
+    /// The estimated number of distinct values of each field, present when
+    /// `--estimate-cardinality` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cardinality_estimates: Option<Vec<u64>>,
+    /// The entropy of each field, present when `--entropy` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entropy: Option<Vec<Entropy>>,
+    /// The number of records with each number of fields, present when `--field-count-histogram`
+    /// is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field_counts: Option<Vec<FieldCount>>,
+    invalid_line_count: u64,
+    skipped_line_count: u64,
+    /// The number of records skipped for their weight, present when `--weight-by` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    invalid_weight_count: Option<u64>,
+    /// The number of empty records skipped, present when `--skip-empty` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    empty_line_count: Option<u64>,
+    /// The number of records truncated, present when `--max-line-bytes` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated_line_count: Option<u64>,
+}
+
+fn display_json(pr: &SampledFields, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    let mut top_k_fields: Vec<Vec<JsonValue>> = pr
+        .accumulators
+        .iter()
+        .map(|a| json_top_k(a, args))
+        .collect();
+    let whole_line = args
+        .sample
+        .whole_record_index()
+        .map(|reservoir_index| top_k_fields.remove(reservoir_index));
+    // The other arrays describe the fields alone, like `top_k_fields`.
+    let num_fields = top_k_fields.len();
+    fn truncated<T>(mut values: Vec<T>, len: usize) -> Vec<T> {
+        values.truncate(len);
+        values
+    }
+    let (top_k_fields, missing_field_counts) = if args.sample.samples_whole_records() {
+        (None, None)
+    } else {
+        (
+            Some(top_k_fields),
+            Some(truncated(pr.missing_field_counts.clone(), num_fields)),
+        )
+    };
+    to_writer_pretty(
+        &mut *out,
+        &JsonOut {
+            schema_version: JSON_SCHEMA_VERSION,
+            generated_by: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            field_names: field_names(pr, args).map(|values| truncated(values, num_fields)),
+            whole_line,
+            top_k_fields,
+            missing_field_counts,
+            percentiles: field_percentiles(pr, args).map(|values| truncated(values, num_fields)),
+            cardinality_estimates: cardinality_estimates(pr)
+                .map(|values| truncated(values, num_fields)),
+            entropy: field_entropies(pr, args).map(|values| truncated(values, num_fields)),
+            field_counts: field_count_histogram(pr).map(|counts| {
+                counts
+                    .into_iter()
+                    .map(|count| FieldCount {
+                        freq: round_to_precision(count.freq, args.precision),
+                        ..count
+                    })
+                    .collect()
+            }),
+            invalid_line_count: pr.invalid_line_count,
+            skipped_line_count: pr.skipped_line_count,
+            invalid_weight_count: args.sample.weight_by.map(|_| pr.invalid_weight_count),
+            empty_line_count: args.sample.skip_empty.then_some(pr.empty_line_count),
+            truncated_line_count: args.sample.max_line_bytes.map(|_| pr.truncated_line_count),
+        },
+    )?;
+    writeln!(out)
+}
+
+/// Identifies what a line of `json-lines` output, or a field of `--distinct` or `--raw`
+/// output, describes.
+#[derive(Serialize)]
+struct FieldId {
+    /// The index of the field, or null when whole lines are sampled or with `--composite`.
+    field: Option<isize>,
+    /// The indexes of the fields sampled together, present with `--composite`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<Vec<isize>>,
+    /// The index of the part of `field` that is sampled, present for a `--subfield`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subfield: Option<isize>,
+}
+
+impl FieldId {
+    fn new(args: &Args, reservoir_index: usize) -> FieldId {
+        let (field, fields, subfield) = match accumulator_source(args, reservoir_index) {
+            Source::Records => (None, None, None),
+            Source::Fields(&[field_index]) if !args.sample.composite => {
+                (Some(field_index), None, None)
+            }
+            Source::Fields(fields) => (None, Some(fields.to_vec()), None),
+            Source::Subfield(subfield) => (Some(subfield.field), None, Some(subfield.index)),
+        };
+        FieldId {
+            field,
+            fields,
+            subfield,
+        }
+    }
+}
+
+/// One line of `json-lines` output, describing a single field.
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    id: FieldId,
+    values: Vec<JsonValue<'a>>,
+    missing: u64,
+}
+
+/// Writes one compact JSON object per field, each on its own line.
+fn display_json_lines(pr: &SampledFields, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    for (reservoir_index, accumulator) in pr.accumulators.iter().enumerate() {
+        let line = JsonLine {
+            schema_version: JSON_SCHEMA_VERSION,
+            id: FieldId::new(args, reservoir_index),
+            values: json_top_k(accumulator, args),
+            missing: pr.missing_field_counts[reservoir_index],
+        };
+        serde_json::to_writer(&mut *out, &line)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// The `field` column of CSV output for the accumulator at `reservoir_index`: empty when whole
+/// lines are sampled, otherwise the indexes of its fields joined like their values, or a
+/// subfield's field and index like `3.1`.
+fn csv_field(args: &Args, reservoir_index: usize) -> String {
+    match accumulator_source(args, reservoir_index) {
+        Source::Records => String::new(),
+        Source::Fields(fields) => fields
+            .iter()
+            .map(|field_index| field_index.to_string())
+            .collect::<Vec<_>>()
+            .join(COMPOSITE_SEPARATOR),
+        Source::Subfield(subfield) => format!("{}.{}", subfield.field, subfield.index),
+    }
+}
+
+/// Writes one `field,value,frequency,missing` row per top-k value of each field, without the
+/// `frequency` column with `--no-freq`. The `missing` column repeats the field's missing-value
+/// count on each of its rows; `field` is empty when whole lines are sampled, and lists the indexes
+/// of the fields joined like their values with `--composite`.
+fn display_csv(pr: &SampledFields, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    match args.no_freq {
+        true => writer.write_record(["field", "value", "missing"])?,
+        false => writer.write_record(["field", "value", "frequency", "missing"])?,
+    }
+    for (reservoir_index, accumulator) in pr.accumulators.iter().enumerate() {
+        let field = csv_field(args, reservoir_index);
+        let missing = pr.missing_field_counts[reservoir_index].to_string();
+        for value in histogram_top_k(
+            accumulator,
+            args.num_results,
+            args.min_frequency,
+            false,
+            false,
+        ) {
+            match args.no_freq {
+                true => writer.write_record([field.as_str(), value.val, &missing])?,
+                false => writer.write_record([
+                    field.as_str(),
+                    value.val,
+                    &format_frequency(value.freq.fraction(), args),
+                    &missing,
+                ])?,
+            }
+        }
+    }
+    writer.flush()
+}
+
+/// Escapes a TSV value, so that it can't contain the tabs and newlines separating values:
+/// backslashes, tabs, carriage returns and newlines are written as `\\`, `\t`, `\r` and `\n`.
+fn escape_tsv(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\\', '\t', '\r', '\n']) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 2);
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Writes one `field<TAB>value<TAB>frequency` line per top-k value of each field, without the
+/// frequency with `--no-freq`, with values escaped by `escape_tsv`. After a blank line, a final
+/// section has a `field<TAB>missing` line per field with its count of missing values. `field` is
+/// named as in CSV output.
+fn display_tsv(pr: &SampledFields, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    match args.no_freq {
+        true => writeln!(out, "field\tvalue")?,
+        false => writeln!(out, "field\tvalue\tfrequency")?,
+    }
+    for (reservoir_index, accumulator) in pr.accumulators.iter().enumerate() {
+        let field = csv_field(args, reservoir_index);
+        for value in histogram_top_k(
+            accumulator,
+            args.num_results,
+            args.min_frequency,
+            false,
+            false,
+        ) {
+            write!(out, "{}\t{}", escape_tsv(&field), escape_tsv(value.val))?;
+            if !args.no_freq {
+                write!(out, "\t{}", format_frequency(value.freq.fraction(), args))?;
+            }
+            writeln!(out)?;
+        }
+    }
+    writeln!(out)?;
+    writeln!(out, "field\tmissing")?;
+    for (reservoir_index, missing) in pr.missing_field_counts.iter().enumerate() {
+        let field = csv_field(args, reservoir_index);
+        writeln!(out, "{}\t{}", escape_tsv(&field), missing)?;
+    }
+    Ok(())
+}
+
+/// A one-line summary of the input read: the number of records, bytes and splits, the number of
+/// empty records skipped with `--skip-empty` and of those truncated with `--max-line-bytes`, and
+/// the number of missing values of each field.
+fn summary(pr: &SampledFields, args: &Args) -> String {
+    let mut summary = format!(
+        "read {} records, {} bytes, in {} splits",
+        pr.total_records, pr.total_bytes, pr.num_splits
+    );
+    if args.sample.skip_empty {
+        summary.push_str(&format!("; skipped {} empty", pr.empty_line_count));
+    }
+    if args.sample.max_line_bytes.is_some() {
+        summary.push_str(&format!("; truncated {} long", pr.truncated_line_count));
+    }
+    if !args.sample.samples_whole_records() {
+        let missing: Vec<String> = pr
+            .missing_field_counts
+            .iter()
+            .enumerate()
+            .filter(|&(reservoir_index, _)| {
+                args.sample.whole_record_index() != Some(reservoir_index)
+            })
+            .map(|(reservoir_index, missing)| {
+                format!("{} {missing}", field_label(pr, reservoir_index, args))
+            })
+            .collect();
+        summary.push_str(&format!("; missing: {}", missing.join(", ")));
+    }
+    summary
+}
+
+/// How much of the input a run read, not counting any state file it was merged with.
+struct InputRead {
+    bytes: u64,
+    splits: u64,
+}
+
+/// Returns true if stdin is the only input, read by `process_stdin`: no `-i`, or only `-i -`.
+fn reads_stdin(args: &Args) -> bool {
+    args.input_files
+        .iter()
+        .all(|filename| filename == STDIN_INPUT)
+}
+
+/// Samples the input, merging it with the state file if there is one. Unless `--quiet` is given,
+/// a summary of the input is printed before merging.
+fn run(args: &Args) -> io::Result<(SampledFields, InputRead)> {
+    let pr = if reads_stdin(args) {
+        process_stdin(args)?
+    } else {
+        process_file(args)?
+    };
+    if !args.quiet {
+        eprintln!("{}", summary(&pr, args));
+    }
+    let read = InputRead {
+        bytes: pr.total_bytes,
+        splits: pr.num_splits,
+    };
+    let pr = match &args.state_file {
+        Some(state_file) => update_state_file(state_file, pr, args)?,
+        None => pr,
+    };
+    Ok((pr, read))
+}
+
+/// The line printed by `--time`: the megabytes read, the time taken and the rate, and when the
+/// input was read in several splits, how many and on how many threads.
+fn timing(read: &InputRead, elapsed: Duration) -> String {
+    let megabytes = read.bytes as f64 / 1e6;
+    let seconds = elapsed.as_secs_f64();
+    let mut timing = format!(
+        "processed {megabytes:.1} MB in {seconds:.2} s ({:.1} MB/s)",
+        megabytes / seconds.max(f64::MIN_POSITIVE)
+    );
+    if read.splits > 1 {
+        timing.push_str(&format!(
+            " from {} splits on {} threads",
+            read.splits,
+            rayon::current_num_threads()
+        ));
+    }
+    timing
+}
+
+/// The first `k` distinct values in an accumulator, in sorted order.
+fn distinct_values(accumulator: &Accumulator, k: u32) -> Vec<&String> {
+    let mut values: Vec<&String> = accumulator.to_histogram().into_keys().collect();
+    values.sort_unstable();
+    values.truncate(k as usize);
+    values
+}
+
+/// The first `k` values held in an accumulator's sample, in no particular order.
+fn raw_values(accumulator: &Accumulator, k: u32) -> Vec<&String> {
+    accumulator
+        .value_counts()
+        .flat_map(|(value, count)| std::iter::repeat_n(value, count as usize))
+        .take(k as usize)
+        .collect()
+}
+
+/// The values of one field, as written by `--distinct` or `--raw` in `json` and `json-lines`
+/// output.
+#[derive(Serialize)]
+struct FieldValues<'a> {
+    #[serde(flatten)]
+    id: FieldId,
+    /// The name of the field, present when `--header` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    values: Vec<&'a String>,
+}
+
+/// Writes the distinct values of each field with `--distinct`, or the values held in its sample
+/// with `--raw`: a column per field in a table, a `field,value` row per value in CSV, and a
+/// `FieldValues` object per field in JSON, all in one array with `json` and one per line with
+/// `json-lines`.
+fn display_values(pr: &SampledFields, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    let values = if args.raw {
+        raw_values
+    } else {
+        distinct_values
+    };
+    let fields: Vec<Vec<&String>> = pr
+        .accumulators
+        .iter()
+        .map(|a| values(a, args.num_results))
+        .collect();
+    match args.output_format {
+        DisplayFormat::Table => {
+            let mut table = Table::new();
+            table.add_row(Row::new(
+                (0..fields.len())
+                    .map(|reservoir_index| Cell::new(&field_label(pr, reservoir_index, args)))
+                    .collect(),
+            ));
+            let num_rows = fields.iter().map(Vec::len).max().unwrap_or(0);
+            for row_index in 0..num_rows {
+                table.add_row(Row::new(
+                    fields
+                        .iter()
+                        .map(|values| {
+                            values
+                                .get(row_index)
+                                .map_or(Cell::new(""), |v| Cell::new(v))
+                        })
+                        .collect(),
+                ));
+            }
+            table.set_format(*format::consts::FORMAT_CLEAN);
+            table.print(out)?;
+            Ok(())
+        }
+        DisplayFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(["field", "value"])?;
+            for (reservoir_index, values) in fields.iter().enumerate() {
+                let field = csv_field(args, reservoir_index);
+                for value in values {
+                    writer.write_record([field.as_str(), value])?;
+                }
+            }
+            writer.flush()
+        }
+        DisplayFormat::Tsv => {
+            writeln!(out, "field\tvalue")?;
+            for (reservoir_index, values) in fields.iter().enumerate() {
+                let field = csv_field(args, reservoir_index);
+                for value in values {
+                    writeln!(out, "{}\t{}", escape_tsv(&field), escape_tsv(value))?;
+                }
+            }
+            Ok(())
+        }
+        DisplayFormat::Json | DisplayFormat::JsonLines => {
+            let names = field_names(pr, args);
+            let field_values = fields
+                .into_iter()
+                .enumerate()
+                .map(|(reservoir_index, values)| FieldValues {
+                    id: FieldId::new(args, reservoir_index),
+                    name: names.as_ref().map(|names| names[reservoir_index].clone()),
+                    values,
+                });
+            if matches!(args.output_format, DisplayFormat::Json) {
+                to_writer_pretty(&mut *out, &field_values.collect::<Vec<_>>())?;
+                return writeln!(out);
+            }
+            for line in field_values {
+                serde_json::to_writer(&mut *out, &line)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The number of records a field is present in and missing from, as written by `--count-only` in
+/// `json` and `json-lines` output.
+#[derive(Serialize)]
+struct FieldCounts {
+    #[serde(flatten)]
+    id: FieldId,
+    /// The name of the field, present when `--header` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    present: u64,
+    missing: u64,
+}
+
+/// Writes the number of records each field is present in and missing from with `--count-only`:
+/// a `field present missing` row per field in a table, CSV or TSV, and a `FieldCounts` object per
+/// field in JSON, all in one array with `json` and one per line with `json-lines`.
+fn display_field_counts(pr: &SampledFields, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    let counts: Vec<(u64, u64)> = pr
+        .accumulators
+        .iter()
+        .zip(&pr.missing_field_counts)
+        .map(|(accumulator, missing)| (accumulator.num_adds(), *missing))
+        .collect();
+    match args.output_format {
+        DisplayFormat::Table => {
+            let mut table = Table::new();
+            table.add_row(Row::new(vec![
+                Cell::new("field"),
+                Cell::new("present"),
+                Cell::new("missing"),
+            ]));
+            for (reservoir_index, (present, missing)) in counts.into_iter().enumerate() {
+                table.add_row(Row::new(vec![
+                    Cell::new(&field_label(pr, reservoir_index, args)),
+                    Cell::new(&present.to_string()),
+                    Cell::new(&missing.to_string()),
+                ]));
+            }
+            table.set_format(*format::consts::FORMAT_CLEAN);
+            table.print(out)?;
+            Ok(())
+        }
+        DisplayFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(["field", "present", "missing"])?;
+            for (reservoir_index, (present, missing)) in counts.into_iter().enumerate() {
+                writer.write_record([
+                    csv_field(args, reservoir_index),
+                    present.to_string(),
+                    missing.to_string(),
+                ])?;
+            }
+            writer.flush()
+        }
+        DisplayFormat::Tsv => {
+            writeln!(out, "field\tpresent\tmissing")?;
+            for (reservoir_index, (present, missing)) in counts.into_iter().enumerate() {
+                let field = csv_field(args, reservoir_index);
+                writeln!(out, "{}\t{present}\t{missing}", escape_tsv(&field))?;
+            }
+            Ok(())
+        }
+        DisplayFormat::Json | DisplayFormat::JsonLines => {
+            let names = field_names(pr, args);
+            let field_counts =
+                counts
+                    .into_iter()
+                    .enumerate()
+                    .map(|(reservoir_index, (present, missing))| FieldCounts {
+                        id: FieldId::new(args, reservoir_index),
+                        name: names.as_ref().map(|names| names[reservoir_index].clone()),
+                        present,
+                        missing,
+                    });
+            if matches!(args.output_format, DisplayFormat::Json) {
+                to_writer_pretty(&mut *out, &field_counts.collect::<Vec<_>>())?;
+                return writeln!(out);
+            }
+            for line in field_counts {
+                serde_json::to_writer(&mut *out, &line)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Writes the results in the `--output-format`, to the `--output-file` if one is given or else to
+/// stdout.
+fn write_output(pr: &SampledFields, args: &Args) -> io::Result<()> {
+    let mut out: Box<dyn Write> = match &args.output_file {
+        Some(output_file) => Box::new(BufWriter::new(File::create(output_file)?)),
+        None => Box::new(BufWriter::new(stdout())),
+    };
+    write_to(pr, args, &mut out)?;
+    out.flush()
+}
+
+/// Writes the results of each field to its own file in `dir`, for `--output-dir`, each as if it
+/// were the only field sampled. The accumulators are moved out of `pr` to do so.
+fn write_output_dir(pr: &mut SampledFields, dir: &str, args: &Args) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut names = HashSet::new();
+    for (reservoir_index, accumulator) in mem::take(&mut pr.accumulators).into_iter().enumerate() {
+        let mut name = output_file_name(pr, reservoir_index, args);
+        if !names.insert(name.clone()) {
+            // Two fields have the same name in the header.
+            name = format!("{name}-{reservoir_index}");
+        }
+        let path = Path::new(dir).join(format!("{name}.{}", args.output_format.extension()));
+        let (field_pr, field_args) = single_field(pr, accumulator, reservoir_index, args);
+        let mut out = BufWriter::new(File::create(&path)?);
+        write_to(&field_pr, &field_args, &mut out)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// The name of the `--output-dir` file of the accumulator at `reservoir_index`, without its
+/// extension: `line` for whole lines, otherwise the field's name in the header or its index, with
+/// any character that can't safely be part of a file name replaced by `_`. Composite fields are
+/// joined by `_`, and a subfield is named like `3.1`.
+fn output_file_name(pr: &SampledFields, reservoir_index: usize, args: &Args) -> String {
+    let name = |field_index: isize| match pr
+        .header
+        .as_ref()
+        .and_then(|names| header_name(names, field_index))
+    {
+        Some(name) => name
+            .chars()
+            .map(|c| match c.is_alphanumeric() || "-_.".contains(c) {
+                true => c,
+                false => '_',
+            })
+            .collect(),
+        None => field_index.to_string(),
+    };
+    let name: String = match accumulator_source(args, reservoir_index) {
+        Source::Records => "line".to_string(),
+        Source::Fields(fields) => fields
+            .iter()
+            .map(|&field_index| name(field_index))
+            .collect::<Vec<_>>()
+            .join("_"),
+        Source::Subfield(subfield) => format!("{}.{}", name(subfield.field), subfield.index),
+    };
+    match name.trim_start_matches('.') {
+        "" => format!("field-{reservoir_index}"),
+        _ => name,
+    }
+}
+
+/// The results and arguments of `accumulator`, from `reservoir_index` of `pr`, as if it were the
+/// only one sampled: the arguments select only what it samples.
+fn single_field(
+    pr: &SampledFields,
+    accumulator: Accumulator,
+    reservoir_index: usize,
+    args: &Args,
+) -> (SampledFields, Args) {
+    let mut field_args = args.clone();
+    let options = &mut field_args.sample;
+    let (fields, subfields) = match accumulator_source(args, reservoir_index) {
+        Source::Records => (vec![], vec![]),
+        Source::Fields(fields) => (fields.to_vec(), vec![]),
+        Source::Subfield(subfield) => (vec![], vec![subfield.clone()]),
+    };
+    options.composite &= !fields.is_empty();
+    options.fields = fields.into();
+    options.subfields = subfields;
+    options.include_whole_line = false;
+    let field_pr = SampledFields {
+        accumulators: vec![accumulator],
+        missing_field_counts: vec![pr.missing_field_counts[reservoir_index]],
+        header: pr.header.clone(),
+        sketches: pr
+            .sketches
+            .as_ref()
+            .map(|sketches| vec![sketches[reservoir_index].clone()]),
+        field_counts: pr.field_counts.clone(),
+        ..*pr
+    };
+    (field_pr, field_args)
+}
+
+/// Writes the results to `out` in the `--output-format`.
+fn write_to(pr: &SampledFields, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    match args.output_format {
+        _ if args.distinct || args.raw => display_values(pr, args, out),
+        _ if args.count_only => display_field_counts(pr, args, out),
+        DisplayFormat::Table => display_table(pr, args, out),
+        DisplayFormat::Json => display_json(pr, args, out),
+        DisplayFormat::JsonLines => display_json_lines(pr, args, out),
+        DisplayFormat::Csv => display_csv(pr, args, out),
+        DisplayFormat::Tsv => display_tsv(pr, args, out),
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Sample(args)) => sample(*args),
+        Some(Command::Merge(args)) => merge_state_files(&args),
+        Some(Command::Stats(args)) => print_state_stats(&args),
+        None => sample(cli.sample),
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        process::exit(1);
+    });
+}
+
+/// Samples the input as the `sample` subcommand, printing the results.
+fn sample(mut args: Args) -> io::Result<()> {
+    let start = Instant::now();
+    args.input_files = expand_input_globs(&args)?;
+    if let Some(list) = &args.files_from {
+        let paths = files_from(list)?;
+        if paths.is_empty() && args.input_files.is_empty() {
+            let list = if list == "-" { "stdin" } else { list };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{list} doesn't list any input files that exist"),
+            ));
+        }
+        args.input_files.extend(paths);
+    }
+    if args.sample_splits.is_some() && reads_stdin(&args) {
+        eprintln!("warning: --sample-splits needs input files, reading all of stdin");
+    }
+    if let Some(target_error) = args.auto_samples {
+        if reads_stdin(&args) {
+            eprintln!(
+                "warning: --auto-samples needs input files of known length, using -n {}",
+                args.sample.sample_size
+            );
+        } else {
+            let num_records = estimate_num_records(&args)?;
+            args.sample.sample_size = auto_sample_size(target_error, num_records);
+            // A short or empty input needs fewer samples than the top-k values shown, which
+            // isn't a mistake in the arguments as it would be with `-n`.
+            if args.num_results != ALL_RESULTS {
+                args.num_results = args.num_results.min(args.sample.sample_size as u32);
+            }
+        }
+    }
+    if args.count_only {
+        // Reservoirs with no room still count the values added to them.
+        args.sample.sample_size = 0;
+    } else if args.num_results != ALL_RESULTS && args.num_results > args.sample.sample_size as u32 {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "num-results must be <= num-samples",
+            )
+            .exit();
+    }
+    if !args.allow_duplicate_fields {
+        if let Some(field_index) = duplicate_field_index(&args.sample.fields) {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "field {field_index} is given more than once; use --allow-duplicate-fields to sample it repeatedly"
+                    ),
+                )
+                .exit();
+        }
+    }
+    if args.sample.csv_input
+        && args
+            .sample
+            .field_separator
+            .as_ref()
+            .is_some_and(|separator| separator.len() != 1)
+    {
+        Args::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "field-separator must be a single byte with --csv-input",
+            )
+            .exit();
+    }
+    if args.split_size < MIN_SPLIT_SIZE {
+        eprintln!(
+            "warning: --split-size {} is too small, using {MIN_SPLIT_SIZE} instead",
+            args.split_size
+        );
+        args.split_size = MIN_SPLIT_SIZE;
+    }
+    if args.dry_run {
+        if reads_stdin(&args) {
+            Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "--dry-run needs input files to split",
+                )
+                .exit();
+        }
+        return dry_run(&args);
+    }
+    let (mut pr, read) = run(&args)?;
+    match &args.output_dir {
+        Some(dir) => write_output_dir(&mut pr, dir, &args)?,
+        None => write_output(&pr, &args)?,
+    }
+    if args.time {
+        eprintln!("{}", timing(&read, start.elapsed()));
+    }
+    if pr.invalid_line_count > 0 {
+        eprintln!(
+            "warning: skipped {} records that weren't valid UTF-8; use --lossy to sample them",
+            pr.invalid_line_count
+        );
+    }
+    if pr.invalid_weight_count > 0 {
+        eprintln!(
+            "warning: skipped {} records whose --weight-by field was missing or not a number",
+            pr.invalid_weight_count
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rs_tool::{ExactCounts, Reservoir};
+
+    /// Writes `contents` to a file in the temp directory, returning its path.
+    fn write_temp_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rs-tool-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_multiple_files_weighted_by_size() {
+        let small = write_temp_file("small.txt", &"a\n".repeat(1000));
+        let large = write_temp_file("large.txt", &"b\n".repeat(9000));
+        let args = Args::parse_from([
+            "rs-tool", "-i", &small, "-i", &large, "-c", "1000", "--seed", "1",
+        ]);
+        let pr = process_file(&args).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        let a_freq = h.get(&"a".to_string()).unwrap();
+        assert!((a_freq - 0.1f32).abs() < 0.05f32, "a_freq == {a_freq}");
+        std::fs::remove_file(small).unwrap();
+        std::fs::remove_file(large).unwrap();
+    }
+
+    #[test]
+    fn test_top_k_ties_ordered_by_value() {
+        let mut r = Reservoir::new(100);
+        for val in ["d", "b", "e", "a", "c", "b", "a"] {
+            r.add(val.to_string());
+        }
+        let accumulator = Accumulator::Sample(r);
+        let top_k: Vec<&str> = histogram_top_k(&accumulator, 10, 0.0, false, false)
+            .iter()
+            .map(|vf| vf.val.as_str())
+            .collect();
+        assert_eq!(vec!["a", "b", "c", "d", "e"], top_k);
+    }
+
+    #[test]
+    fn test_top_k_keeps_highest_ranked() {
+        let mut counts = ExactCounts::new();
+        for (val, n) in [("c", 3), ("a", 1), ("e", 5), ("b", 3), ("d", 4), ("f", 1)] {
+            for _ in 0..n {
+                counts.add(val.to_string());
+            }
+        }
+        let accumulator = Accumulator::Exact(counts);
+        let top_k: Vec<&str> = histogram_top_k(&accumulator, 4, 0.0, false, false)
+            .iter()
+            .map(|vf| vf.val.as_str())
+            .collect();
+        assert_eq!(vec!["e", "d", "b", "c"], top_k);
+        assert!(histogram_top_k(&accumulator, 0, 0.0, false, false).is_empty());
+    }
+
+    #[test]
+    fn test_top_k_ranks_by_exact_counts() {
+        // Counts too large for their frequencies to differ as `f32`s.
+        let counts: ExactCounts<String> = serde_json::from_str(
+            r#"{"counts": {"a": 16777216, "b": 16777217}, "total": 33554433}"#,
+        )
+        .unwrap();
+        let accumulator = Accumulator::Exact(counts);
+        let histogram = accumulator.to_histogram();
+        assert_eq!(histogram[&"a".to_string()], histogram[&"b".to_string()]);
+        let top_k = histogram_top_k(&accumulator, 10, 0.0, true, false);
+        assert_eq!(
+            vec![("b", Some(16777217)), ("a", Some(16777216))],
+            top_k
+                .iter()
+                .map(|v| (v.val.as_str(), v.count))
+                .collect::<Vec<_>>()
+        );
+        assert!(top_k[0].freq.fraction() > top_k[1].freq.fraction());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(Ok(4096), parse_size("4096"));
+        assert_eq!(Ok(1024), parse_size("1K"));
+        assert_eq!(Ok(512 << 10), parse_size("512k"));
+        assert_eq!(Ok(1 << 20), parse_size("1M"));
+        assert_eq!(Ok(32 << 20), parse_size("32m"));
+        assert_eq!(Ok(1 << 30), parse_size("1G"));
+        assert_eq!(Ok(2 << 40), parse_size("2t"));
+        for invalid in [
+            "",
+            "M",
+            "1.5M",
+            "-1K",
+            "+1K",
+            "1KB",
+            "1X",
+            "1 M",
+            "16777216T",
+        ] {
+            assert!(parse_size(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(
+            32 << 20,
+            Args::parse_from(["rs-tool", "-c", "32M"]).split_size
+        );
+    }
+
+    #[test]
+    fn test_min_frequency() {
+        let mut counts = ExactCounts::new();
+        for (val, n) in [("c", 3), ("a", 1), ("e", 5), ("b", 3), ("d", 4), ("f", 1)] {
+            for _ in 0..n {
+                counts.add(val.to_string());
+            }
+        }
+        let accumulator = Accumulator::Exact(counts);
+        let top_k = |k, min_frequency| -> Vec<&str> {
+            histogram_top_k(&accumulator, k, min_frequency, false, false)
+                .iter()
+                .map(|vf| vf.val.as_str())
+                .collect()
+        };
+        // The values seen once make up 1/17 of the values each, and the next fewest 3/17.
+        assert_eq!(vec!["e", "d", "b", "c"], top_k(10, 0.1));
+        assert_eq!(vec!["e", "d"], top_k(2, 0.1));
+        assert_eq!(vec!["e", "d"], top_k(10, 0.2));
+        assert!(top_k(10, 1.0).is_empty());
+        assert_eq!(
+            0.1,
+            Args::parse_from(["rs-tool", "--min-frequency", "0.1"]).min_frequency
+        );
+        assert!(Args::try_parse_from(["rs-tool", "--min-frequency", "2"]).is_err());
+    }
+
+    /// Compares `histogram_top_k` against sorting the whole histogram, on a pool of 100k distinct
+    /// values. Run with `cargo test --release -- --ignored --nocapture bench_top_k`.
+    #[test]
+    #[ignore]
+    fn bench_top_k() {
+        let mut r = Reservoir::with_seed(100_000, 1);
+        for i in 0..100_000 {
+            r.add(format!("value-{i}"));
+        }
+        let accumulator = Accumulator::Sample(r);
+        for k in [10, 100, 1000] {
+            let start = Instant::now();
+            for _ in 0..20 {
+                let histogram = accumulator.to_histogram();
+                let mut vals = histogram.iter().map(|(k, v)| (*v, *k)).collect::<Vec<_>>();
+                vals.sort_by(|(f1, v1), (f2, v2)| f2.total_cmp(f1).then(v1.cmp(v2)));
+                vals.truncate(k);
+                std::hint::black_box(vals);
+            }
+            let sorted = start.elapsed() / 20;
+            let start = Instant::now();
+            for _ in 0..20 {
+                std::hint::black_box(histogram_top_k(&accumulator, k as u32, 0.0, false, false));
+            }
+            let heap = start.elapsed() / 20;
+            println!("k={k}: full sort {sorted:?}, bounded heap {heap:?}");
+        }
+    }
+
+    #[test]
+    fn test_merge_tree() {
+        let args = Args::parse_from(["rs-tool", "-n", "50", "-f", "0", "--seed", "1"]);
+        let parts: Vec<SampledFields> = (0..100)
+            .map(|i| {
+                let input: String = (0..20).map(|j| format!("{} x\n", i * 20 + j)).collect();
+                process_reader(Cursor::new(input), None, None, Some(i), &args.sample).unwrap()
+            })
+            .collect();
+        let single_pass = SampledFields::merge_many(&parts);
+        let tree = merge_tree(parts);
+        let sorted = |pr: &SampledFields| {
+            let mut values: Vec<String> = pr.accumulators[0]
+                .value_counts()
+                .map(|(value, _)| value.clone())
+                .collect();
+            values.sort();
+            values
+        };
+        assert_eq!(sorted(&single_pass), sorted(&tree));
+        assert_eq!(2000, tree.total_records);
+        assert_eq!(100, tree.num_splits);
+    }
+
+    /// Compares merging the samples of thousands of splits in a single pass against
+    /// `merge_tree`. Run with `cargo test --release -- --ignored --nocapture bench_merge`.
+    #[test]
+    #[ignore]
+    fn bench_merge() {
+        let args = Args::parse_from(["rs-tool", "-n", "1000", "--seed", "1"]);
+        let parts = || -> Vec<SampledFields> {
+            (0..4096)
+                .map(|i| {
+                    let input: String =
+                        (0..2000).map(|j| format!("{}\n", (i * j) % 5000)).collect();
+                    process_reader(Cursor::new(input), None, None, Some(i), &args.sample).unwrap()
+                })
+                .collect()
+        };
+        // Both times include dropping the parts, as `process_file` does once they're merged.
+        let single_pass_parts = parts();
+        let start = Instant::now();
+        std::hint::black_box(SampledFields::merge_many(&single_pass_parts));
+        drop(single_pass_parts);
+        let single_pass = start.elapsed();
+        let tree_parts = parts();
+        let start = Instant::now();
+        std::hint::black_box(merge_tree(tree_parts));
+        let tree = start.elapsed();
+        println!(
+            "{} threads: single pass {single_pass:?}, tree {tree:?}",
+            rayon::current_num_threads()
+        );
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let empty = write_temp_file("empty.txt", "");
+        let args = Args::parse_from(["rs-tool", "-i", &empty]);
+        let pr = process_file(&args).unwrap();
+        assert!(pr.accumulators[0].to_histogram().is_empty());
+        std::fs::remove_file(empty).unwrap();
+    }
+
+    #[test]
+    fn test_tcp_input() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("tcp://{}", listener.local_addr().unwrap());
+        let sender = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"a 1\nb 2\na 3\n").unwrap();
+        });
+        let args = Args::parse_from(["rs-tool", "-i", &address, "-f", "0"]);
+        assert!(is_stream(&address).unwrap());
+        let pr = process_file(&args).unwrap();
+        sender.join().unwrap();
+        assert_eq!(3, pr.total_records);
+        let histogram = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&(2.0 / 3.0)), histogram.get(&"a".to_string()));
+    }
+
+    /// A reader that fails to seek, like a pipe.
+    struct Unseekable<R>(R);
+
+    impl<R: Read> Read for Unseekable<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl<R> Seek for Unseekable<R> {
+        fn seek(&mut self, _: io::SeekFrom) -> io::Result<u64> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "Illegal seek"))
+        }
+    }
+
+    #[test]
+    fn test_unseekable_input() {
+        let input = "a\nb\na\n";
+        assert!(is_seekable(&mut Cursor::new(input)));
+        let mut unseekable = Unseekable(Cursor::new(input));
+        assert!(!is_seekable(&mut unseekable));
+        assert!(get_splits(BufReader::new(&mut unseekable), 2, b'\n').is_err());
+        // Read whole instead of split, an unseekable input samples every record.
+        let args = Args::parse_from(["rs-tool", "-n", "5"]);
+        let pr = process_whole(BufReader::new(unseekable), None, None, &args.sample).unwrap();
+        assert_eq!(3, pr.accumulators[0].num_adds());
+    }
+
+    #[test]
+    fn test_utf16_input() {
+        let text = "id,name\n1,café\n2,naïve\n3,café\n";
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let file = write_temp_file("utf16le.csv", "");
+        std::fs::write(&file, utf16).unwrap();
+        // The byte order mark is enough to decode it, and it's read whole rather than split.
+        for extra in [&[][..], &["--encoding", "utf16le"]] {
+            let mut argv = vec!["rs-tool", "-i", &file, "-s", ",", "-f", "1", "--header"];
+            argv.extend(extra);
+            let args = Args::parse_from(argv);
+            assert!(open_inputs(&args).unwrap()[0].whole);
+            let pr = process_file(&args).unwrap();
+            assert_eq!(Some(vec!["id".to_string(), "name".to_string()]), pr.header);
+            let h = pr.accumulators[0].to_histogram();
+            assert_eq!(Some(&(2.0 / 3.0)), h.get(&"café".to_string()));
+            assert_eq!(Some(&(1.0 / 3.0)), h.get(&"naïve".to_string()));
+        }
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_stdin_input() {
+        assert!(reads_stdin(&Args::parse_from(["rs-tool"])));
+        let args = Args::parse_from(["rs-tool", "-i", "-", "-f", "0"]);
+        assert!(reads_stdin(&args));
+        assert!(is_stream(STDIN_INPUT).unwrap());
+        let file = write_temp_file("stdin-input.txt", "a\n");
+        let args = Args::parse_from(["rs-tool", "-i", "-", "-i", &file]);
+        assert!(!reads_stdin(&args));
+        let inputs = open_inputs(&args).unwrap();
+        assert!(inputs[0].stream && inputs[0].whole && !inputs[0].gzip);
+        assert!(!inputs[1].stream);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_truncate_records() {
+        let args = Args::parse_from(["rs-tool"]);
+        let mut chunk = b"a\nb\nc".to_vec();
+        assert_eq!(2, truncate_records(&mut chunk, 2, &args));
+        assert_eq!(b"a\nb\n", chunk.as_slice());
+        let mut chunk = b"a\nb\nc".to_vec();
+        assert_eq!(3, truncate_records(&mut chunk, 5, &args));
+        assert_eq!(b"a\nb\nc", chunk.as_slice());
+    }
+
+    #[test]
+    fn test_skip_lines_only_in_first_split() {
+        let file = write_temp_file("skip.txt", &"a\n".repeat(1000));
+        let args = Args::parse_from(["rs-tool", "-i", &file, "-c", "100", "--skip-lines", "5"]);
+        let pr = process_file(&args).unwrap();
+        assert_eq!(5, pr.skipped_line_count);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_json_lines_output() {
+        let args = Args::parse_from(["rs-tool", "-f", "0", "-f", "2", "-o", "json-lines"]);
+        let pr =
+            process_reader(Cursor::new("a 1\na 2 3\n"), None, None, None, &args.sample).unwrap();
+        let mut out = Vec::new();
+        display_json_lines(&pr, &args, &mut out).unwrap();
+        assert_eq!(
+            "{\"schema_version\":2,\"field\":0,\"values\":[{\"val\":\"a\",\"freq\":1.0}],\"missing\":0}\n\
+             {\"schema_version\":2,\"field\":2,\"values\":[{\"val\":\"3\",\"freq\":1.0}],\"missing\":1}\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_distinct() {
+        let args = Args::parse_from(["rs-tool", "-k", "3", "--distinct", "-o", "csv"]);
+        let pr = process_reader(
+            Cursor::new("d\nb\nd\na\nc\n"),
+            None,
+            None,
+            None,
+            &args.sample,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        display_values(&pr, &args, &mut out).unwrap();
+        assert_eq!("field,value\n,a\n,b\n,c\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_raw() {
+        let raw = |argv: &[&str]| {
+            let args = Args::parse_from(argv);
+            let pr =
+                process_reader(Cursor::new("b\na\nb\n"), None, None, None, &args.sample).unwrap();
+            let mut out = Vec::new();
+            write_to(&pr, &args, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let out = raw(&["rs-tool", "--raw", "-n", "5", "-k", "all", "-o", "tsv"]);
+        let mut lines: Vec<&str> = out.lines().collect();
+        assert_eq!("field\tvalue", lines.remove(0));
+        lines.sort_unstable();
+        assert_eq!(vec!["\ta", "\tb", "\tb"], lines);
+
+        let out = raw(&["rs-tool", "--raw", "-n", "5", "-k", "2", "-o", "json-lines"]);
+        let line: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(2, line["values"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn test_subfield_labels() {
+        let args = Args::parse_from(["rs-tool", "-f", "0", "--subfield", "2:,:1", "--header"]);
+        let pr = process_reader(Cursor::new("a b c\n"), None, None, None, &args.sample).unwrap();
+        assert_eq!("field 2.1", field_label(&pr, 1, &args));
+        assert_eq!("2.1", csv_field(&args, 1));
+        let pr = SampledFields {
+            header: Some(vec!["id".to_string(), "x".to_string(), "tags".to_string()]),
+            ..pr
+        };
+        assert_eq!("field \"tags\".1", field_label(&pr, 1, &args));
+        assert_eq!(
+            Some(vec!["id".to_string(), "tags.1".to_string()]),
+            field_names(&pr, &args)
+        );
+    }
+
+    #[test]
+    fn test_tsv_output() {
+        let args = Args::parse_from(["rs-tool", "-f", "0", "-s", ",", "-o", "tsv"]);
+        let input = "a\tb,1\na\tb,2\nc\\d,3\n\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &args.sample).unwrap();
+        let mut out = Vec::new();
+        display_tsv(&pr, &args, &mut out).unwrap();
+        assert_eq!(
+            "field\tvalue\tfrequency\n\
+             0\ta\\tb\t0.50000\n\
+             0\t\t0.25000\n\
+             0\tc\\\\d\t0.25000\n\
+             \n\
+             field\tmissing\n\
+             0\t0\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!("x\\ny\\r", escape_tsv("x\ny\r"));
+    }
+
+    #[test]
+    fn test_preview_merges_parts() {
+        let args = Args::parse_from(["rs-tool", "--preview-interval", "1"]);
+        let preview = Preview::new(&args);
+        let total = preview.run_while(&ProgressBar::hidden(), || {
+            ["a\nb\n", "c\n"]
+                .iter()
+                .map(|part| {
+                    let pr = process_reader(Cursor::new(part), None, None, None, &args.sample);
+                    preview.add(&pr.unwrap());
+                })
+                .count()
+        });
+        assert_eq!(2, total);
+        let partial = preview.partial.lock().unwrap();
+        let (merged, num_parts) = partial.as_ref().unwrap();
+        assert_eq!(2, *num_parts);
+        assert_eq!(3, merged.accumulators[0].num_adds());
+
+        let args = Args::parse_from(["rs-tool"]);
+        let preview = Preview::new(&args);
+        preview.add(&SampledFields::new(None, &args.sample));
+        assert!(preview.partial.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let file = write_temp_file("empty-input.txt", "");
+        for argv in [
+            vec!["rs-tool", "-i", &file, "-o", "json"],
+            vec!["rs-tool", "-i", &file, "-o", "json", "-f", "0", "--exact"],
+            vec![
+                "rs-tool",
+                "-i",
+                &file,
+                "-o",
+                "json",
+                "--mmap",
+                "--heavy-hitters",
+                "2",
+            ],
+            vec!["rs-tool", "-o", "json", "--parallel-stdin"],
+        ] {
+            let args = Args::parse_from(&argv);
+            let pr = match args.input_files.is_empty() {
+                true => process_stdin_chunks(Cursor::new(""), &Preview::new(&args), &args),
+                false => process_file(&args),
+            }
+            .unwrap();
+            assert_eq!(0, pr.total_records, "{argv:?}");
+            let mut out = Vec::new();
+            write_to(&pr, &args, &mut out).unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+            let values = match args.sample.fields.is_empty() {
+                true => &json["whole_line"],
+                false => &json["top_k_fields"][0],
+            };
+            assert_eq!(Some(0), values.as_array().map(Vec::len), "{argv:?}");
+
+            let args = Args::parse_from(argv.iter().filter(|&&arg| arg != "json" && arg != "-o"));
+            let mut out = Vec::new();
+            write_to(&pr, &args, &mut out).unwrap();
+            assert_eq!(
+                1,
+                String::from_utf8(out).unwrap().lines().count(),
+                "{argv:?}"
+            );
+        }
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_auto_sample_size() {
+        assert_eq!(2500, auto_sample_size(0.01, None));
+        assert_eq!(100, auto_sample_size(0.05, None));
+        // A sample of most of a short input is nearly exact.
+        assert_eq!(97, auto_sample_size(0.01, Some(100)));
+        assert_eq!(2001, auto_sample_size(0.01, Some(10_000)));
+        assert_eq!(2500, auto_sample_size(0.01, Some(100_000_000)));
+        assert_eq!(1, auto_sample_size(0.01, Some(0)));
+    }
+
+    #[test]
+    fn test_estimate_num_records() {
+        let file = write_temp_file("estimate.txt", &"0123456789\n".repeat(1000));
+        let args = Args::parse_from(["rs-tool", "-i", &file, "-i", &file]);
+        assert_eq!(Some(2000), estimate_num_records(&args).unwrap());
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_show_counts() {
+        let args = Args::parse_from(["rs-tool", "-n", "2", "--show-counts", "--seed", "1"]);
+        let pr =
+            process_reader(Cursor::new("a\na\na\na\n"), None, None, None, &args.sample).unwrap();
+        let top_k = histogram_top_k(&pr.accumulators[0], 10, 0.0, true, false);
+        assert_eq!(Some(4), top_k[0].count);
+    }
+
+    #[test]
+    fn test_field_ranges() {
+        let args = Args::parse_from(["rs-tool", "-f", "0-2,7", "-f", "-1", "-f", "4"]);
+        assert_eq!(vec![0, 1, 2, 7, -1, 4], *args.sample.fields);
+        assert!(Args::parse_from(["rs-tool"]).sample.fields.is_empty());
+    }
+
+    #[test]
+    fn test_json_without_fields() {
+        let json = |argv: &[&str]| {
+            let args = Args::parse_from(argv);
+            let pr = process_reader(
+                Cursor::new("a,1\na,1\nb,2\n"),
+                None,
+                None,
+                None,
+                &args.sample,
+            )
+            .unwrap();
+            let mut out = Vec::new();
+            display_json(&pr, &args, &mut out).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&out).unwrap()
+        };
+        let whole_lines = json(&["rs-tool", "-o", "json"]);
+        let keys: Vec<&String> = whole_lines.as_object().unwrap().keys().collect();
+        assert_eq!(
+            vec![
+                "generated_by",
+                "invalid_line_count",
+                "schema_version",
+                "skipped_line_count",
+                "whole_line"
+            ],
+            keys
+        );
+        assert_eq!(JSON_SCHEMA_VERSION, whole_lines["schema_version"]);
+        assert_eq!(
+            format!("rs-tool {}", env!("CARGO_PKG_VERSION")),
+            whole_lines["generated_by"]
+        );
+        assert_eq!(2, whole_lines["whole_line"].as_array().unwrap().len());
+        assert_eq!("a,1", whole_lines["whole_line"][0]["val"]);
+
+        let fields = json(&["rs-tool", "-o", "json", "-s", ",", "-f", "0"]);
+        assert!(fields.get("whole_line").is_none());
+        assert_eq!("a", fields["top_k_fields"][0][0]["val"]);
+        assert_eq!(serde_json::json!([0]), fields["missing_field_counts"]);
+    }
+
+    #[test]
+    fn test_entropy() {
+        let args = Args::parse_from(["rs-tool", "-o", "json", "-f", "0,1,2", "--entropy"]);
+        let pr = process_reader(
+            Cursor::new("a x 1\nb x 1\nc x 2\nd x 2\n"),
+            None,
+            None,
+            None,
+            &args.sample,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        display_json(&pr, &args, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                {"bits": 2.0, "normalized": 1.0},
+                {"bits": 0.0, "normalized": null},
+                {"bits": 1.0, "normalized": 1.0},
+            ]),
+            json["entropy"]
+        );
+
+        let mut reservoir = Reservoir::new(10);
+        for value in ["a", "a", "a", "b"] {
+            reservoir.add(value.to_string());
+        }
+        let skewed = Entropy::new(&Accumulator::Sample(reservoir));
+        let bits = 0.75 * (4.0f64 / 3.0).log2() + 0.25 * 4.0f64.log2();
+        assert_eq!(bits, skewed.bits);
+        assert_eq!(Some(bits), skewed.normalized);
+    }
+
+    #[test]
+    fn test_field_count_histogram() {
+        let output = |format| {
+            let args = Args::parse_from(["rs-tool", "-o", format, "--field-count-histogram"]);
+            let pr = process_reader(
+                Cursor::new("a b c\nd e f\ng h\ni j k\n"),
+                None,
+                None,
+                None,
+                &args.sample,
+            )
+            .unwrap();
+            let mut out = Vec::new();
+            write_to(&pr, &args, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let json: serde_json::Value = serde_json::from_str(&output("json")).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                {"fields": 2, "records": 1, "freq": 0.25},
+                {"fields": 3, "records": 3, "freq": 0.75},
+            ]),
+            json["field_counts"]
+        );
+        let table = output("table");
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .skip_while(|line| !line.contains("records"))
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            vec![
+                vec!["fields", "records", "frequency"],
+                vec!["2", "1", "0.25000"],
+                vec!["3", "3", "0.75000"],
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn test_no_freq() {
+        let output = |format| {
+            let args = Args::parse_from(["rs-tool", "-o", format, "-f", "0,1", "--no-freq"]);
+            let pr = process_reader(Cursor::new("b x\na y\nb\n"), None, None, None, &args.sample)
+                .unwrap();
+            let mut out = Vec::new();
+            write_to(&pr, &args, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let rows: Vec<Vec<String>> = output("table")
+            .lines()
+            .map(|line| line.split_whitespace().map(str::to_string).collect())
+            .collect();
+        assert_eq!(vec!["field", "0", "field", "1"], rows[0]);
+        assert_eq!(vec!["b", "x"], rows[1]);
+        assert_eq!(vec!["a", "y"], rows[2]);
+        assert_eq!(vec!["1", "<missing", "field>"], rows[4]);
+        assert_eq!(
+            "field,value,missing\n0,b,0\n0,a,0\n1,x,1\n1,y,1\n",
+            output("csv")
+        );
+        assert!(output("tsv").starts_with("field\tvalue\n0\tb\n0\ta\n"));
+        let json: serde_json::Value = serde_json::from_str(&output("json")).unwrap();
+        assert_eq!(
+            serde_json::json!([["b", "a"], ["x", "y"]]),
+            json["top_k_fields"]
+        );
+        assert!(Args::try_parse_from(["rs-tool", "--no-freq", "--show-counts"]).is_err());
+    }
+
+    #[test]
+    fn test_summary() {
+        let file = write_temp_file("summary.txt", &"a,b\nc\n".repeat(50));
+        let args = Args::parse_from(["rs-tool", "-i", &file, "-s", ",", "-f", "0,1"]);
+        let pr = process_file(&args).unwrap();
+        assert_eq!(
+            "read 100 records, 300 bytes, in 1 splits; missing: field 0 0, field 1 50",
+            summary(&pr, &args)
+        );
+        std::fs::remove_file(file).unwrap();
+
+        let args = Args::parse_from(["rs-tool", "--skip-empty"]);
+        let pr = process_reader(Cursor::new("a\n\nb\n"), None, None, None, &args.sample).unwrap();
+        assert_eq!(
+            "read 3 records, 5 bytes, in 1 splits; skipped 1 empty",
+            summary(&pr, &args)
+        );
+    }
+
+    #[test]
+    fn test_unique_sample() {
+        let args = Args::parse_from(["rs-tool", "--unique-sample", "-n", "2"]);
+        let input = "a\na\na\nb\nc\na\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &args.sample).unwrap();
+        let top_k = histogram_top_k(&pr.accumulators[0], 10, 0.0, true, true);
+        assert_eq!(2, top_k.len());
+        for v in &top_k {
+            assert_eq!(Frequency::Fraction(0.5), v.freq);
+            assert_eq!((None, Some(0.0)), (v.count, v.stderr));
+        }
+        assert_eq!(6, pr.accumulators[0].num_adds());
+
+        // The sample only depends on the distinct values, so it survives saving and loading.
+        let mut saved = Vec::new();
+        pr.save(&args.sample.selection(), &mut saved).unwrap();
+        let (_, loaded) = SampledFields::load(Cursor::new(saved)).unwrap();
+        let values = |pr: &SampledFields| {
+            let mut values: Vec<String> = pr.accumulators[0]
+                .value_counts()
+                .map(|(value, _)| value.clone())
+                .collect();
+            values.sort();
+            values
+        };
+        assert_eq!(values(&pr), values(&loaded));
+        let again = process_reader(Cursor::new(input), None, None, None, &args.sample).unwrap();
+        assert_eq!(values(&pr), values(&again));
+    }
+
+    #[test]
+    fn test_write_splits() {
+        let file = write_temp_file("dry-run.txt", &"abc\n".repeat(2500));
+        let args = Args::parse_from(["rs-tool", "-i", &file, "-c", "4096", "--dry-run"]);
+        let inputs = open_inputs(&args).unwrap();
+        let splits = input_splits(&inputs, &args).unwrap();
+        let mut out = Vec::new();
+        write_splits(&splits, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            format!(
+                "0 4100 4100 {file}\n4100 8200 4100 {file}\n8200 10000 1800 {file}\n3 splits\n"
+            ),
+            out
+        );
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_sample_splits() {
+        assert_eq!(vec![0, 1, 2], choose_splits(vec![0, 1, 2], 5, None));
+        let chosen = choose_splits((0..10).collect(), 3, Some(7));
+        assert_eq!(3, chosen.len());
+        assert!(chosen.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(chosen, choose_splits((0..10).collect(), 3, Some(7)));
+
+        let file = write_temp_file("sample-splits.txt", &"abc\n".repeat(2500));
+        let argv = ["rs-tool", "-i", &file, "-c", "4096", "--sample-splits", "2"];
+        let args = Args::parse_from(argv.iter().chain(&["--seed", "1"]));
+        let inputs = open_inputs(&args).unwrap();
+        let splits = input_splits(&inputs, &args).unwrap();
+        assert_eq!(2, splits.len());
+        let pr = process_file(&args).unwrap();
+        assert_eq!(2, pr.num_splits);
+        let read: u64 = splits.iter().map(|split| split_len(split).unwrap()).sum();
+        assert_eq!(read / 4, pr.total_records);
+        std::fs::remove_file(file).unwrap();
+
+        // The header is read from the start of the file whether or not its range is chosen.
+        let contents = format!("name\n{}", "abc\n".repeat(3000));
+        let file = write_temp_file("sample-splits-header.txt", &contents);
+        for seed in ["1", "2", "3", "4", "5"] {
+            let args = Args::parse_from([
+                "rs-tool",
+                "-i",
+                &file,
+                "-c",
+                "4096",
+                "--sample-splits",
+                "1",
+                "--header",
+                "-f",
+                "0",
+                "--seed",
+                seed,
+            ]);
+            let pr = process_file(&args).unwrap();
+            assert_eq!(Some(vec!["name".to_string()]), pr.header);
+            assert_eq!(
+                Some(&1.0),
+                pr.accumulators[0].to_histogram().get(&"abc".to_string())
+            );
+        }
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_output_dir() {
+        let file = write_temp_file("output-dir.txt", "name age\nx 1\ny 1\n");
+        let dir = std::env::temp_dir().join(format!("rs-tool-{}-output-dir", process::id()));
+        let dir = dir.to_str().unwrap();
+        let args = Args::parse_from([
+            "rs-tool",
+            "-i",
+            &file,
+            "--header",
+            "-f",
+            "0-1",
+            "--subfield",
+            "1:.:0",
+            "--include-whole-line",
+            "-o",
+            "csv",
+            "--output-dir",
+            dir,
+        ]);
+        let mut pr = process_file(&args).unwrap();
+        write_output_dir(&mut pr, dir, &args).unwrap();
+        let read = |name: &str| std::fs::read_to_string(Path::new(dir).join(name)).unwrap();
+        assert_eq!(
+            "field,value,frequency,missing\n1,1,1.00000,0\n",
+            read("age.csv")
+        );
+        assert_eq!(
+            "field,value,frequency,missing\n1.0,1,1.00000,0\n",
+            read("age.0.csv")
+        );
+        assert!(read("name.csv").contains("\n0,x,0.50000,0\n"));
+        assert!(read("line.csv").contains("\n,x 1,0.50000,0\n"));
+        assert_eq!(4, std::fs::read_dir(dir).unwrap().count());
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_heavy_hitters() {
+        let args = Args::parse_from(["rs-tool", "--heavy-hitters", "2", "-k", "2"]);
+        let pr = process_reader(
+            Cursor::new("a\na\na\nb\nc\n"),
+            None,
+            None,
+            None,
+            &args.sample,
+        )
+        .unwrap();
+        let top_k = histogram_top_k(&pr.accumulators[0], 2, 0.0, false, false);
+        assert_eq!(
+            vec![("a", Some(3), Some(0)), ("c", Some(2), Some(1))],
+            top_k
+                .iter()
+                .map(|v| (v.val.as_str(), v.count, v.max_error))
+                .collect::<Vec<_>>()
+        );
+        let mut out = Vec::new();
+        display_table(&pr, &args, &mut out).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(vec!["0.60000", "3", "a"], rows[1]);
+        assert_eq!(vec!["0.40000", "1-2", "c"], rows[2]);
+    }
+
+    #[test]
+    fn test_count_only() {
+        let mut args = Args::parse_from(["rs-tool", "--count-only", "-f", "0,2", "-o", "csv"]);
+        // As `sample` does for `--count-only`.
+        args.sample.sample_size = 0;
+        let input = "a b c\nd e\nf g h\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &args.sample).unwrap();
+        assert!(pr.accumulators[0].to_histogram().is_empty());
+        let mut out = Vec::new();
+        write_to(&pr, &args, &mut out).unwrap();
+        assert_eq!(
+            "field,present,missing\n0,3,0\n2,2,1\n",
+            String::from_utf8(out).unwrap()
+        );
+        args.output_format = DisplayFormat::Table;
+        let mut out = Vec::new();
+        write_to(&pr, &args, &mut out).unwrap();
+        let rows: Vec<Vec<String>> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| l.split_whitespace().map(String::from).collect())
+            .collect();
+        assert_eq!(vec!["field", "present", "missing"], rows[0]);
+        assert_eq!(vec!["field", "2", "2", "1"], rows[2]);
+        assert!(Args::try_parse_from(["rs-tool", "--count-only", "-n", "5"]).is_err());
+    }
+
+    #[test]
+    fn test_few_values_trim_blank_rows() {
+        let args = Args::parse_from(["rs-tool", "-k", "100", "-s", ",", "-f", "0,1"]);
+        let input: String = (0..12).map(|i| format!("{},{}\n", i % 3, i % 2)).collect();
+        let pr = process_reader(Cursor::new(input), None, None, None, &args.sample).unwrap();
+        let mut out = Vec::new();
+        display_table(&pr, &args, &mut out).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        // A header row, then only as many rows as the field with the most values, the second
+        // field padded in the last.
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(4, rows.len(), "{table}");
+        assert_eq!(4, rows[2].len());
+        assert_eq!(2, rows[3].len());
+    }
+
+    #[test]
+    fn test_all_results() {
+        let args = Args::parse_from(["rs-tool", "-k", "all", "-s", ",", "-f", "0,1"]);
+        assert_eq!(ALL_RESULTS, args.num_results);
+        assert_eq!(
+            ALL_RESULTS,
+            Args::parse_from(["rs-tool", "-k", "0"]).num_results
+        );
+        let input: String = (0..15).map(|i| format!("{i},{}\n", i % 3)).collect();
+        let pr = process_reader(Cursor::new(input), None, None, None, &args.sample).unwrap();
+        assert_eq!(
+            15,
+            histogram_top_k(&pr.accumulators[0], ALL_RESULTS, 0.0, false, false).len()
+        );
+        let mut out = Vec::new();
+        display_table(&pr, &args, &mut out).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        // A header row, then a row per value of the first field, the second padded after 3.
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(16, rows.len());
+        assert_eq!(vec!["0.06667", "0", "0.33333", "0"], rows[1]);
+        assert_eq!(vec!["0.06667", "9"], rows[15]);
+    }
+
+    #[test]
+    fn test_files_from() {
+        let file = write_temp_file("listed.txt", "a\n");
+        let missing = std::env::temp_dir().join("rs-tool-no-such-file.txt");
+        let list = write_temp_file(
+            "list.txt",
+            &format!("{file}\r\n\n{}\n{file}", missing.display()),
+        );
+        assert_eq!(vec![file.clone(), file.clone()], files_from(&list).unwrap());
+        assert!(files_from(missing.to_str().unwrap()).is_err());
+        std::fs::remove_file(file).unwrap();
+        std::fs::remove_file(list).unwrap();
+    }
+
+    #[test]
+    fn test_expand_input_globs() {
+        let file = write_temp_file("glob-1.txt", "a\n");
+        let other = write_temp_file("glob-2.txt", "b\n");
+        let pattern = file.replace("glob-1", "glob-?");
+        let args = Args::parse_from(["rs-tool", "-q", "-i", &pattern, "-i", &file]);
+        assert_eq!(
+            vec![file.clone(), other.clone(), file.clone()],
+            expand_input_globs(&args).unwrap()
+        );
+        let unmatched = file.replace("glob-1", "glob-[3-9]");
+        let args = Args::parse_from(["rs-tool", "-i", &unmatched]);
+        assert!(expand_input_globs(&args).is_err());
+        std::fs::remove_file(file).unwrap();
+        std::fs::remove_file(other).unwrap();
+    }
+
+    #[test]
+    fn test_precision() {
+        let input = "a\na\nb\n";
+        let output = |argv: &[&str]| {
+            let args = Args::parse_from(argv);
+            let pr = process_reader(Cursor::new(input), None, None, None, &args.sample).unwrap();
+            let mut out = Vec::new();
+            write_to(&pr, &args, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let first_row = |argv: &[&str]| {
+            let table = output(argv);
+            table
+                .lines()
+                .nth(1)
+                .unwrap()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!("0.67 a", first_row(&["rs-tool", "--precision", "2"]));
+        assert_eq!("1 a", first_row(&["rs-tool", "--precision", "0"]));
+        let csv = output(&["rs-tool", "--precision", "3", "-o", "csv"]);
+        assert!(csv.contains(",a,0.667,"), "{csv}");
+        let json = output(&[
+            "rs-tool",
+            "--precision",
+            "2",
+            "-o",
+            "json-lines",
+            "--show-error",
+        ]);
+        assert!(json.contains(r#""freq":0.67,"stderr":0.27"#), "{json}");
+        assert_eq!("3", format_percentile(3.0, 0));
+        assert_eq!("2.5", format_percentile(2.5, 3));
+    }
+
+    #[test]
+    fn test_as_percent() {
+        let args = Args::parse_from(["rs-tool", "--as-percent", "--precision", "1"]);
+        let pr = process_reader(Cursor::new("a\na\nb\n"), None, None, None, &args.sample).unwrap();
+        let mut out = Vec::new();
+        display_tsv(&pr, &args, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("\ta\t66.7%\n"));
+        let args = Args::parse_from(["rs-tool", "--as-percent", "-o", "json-lines"]);
+        let mut out = Vec::new();
+        display_json_lines(&pr, &args, &mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains(r#"{"val":"a","percent":66.66667}"#), "{json}");
+        assert!(!json.contains("freq"), "{json}");
+    }
+
+    #[test]
+    fn test_color() {
+        let table = |color: &str| {
+            let args = Args::parse_from(["rs-tool", "-f", "1", "--color", color]);
+            let pr =
+                process_reader(Cursor::new("a b\nc\n"), None, None, None, &args.sample).unwrap();
+            let mut out = Vec::new();
+            display_table(&pr, &args, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(!table("never").contains('\x1b'));
+        assert!(table("never").contains("<missing field>"));
+        if std::env::var_os("TERM").is_some() && term::terminfo::TermInfo::from_env().is_ok() {
+            assert!(table("always").contains('\x1b'));
+        }
+        let args = Args::parse_from(["rs-tool", "-O", "out.txt"]);
+        assert!(!colors_table(&args));
+    }
+
+    #[test]
+    fn test_merge_state_files_and_stats() {
+        let save = |name: &str, input: &str, argv: &[&str]| {
+            let args = Args::parse_from(argv);
+            let pr = process_reader(Cursor::new(input), None, None, None, &args.sample).unwrap();
+            let path = write_temp_file(name, "");
+            pr.save(&args.sample.selection(), File::create(&path).unwrap())
+                .unwrap();
+            path
+        };
+        let exact = ["rs-tool", "-f", "0", "--exact"];
+        let state1 = save("state1.json", "a 1\nb 2\n", &exact);
+        let state2 = save("state2.json", "a 3\n\n", &exact);
+        let merged = write_temp_file("merged.json", "");
+        let Cli {
+            command: Some(Command::Merge(merge_args)),
+            ..
+        } = Cli::parse_from(["rs-tool", "merge", &state1, &state2, "--into", &merged])
+        else {
+            panic!("not parsed as merge");
+        };
+        merge_state_files(&merge_args).unwrap();
+        let (selection, pr) = SampledFields::load(File::open(&merged).unwrap()).unwrap();
+        assert_eq!(
+            "kind: exact counts\n\
+             fields: 0\n\
+             records: 4, bytes: 13\n\
+             skipped records: 0, empty: 0, invalid UTF-8: 0, invalid weights: 0, truncated: 0\n\
+             accumulator 0: 2 values held of 3 added, 2 distinct, 1 missing",
+            state_stats(&selection, &pr)
+        );
+
+        // Nor can samples of other selections or kinds be merged, even with as many accumulators.
+        let whole = save("whole.json", "a\n", &["rs-tool", "--exact"]);
+        let composite = save(
+            "composite.json",
+            "a\n",
+            &["rs-tool", "-f", "0", "--composite"],
+        );
+        let sample = save("sample.json", "a\n", &["rs-tool", "-f", "0"]);
+        for (other, message) in [
+            (&whole, "holds a sample of whole records rather than -f 0"),
+            (
+                &composite,
+                "holds a sample of -f 0 --composite rather than -f 0",
+            ),
+            (&sample, "holds sample rather than exact counts"),
+        ] {
+            let merge_args = MergeArgs {
+                state_files: vec![state1.clone(), other.clone()],
+                into: merged.clone(),
+            };
+            let err = merge_state_files(&merge_args).unwrap_err();
+            assert!(err.to_string().ends_with(message), "{err}");
+        }
+        for path in [state1, state2, merged, whole, composite, sample] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_state_file_rejects_other_selection() {
+        let state_file = write_temp_file("selection-state.json", "");
+        let update = |argv: &[&str]| {
+            let args = Args::parse_from([&["rs-tool", "-s", "|"], argv].concat());
+            let pr =
+                process_reader(Cursor::new("a|x,y\n"), None, None, None, &args.sample).unwrap();
+            update_state_file(&state_file, pr, &args)
+        };
+        // Each pair has as many accumulators of the same kind, but of different values.
+        for (saved, other) in [
+            (
+                &["-f", "0", "--subfield", "1:,:0"][..],
+                &["-f", "0", "--subfield", "1:,:1"][..],
+            ),
+            (
+                &["-f", "0", "-f", "1"],
+                &["-f", "0", "-f", "1", "--composite", "--include-whole-line"],
+            ),
+            (&["-f", "0"], &["-f", "1"]),
+            (&["-f", "0"], &["-f", "0", "-f", "1", "--composite"]),
+            (
+                &["-f", "0", "-f", "1"],
+                &["-f", "0", "--include-whole-line"],
+            ),
+            (&["--exact"], &["-f", "0", "--exact"]),
+        ] {
+            std::fs::remove_file(&state_file).unwrap_or_default();
+            update(saved).unwrap();
+            assert_eq!(2, update(saved).unwrap().accumulators[0].num_adds());
+            let err = update(other).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            let saved = Args::parse_from([&["rs-tool"], saved].concat())
+                .sample
+                .selection();
+            let other = Args::parse_from([&["rs-tool"], other].concat())
+                .sample
+                .selection();
+            assert!(err
+                .to_string()
+                .ends_with(&format!("holds a sample of {saved} rather than {other}")));
+            // The state file is left as it was.
+            let (selection, _) = SampledFields::load(File::open(&state_file).unwrap()).unwrap();
+            assert_eq!(saved, selection);
+        }
+        std::fs::remove_file(state_file).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_field_index() {
+        let args = Args::parse_from(["rs-tool", "-f", "1", "-f", "1"]);
+        assert_eq!(Some(1), duplicate_field_index(&args.sample.fields));
+        let args = Args::parse_from(["rs-tool", "-f", "0-2,1"]);
+        assert_eq!(Some(1), duplicate_field_index(&args.sample.fields));
+        let args = Args::parse_from(["rs-tool", "-f", "0-2", "-f", "-1"]);
+        assert_eq!(None, duplicate_field_index(&args.sample.fields));
+    }
+
+    #[test]
+    fn test_same_seed_same_result() {
+        let contents: String = (0..2000).map(|i| format!("{}\n", i % 300)).collect();
+        let file = write_temp_file("seeded.txt", &contents);
+        for extra in [&["-n", "50"][..], &["--heavy-hitters", "20"]] {
+            let argv = [&["rs-tool", "-i", &file, "-c", "500", "--seed", "3"], extra].concat();
+            let args = Args::parse_from(&argv);
+            let histogram = |pr: &SampledFields| {
+                let mut values: Vec<(String, u64)> = pr.accumulators[0]
+                    .value_counts()
+                    .map(|(value, count)| (value.clone(), count))
+                    .collect();
+                values.sort();
+                values
+            };
+            let from_file = || histogram(&process_file(&args).unwrap());
+            assert_eq!(from_file(), from_file(), "{argv:?}");
+            let from_stdin = || {
+                let input = Cursor::new(contents.as_bytes());
+                histogram(&process_stdin_chunks(input, &Preview::new(&args), &args).unwrap())
+            };
+            assert_eq!(from_stdin(), from_stdin(), "{argv:?}");
+        }
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_timing() {
+        let read = InputRead {
+            bytes: 3_000_000,
+            splits: 1,
+        };
+        assert_eq!(
+            "processed 3.0 MB in 1.50 s (2.0 MB/s)",
+            timing(&read, Duration::from_millis(1500))
+        );
+        let read = InputRead {
+            bytes: 3_000_000,
+            splits: 4,
+        };
+        assert!(timing(&read, Duration::from_secs(1)).contains(" from 4 splits on "));
+    }
+
+    #[test]
+    fn test_show_error() {
+        let args = Args::parse_from(["rs-tool", "--show-error", "--show-counts"]);
+        let pr =
+            process_reader(Cursor::new("a\na\na\nb\n"), None, None, None, &args.sample).unwrap();
+        let top_k = histogram_top_k(&pr.accumulators[0], 10, 0.0, false, true);
+        assert_eq!(Some((0.75f64 * 0.25 / 4.0).sqrt()), top_k[0].stderr);
+        let mut out = Vec::new();
+        display_table(&pr, &args, &mut out).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(vec!["whole", "line"], rows[0]);
+        assert_eq!(vec!["0.75000", "3", "±0.21651", "a"], rows[1]);
+    }
+
+    #[test]
+    fn test_progress_reader_counts_bytes_and_lines() {
+        let progress = ProgressBar::hidden();
+        let mut reader = ProgressReader::new(Cursor::new("a\nbc\nd"), &progress).counting_lines();
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        assert_eq!(6, progress.position());
+        assert_eq!(Some(2), reader.lines);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_mmap_matches_reading_splits() {
+        let file = write_temp_file("mmap.txt", &"one two\nthree\nfour five six\n".repeat(100));
+        let sample = |extra: &[&str]| {
+            let mut argv = vec![
+                "rs-tool", "-i", &file, "-c", "50", "--seed", "7", "--header",
+            ];
+            argv.extend(extra);
+            let pr = process_file(&Args::parse_from(argv)).unwrap();
+            let mut values: Vec<String> = match &pr.accumulators[0] {
+                Accumulator::Sample(r) => r.iter().cloned().collect(),
+                _ => unreachable!(),
+            };
+            values.sort();
+            (pr.header, values)
+        };
+        assert_eq!(sample(&[]), sample(&["--mmap"]));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_paragraphs_across_splits() {
+        let paragraphs = ["alpha\nbeta", "gamma", "delta\nepsilon\nzeta"];
+        let file = write_temp_file(
+            "paragraphs.txt",
+            &paragraphs.map(|p| format!("{p}\n\n")).concat().repeat(50),
+        );
+        let args = Args::parse_from(["rs-tool", "-i", &file, "-c", "7", "--paragraph-mode"]);
+        let pr = process_file(&args).unwrap();
+        assert_eq!(150, pr.total_records);
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(3, h.len(), "{h:?}");
+        for paragraph in paragraphs {
+            let freq = h.get(&paragraph.to_string()).unwrap();
+            assert!((freq - 1.0f32 / 3.0).abs() < 0.001, "{paragraph}: {freq}");
+        }
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_record_separator_across_splits() {
+        let records = ["alpha\nbeta", "gamma", "delta epsilon"];
+        let file = write_temp_file(
+            "nul.txt",
+            &records.map(|r| format!("{r}\0")).concat().repeat(50),
+        );
+        let args = Args::parse_from([
+            "rs-tool",
+            "-i",
+            &file,
+            "-c",
+            "7",
+            "--record-separator",
+            "\\0",
+        ]);
+        let pr = process_file(&args).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(3, h.len(), "{h:?}");
+        for record in records {
+            let freq = h.get(&record.to_string()).unwrap();
+            assert!((freq - 1.0f32 / 3.0).abs() < 0.001, "{record}: {freq}");
+        }
+        std::fs::remove_file(file).unwrap();
     }
 }
@@ -0,0 +1,129 @@
+use serde::Serialize;
+
+/// An estimate of one percentile of a numeric sample.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Percentile {
+    pub percent: f64,
+    /// The estimated value, or `None` if the sample held no numeric values.
+    pub value: Option<f64>,
+}
+
+/// Percentiles estimated from the numeric values of a sample.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Percentiles {
+    /// One estimate per requested percent, in the order they were requested.
+    pub values: Vec<Percentile>,
+    /// The number of sampled values that weren't finite numbers and were left out of the
+    /// estimates.
+    pub non_numeric: u64,
+}
+
+/// Estimates each of `percents` (from 0 to 100) from the values in `sample` that parse as finite
+/// numbers. Each value in `sample` comes with the number of times it occurs. Since a reservoir
+/// holds a uniform sample of its input, these are unbiased estimates of the percentiles of the
+/// whole input.
+///
+/// Percentiles that fall between two sampled values are linearly interpolated, so small samples
+/// still give an estimate for every percent, if a coarse one.
+pub fn percentiles<I, S>(sample: I, percents: &[f64]) -> Percentiles
+where
+    I: IntoIterator<Item = (S, u64)>,
+    S: AsRef<str>,
+{
+    let mut numbers = Vec::new();
+    let mut non_numeric = 0;
+    for (value, count) in sample {
+        match value.as_ref().trim().parse::<f64>() {
+            Ok(number) if number.is_finite() => numbers.push((number, count)),
+            _ => non_numeric += count,
+        }
+    }
+    numbers.sort_by(|(n1, _), (n2, _)| n1.total_cmp(n2));
+    let values = percents
+        .iter()
+        .map(|&percent| Percentile {
+            percent,
+            value: interpolate(&numbers, percent),
+        })
+        .collect();
+    Percentiles {
+        values,
+        non_numeric,
+    }
+}
+
+/// Finds `percent` in `sorted`, a list of numbers and their counts, by linear interpolation
+/// between the closest ranks.
+fn interpolate(sorted: &[(f64, u64)], percent: f64) -> Option<f64> {
+    let total: u64 = sorted.iter().map(|(_, count)| count).sum();
+    let last = total.checked_sub(1)?;
+    let position = percent / 100.0 * last as f64;
+    let lower = at_rank(sorted, position.floor() as u64);
+    let upper = at_rank(sorted, position.ceil() as u64);
+    Some(lower + (upper - lower) * position.fract())
+}
+
+/// Finds the number at `rank`, counting from 0, in a sorted list of numbers and their counts.
+fn at_rank(sorted: &[(f64, u64)], rank: u64) -> f64 {
+    let mut seen = 0;
+    for (number, count) in sorted {
+        seen += count;
+        if rank < seen {
+            return *number;
+        }
+    }
+    sorted[sorted.len() - 1].0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pairs each value with a count of 1, as in a reservoir's pool.
+    fn once<'a>(values: &'a [&'a str]) -> impl Iterator<Item = (&'a str, u64)> {
+        values.iter().map(|value| (*value, 1))
+    }
+
+    #[test]
+    fn test_empty() {
+        let p = percentiles(once(&[]), &[50.0]);
+        assert_eq!(None, p.values[0].value);
+        assert_eq!(0, p.non_numeric);
+    }
+
+    #[test]
+    fn test_single_value() {
+        let p = percentiles(once(&["7"]), &[0.0, 50.0, 99.0]);
+        let values: Vec<_> = p.values.iter().map(|v| v.value).collect();
+        assert_eq!(vec![Some(7.0), Some(7.0), Some(7.0)], values);
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let sample: Vec<(String, u64)> = (1..=101).rev().map(|i| (i.to_string(), 1)).collect();
+        let p = percentiles(sample, &[0.0, 50.0, 90.0, 100.0]);
+        let values: Vec<_> = p.values.iter().map(|v| v.value).collect();
+        assert_eq!(vec![Some(1.0), Some(51.0), Some(91.0), Some(101.0)], values);
+
+        let p = percentiles(once(&["1", "2"]), &[50.0]);
+        assert_eq!(Some(1.5), p.values[0].value);
+    }
+
+    #[test]
+    fn test_non_numeric_excluded() {
+        let p = percentiles(
+            once(&["1", "abc", "3", "", "NaN", "inf", "-infinity"]),
+            &[50.0],
+        );
+        assert_eq!(Some(2.0), p.values[0].value);
+        assert_eq!(5, p.non_numeric);
+    }
+
+    #[test]
+    fn test_counts() {
+        let p = percentiles([("1", 3), ("10", 1), ("x", 2)], &[0.0, 50.0, 100.0]);
+        let values: Vec<_> = p.values.iter().map(|v| v.value).collect();
+        assert_eq!(vec![Some(1.0), Some(1.0), Some(10.0)], values);
+        assert_eq!(2, p.non_numeric);
+    }
+}
@@ -1,97 +1,666 @@
 use fastrand::Rng;
-use std::cmp::{max, min};
-use std::collections::HashMap;
+use serde::de::{self, DeserializeOwned, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::{max, Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
 use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::iter::Sum;
+use std::mem;
+use std::ops::AddAssign;
 
+/// The A-Res key of one pool slot, ordered by `key` so that the smallest key in the pool can be
+/// found (and evicted) in constant time.
+#[derive(Debug, Clone, Copy)]
+struct SlotKey {
+    key: f64,
+    index: usize,
+}
+
+impl PartialEq for SlotKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SlotKey {}
+
+impl PartialOrd for SlotKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SlotKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .total_cmp(&other.key)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+/// A source of the uniform random numbers a `Reservoir` draws its keys and skips from. It's
+/// implemented for `fastrand::Rng`, which reservoirs use unless another source is given to
+/// `with_rng`, such as a deterministic generator that is the same on every platform.
+pub trait RandomSource: Clone {
+    /// A new source, for the reservoirs that aren't given one: those loaded from a saved state
+    /// and the result of merging no reservoirs.
+    fn new_source() -> Self;
+
+    /// A random number in `[0, n)`, for `n` above 0.
+    fn below(&mut self, n: u64) -> u64;
+
+    /// A random number in `[0, 1)`.
+    fn f32(&mut self) -> f32;
+
+    /// A random number in `[0, 1)`, which the keys are drawn from. By default it's made of 53
+    /// random bits from `below`, since an `f32` has too few to tell the keys of a large pool apart.
+    fn f64(&mut self) -> f64 {
+        const SCALE: u64 = 1 << f64::MANTISSA_DIGITS;
+        self.below(SCALE) as f64 / SCALE as f64
+    }
+}
+
+impl RandomSource for Rng {
+    fn new_source() -> Self {
+        Rng::new()
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.u64(..n)
+    }
+
+    fn f32(&mut self) -> f32 {
+        Rng::f32(self)
+    }
+
+    fn f64(&mut self) -> f64 {
+        Rng::f64(self)
+    }
+}
+
+/// A weighted reservoir sample, maintained with the A-Res algorithm of Efraimidis and Spirakis:
+/// every item is assigned the key `u^(1/weight)` for a uniform random `u`, and the pool keeps
+/// the items with the `capacity` largest keys.
+///
+/// Once the pool is full, items are added with the exponential jumps of A-ExpJ: rather than
+/// drawing a key for every item, the reservoir draws the total weight of the items to skip before
+/// the next one that enters the pool, so random numbers are only needed for replacements.
+///
+/// With `set_decay` the sample is biased towards recent items, by forward decay: each item's
+/// weight is multiplied by `e^(decay * i)` for the `i`th item added, which leaves the weights of
+/// the items relative to each other as if every add had aged the earlier ones by `e^-decay`.
 #[derive(Debug)]
-pub struct Reservoir<T> {
+pub struct Reservoir<T, G = Rng> {
     capacity: usize,
     pool: Vec<T>,
+    /// Min-heap over the keys of the items in `pool`.
+    keys: BinaryHeap<Reverse<SlotKey>>,
     pool_full: bool,
-    rng: Rng,
-    num_adds: u32,
+    /// Items with keys below this are never sampled. It is 0 unless the reservoir was merged from
+    /// full reservoirs of different capacities, which only held the items with keys above it.
+    threshold: f64,
+    /// The weight still to be skipped before the next item enters the full pool, drawn when it is
+    /// first needed.
+    skip_weight: Option<f64>,
+    rng: G,
+    num_adds: u64,
+    /// The rate at which the weights of earlier items decay with each add, 0 for a uniform sample.
+    decay: f64,
+    /// What the weight of the next item added is multiplied by, relative to the keys in the pool.
+    decay_scale: f64,
 }
 
+/// The largest `decay_scale` before the keys in the pool are rescaled. Keys of items with weights
+/// much larger than this would be too close to 1 to tell apart.
+const MAX_DECAY_SCALE: f64 = 1e6;
+
 impl<T> Reservoir<T> {
     pub fn new(capacity: usize) -> Reservoir<T> {
+        Reservoir::with_rng(capacity, Rng::new())
+    }
+
+    /// Creates a reservoir whose random number generator is seeded with `seed`, so that adding
+    /// the same items in the same order always produces the same sample.
+    pub fn with_seed(capacity: usize, seed: u64) -> Reservoir<T> {
+        Reservoir::with_rng(capacity, Rng::with_seed(seed))
+    }
+
+    /// Empties the reservoir like `clear`, and reseeds its random number generator with `seed`,
+    /// so that it samples the new stream exactly as a reservoir created `with_seed` would.
+    pub fn reset(&mut self, seed: u64) {
+        self.clear();
+        self.rng.seed(seed);
+    }
+}
+
+impl<T, G: RandomSource> Reservoir<T, G> {
+    /// Creates a reservoir with a capacity of 0 that has had nothing added, the identity for
+    /// merging: merging it with another reservoir, either way round, gives that reservoir's
+    /// sample, capacity and decay.
+    pub fn empty() -> Reservoir<T, G> {
+        Reservoir::with_rng(0, G::new_source())
+    }
+
+    /// A copy of this reservoir with each item in the pool replaced by `f` of it, keeping its key,
+    /// so that the copy samples the rest of the stream as this reservoir would.
+    pub(crate) fn map<U, F: FnMut(&T) -> U>(&self, f: F) -> Reservoir<U, G> {
+        Reservoir {
+            capacity: self.capacity,
+            pool: self.pool.iter().map(f).collect(),
+            keys: self.keys.clone(),
+            pool_full: self.pool_full,
+            threshold: self.threshold,
+            skip_weight: self.skip_weight,
+            rng: self.rng.clone(),
+            num_adds: self.num_adds,
+            decay: self.decay,
+            decay_scale: self.decay_scale,
+        }
+    }
+
+    /// Replaces each item in the pool with `f` of it, keeping its key.
+    pub(crate) fn map_in_place<F: FnMut(&mut T)>(&mut self, f: F) {
+        self.pool.iter_mut().for_each(f);
+    }
+
+    /// Creates a reservoir drawing its random numbers from `rng`.
+    pub fn with_rng(capacity: usize, rng: G) -> Reservoir<T, G> {
         Reservoir {
             capacity,
             pool: Vec::with_capacity(capacity),
+            keys: BinaryHeap::with_capacity(capacity),
             pool_full: false,
-            rng: Rng::new(),
+            threshold: 0.0,
+            skip_weight: None,
+            rng,
             num_adds: 0,
+            decay: 0.0,
+            decay_scale: 1.0,
         }
     }
 
+    /// Biases the sample towards recent items: from the next item added on, the weights of the
+    /// items added before it decay by a factor of `e^-decay` with every add. With a decay of 0,
+    /// the default, every item keeps its weight.
+    ///
+    /// Only the order of the adds matters, not when they happen. Reservoirs merged with different
+    /// decays keep the decay of the first with a capacity above 0, and their streams are treated
+    /// as ending together.
+    pub fn set_decay(&mut self, decay: f64) {
+        self.decay = decay;
+    }
+
+    /// The rate at which earlier items' weights decay, given to `set_decay`.
+    pub fn decay(&self) -> f64 {
+        self.decay
+    }
+
+    /// Adds an item with weight 1.0, so that every item added this way is equally likely to be
+    /// sampled.
     pub fn add(&mut self, item: T) {
+        self.add_weighted(item, 1.0);
+    }
+
+    /// Adds every item of `items` with weight 1.0, as if by calling `add` for each of them. Until
+    /// the pool is full, items are pushed straight into it, without the checks `add` makes for
+    /// each item.
+    pub fn add_all<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        let mut items = items.into_iter();
+        if self.threshold == 0.0 && self.decay == 0.0 {
+            let room = self.capacity - self.pool.len();
+            self.pool.reserve(room.min(items.size_hint().0));
+            while self.pool.len() < self.capacity {
+                let Some(item) = items.next() else {
+                    return;
+                };
+                self.num_adds += 1;
+                // The A-Res key of an item of weight 1.0 is just a uniform random number.
+                self.keys.push(Reverse(SlotKey {
+                    key: self.rng.f64(),
+                    index: self.pool.len(),
+                }));
+                self.pool.push(item);
+            }
+            self.pool_full = self.pool.len() == self.capacity;
+        }
+        for item in items {
+            self.add(item);
+        }
+    }
+
+    /// Adds an item that is `weight` times as likely to be sampled as an item of weight 1.0.
+    /// Items with a weight that is not positive are counted but never sampled, as is every item
+    /// added to a reservoir with a capacity of 0.
+    pub fn add_weighted(&mut self, item: T, weight: f64) {
+        self.add_weighted_with(weight, || item);
+    }
+
+    /// Like `add_weighted`, but the item is only built by calling `make_item` if it enters the
+    /// pool. Once the pool is full most items are passed over, so this saves building an owned
+    /// item, such as a `String`, for each of them.
+    pub fn add_weighted_with<F: FnOnce() -> T>(&mut self, weight: f64, make_item: F) {
         self.num_adds += 1;
+        let weight = self.decayed(weight);
+        if weight.is_nan() || weight <= 0.0 || self.capacity == 0 {
+            return;
+        }
         if !self.pool_full {
+            let key = self.rng.f64().powf(1.0 / weight);
+            self.insert(key, make_item());
+            return;
+        }
+        let Some(mut smallest) = self.keys.peek_mut() else {
+            return;
+        };
+        let min_key = smallest.0.key;
+        let rng = &mut self.rng;
+        // The weight of the items passed over before one has a key above `min_key` is
+        // exponentially distributed, so it can be drawn in advance.
+        let skip_weight = *self
+            .skip_weight
+            .get_or_insert_with(|| (1.0 - rng.f64()).ln() / min_key.ln());
+        if skip_weight > weight {
+            self.skip_weight = Some(skip_weight - weight);
+            return;
+        }
+        // This item enters the pool, with a key drawn from those above `min_key`.
+        let t = min_key.powf(weight);
+        smallest.0.key = (t + rng.f64() * (1.0 - t)).powf(1.0 / weight);
+        self.pool[smallest.0.index] = make_item();
+        self.skip_weight = None;
+    }
+
+    /// The weight of the item being added, scaled up to decay the items before it. Scaling the
+    /// weights of later items up is the same as scaling those of earlier items down, but keeps
+    /// the keys already in the pool.
+    fn decayed(&mut self, weight: f64) -> f64 {
+        if self.decay == 0.0 {
+            return weight;
+        }
+        if self.decay_scale > MAX_DECAY_SCALE {
+            self.rescale();
+        }
+        let weight = weight * self.decay_scale;
+        self.decay_scale *= self.decay.exp();
+        weight
+    }
+
+    /// Divides the weights of every item, past and future, by `decay_scale`, which doesn't change
+    /// which items are sampled. A key of `u^(1/w)` becomes `u^(decay_scale/w)`.
+    fn rescale(&mut self) {
+        let scale = self.decay_scale;
+        let keys = mem::take(&mut self.keys).into_vec();
+        self.keys = keys
+            .into_iter()
+            .map(|Reverse(slot)| {
+                Reverse(SlotKey {
+                    key: slot.key.powf(scale),
+                    index: slot.index,
+                })
+            })
+            .collect();
+        self.threshold = self.threshold.powf(scale);
+        self.skip_weight = self.skip_weight.map(|skip_weight| skip_weight / scale);
+        self.decay_scale = 1.0;
+    }
+
+    /// The number of items in the pool, which is at most `capacity`.
+    pub fn len(&self) -> usize {
+        self.pool.len()
+    }
+
+    /// Whether the pool is empty, as it is until an item with a positive weight is added.
+    pub fn is_empty(&self) -> bool {
+        self.pool.is_empty()
+    }
+
+    /// The most items the pool can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of items added, including those that weren't sampled.
+    pub fn num_adds(&self) -> u64 {
+        self.num_adds
+    }
+
+    /// The smallest key of an item this reservoir would have kept: every item of its stream with a
+    /// larger key is in the pool.
+    /// Like the keys of `keys_in_pool_order`, it's rescaled so that the next item would have
+    /// its own weight.
+    fn known_keys_threshold(&self) -> f64 {
+        let threshold = match self.keys.peek() {
+            Some(Reverse(smallest)) if self.pool_full => smallest.key,
+            _ => self.threshold,
+        };
+        threshold.powf(self.decay_scale)
+    }
+
+    /// Empties the reservoir so that it can sample a new stream, keeping its capacity and the
+    /// allocation of its pool. The random number generator carries on from where it was.
+    pub fn clear(&mut self) {
+        self.pool.clear();
+        self.keys.clear();
+        self.pool_full = false;
+        self.threshold = 0.0;
+        self.skip_weight = None;
+        self.num_adds = 0;
+        self.decay_scale = 1.0;
+    }
+
+    /// Iterates over the items in the pool, in no particular order.
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.pool.iter()
+    }
+
+    /// Places `item` in the pool if there is room, or if `key` is larger than the smallest key
+    /// currently in the pool. Items with keys below the threshold are dropped.
+    fn insert(&mut self, key: f64, item: T) {
+        if key < self.threshold {
+            return;
+        }
+        if !self.pool_full {
+            self.keys.push(Reverse(SlotKey {
+                key,
+                index: self.pool.len(),
+            }));
             self.pool.push(item);
-            if self.pool.len() == self.capacity {
+            if self.len() == self.capacity() {
                 self.pool_full = true;
             }
-        } else {
-            let j = self.rng.u32(0..self.num_adds);
-            if j < self.capacity as u32 {
-                self.pool[j as usize] = item;
+        } else if let Some(mut smallest) = self.keys.peek_mut() {
+            if key > smallest.0.key {
+                smallest.0.key = key;
+                self.pool[smallest.0.index] = item;
             }
         }
     }
 }
 
-impl<T: Clone> Reservoir<T> {
-    pub fn merge(r1: &Reservoir<T>, r2: &Reservoir<T>) -> Reservoir<T> {
-        let r1_threshold = r1.num_adds as f32 / (r1.num_adds + r2.num_adds) as f32;
-        let r2_threshold = r2.num_adds as f32 / (r1.num_adds + r2.num_adds) as f32;
-        let pool_capacity = max(r1.capacity, r2.capacity);
-        let mut pool: Vec<T> = Vec::with_capacity(pool_capacity);
-        let mut rng = Rng::new();
-        for r1_item in &r1.pool {
-            if rng.f32() < r1_threshold {
-                if pool.len() < pool_capacity {
-                    pool.push(r1_item.clone());
-                } else {
-                    let index_to_evict = rng.u32(0..pool_capacity as u32);
-                    pool[index_to_evict as usize] = r1_item.clone();
-                }
+impl<T, G: RandomSource> Reservoir<T, G> {
+    /// Merges the keyed items of another reservoir into this one in place, keeping the same
+    /// items as `merge_many` would. The pool is only rebuilt when the other reservoir raises the
+    /// threshold above keys already in it, otherwise its allocation is reused.
+    fn merge_in<I: IntoIterator<Item = (f64, T)>>(&mut self, other: MergedFrom, slots: I) {
+        if self.decay_scale != 1.0 {
+            self.rescale();
+        }
+        if self.capacity == 0 {
+            // Nothing was ever sampled into this reservoir, so the other decides the decay.
+            self.decay = other.decay;
+        }
+        let threshold = self.known_keys_threshold().max(other.known_keys_threshold);
+        self.capacity = max(self.capacity, other.capacity);
+        self.num_adds += other.num_adds;
+        self.threshold = threshold;
+        self.skip_weight = None;
+        if self.keys.iter().any(|Reverse(slot)| slot.key < threshold) {
+            let mut keys = vec![0.0; self.pool.len()];
+            for Reverse(slot) in self.keys.drain() {
+                keys[slot.index] = slot.key;
+            }
+            let items: Vec<T> = self.pool.drain(..).collect();
+            self.pool_full = false;
+            for (key, item) in keys.into_iter().zip(items) {
+                self.insert(key, item);
             }
         }
-        for r2_item in &r2.pool {
-            if rng.f32() < r2_threshold {
-                if pool.len() < pool_capacity {
-                    pool.push(r2_item.clone());
-                } else {
-                    let index_to_evict = rng.u32(0..pool_capacity as u32);
-                    pool[index_to_evict as usize] = r2_item.clone();
-                }
+        self.pool_full = self.pool.len() == self.capacity;
+        for (key, item) in slots {
+            self.insert(key, item);
+        }
+    }
+
+    /// The key of each item in the pool, in the order of the pool. With a decay, the keys are
+    /// rescaled so that the next item would have its own weight, as after `rescale`.
+    fn keys_in_pool_order(&self) -> Vec<f64> {
+        let mut keys = vec![0.0; self.pool.len()];
+        for Reverse(slot) in &self.keys {
+            keys[slot.index] = slot.key.powf(self.decay_scale);
+        }
+        keys
+    }
+
+    /// What `merge_in` needs to know of the reservoir merged in, besides its items.
+    fn merged_from(&self) -> MergedFrom {
+        MergedFrom {
+            capacity: self.capacity,
+            num_adds: self.num_adds,
+            known_keys_threshold: self.known_keys_threshold(),
+            decay: self.decay,
+        }
+    }
+}
+
+/// The capacity, number of adds, threshold and decay of a reservoir being merged into another.
+struct MergedFrom {
+    capacity: usize,
+    num_adds: u64,
+    known_keys_threshold: f64,
+    decay: f64,
+}
+
+/// Merges another reservoir into this one, moving its items rather than cloning them.
+impl<T, G: RandomSource> AddAssign for Reservoir<T, G> {
+    fn add_assign(&mut self, other: Reservoir<T, G>) {
+        let keys = other.keys_in_pool_order();
+        self.merge_in(other.merged_from(), keys.into_iter().zip(other.pool));
+    }
+}
+
+/// Merges a copy of the items of another reservoir into this one.
+impl<T: Clone, G: RandomSource> AddAssign<&Reservoir<T, G>> for Reservoir<T, G> {
+    fn add_assign(&mut self, other: &Reservoir<T, G>) {
+        let keys = other.keys_in_pool_order();
+        self.merge_in(
+            other.merged_from(),
+            keys.into_iter().zip(other.pool.iter().cloned()),
+        );
+    }
+}
+
+/// Merges two reservoirs with `a + b`, reusing the pool of `a` like `+=`. The trait isn't imported
+/// here, since its by-value `add` would be found before `Reservoir::add` for an owned reservoir.
+impl<T, G: RandomSource> std::ops::Add for Reservoir<T, G> {
+    type Output = Reservoir<T, G>;
+
+    fn add(mut self, other: Reservoir<T, G>) -> Reservoir<T, G> {
+        self += other;
+        self
+    }
+}
+
+/// Merges every reservoir into the first, reusing its pool. Summing no reservoirs gives
+/// `Reservoir::empty`, like `merge_many`.
+impl<T, G: RandomSource> Sum for Reservoir<T, G> {
+    fn sum<I: Iterator<Item = Reservoir<T, G>>>(mut reservoirs: I) -> Reservoir<T, G> {
+        let mut sum = reservoirs.next().unwrap_or_else(Reservoir::empty);
+        for reservoir in reservoirs {
+            sum += reservoir;
+        }
+        sum
+    }
+}
+
+impl<T, G: RandomSource> Extend<T> for Reservoir<T, G> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        self.add_all(items);
+    }
+}
+
+impl<T: Clone, G: RandomSource> Reservoir<T, G> {
+    /// Combines two reservoirs by keeping the items with the largest keys across both pools,
+    /// which is the sample A-Res would have produced had it seen both streams.
+    pub fn merge(r1: &Reservoir<T, G>, r2: &Reservoir<T, G>) -> Reservoir<T, G> {
+        Reservoir::merge_many([r1, r2])
+    }
+
+    /// Combines any number of reservoirs in a single pass, keeping the items with the largest
+    /// keys across all of their pools. The result has the largest capacity of the inputs, or 0 if
+    /// there are none.
+    ///
+    /// A full reservoir only knows the items of its stream with keys at least as large as its
+    /// smallest key, so only items with keys above the largest of those minimums are kept. When
+    /// the capacities differ, this may leave the merged pool short of its capacity; it is topped up
+    /// by later items with keys above that threshold.
+    pub fn merge_many<'a, I>(reservoirs: I) -> Reservoir<T, G>
+    where
+        I: IntoIterator<Item = &'a Reservoir<T, G>>,
+        T: 'a,
+        G: 'a,
+    {
+        let mut pool_capacity = 0;
+        let mut num_adds = 0;
+        let mut threshold: f64 = 0.0;
+        let mut rng = None;
+        let mut decay = None;
+        let mut slots: Vec<(f64, &T)> = Vec::new();
+        for r in reservoirs {
+            pool_capacity = max(pool_capacity, r.capacity);
+            num_adds += r.num_adds;
+            threshold = threshold.max(r.known_keys_threshold());
+            // An empty reservoir, with no capacity, has no say in the decay or the random numbers.
+            if r.capacity > 0 {
+                rng.get_or_insert_with(|| r.rng.clone());
+                decay.get_or_insert(r.decay);
             }
+            slots.extend(
+                r.keys
+                    .iter()
+                    .map(|Reverse(slot)| (slot.key.powf(r.decay_scale), &r.pool[slot.index])),
+            );
         }
-        Reservoir {
-            capacity: pool_capacity,
-            pool_full: pool.len() == pool_capacity,
-            pool,
-            rng,
-            num_adds: r1.num_adds + r2.num_adds,
+        slots.retain(|(key, _)| *key >= threshold);
+        if slots.len() > pool_capacity {
+            slots.select_nth_unstable_by(pool_capacity, |(k1, _), (k2, _)| k2.total_cmp(k1));
+            slots.truncate(pool_capacity);
         }
+        let mut merged = Reservoir::with_rng(pool_capacity, rng.unwrap_or_else(G::new_source));
+        merged.threshold = threshold;
+        merged.decay = decay.unwrap_or(0.0);
+        for (key, item) in slots {
+            merged.insert(key, item.clone());
+        }
+        merged.num_adds = num_adds;
+        merged
     }
 }
 
-impl<T: Eq + Hash> Reservoir<T> {
-    pub fn to_histogram(&self) -> HashMap<&T, f32> {
-        let mut counts: HashMap<&T, i32> = HashMap::new();
+/// The saved form of a `Reservoir`. The random number generator isn't saved, a loaded reservoir
+/// gets a freshly seeded one.
+#[derive(Serialize, Deserialize)]
+struct ReservoirState<P> {
+    capacity: usize,
+    pool: P,
+    /// The A-Res key of each item in `pool`, needed to merge the loaded reservoir correctly.
+    keys: Vec<f64>,
+    /// Saved for older versions, a loaded reservoir works it out from `capacity` and `pool`.
+    pool_full: bool,
+    #[serde(default)]
+    threshold: f64,
+    num_adds: u64,
+    #[serde(default)]
+    decay: f64,
+}
+
+impl<T: Serialize, G: RandomSource> Serialize for Reservoir<T, G> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ReservoirState {
+            capacity: self.capacity,
+            pool: &self.pool,
+            keys: self.keys_in_pool_order(),
+            pool_full: self.pool_full,
+            threshold: self.threshold.powf(self.decay_scale),
+            num_adds: self.num_adds,
+            decay: self.decay,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>, G: RandomSource> Deserialize<'de> for Reservoir<T, G> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state: ReservoirState<Vec<T>> = ReservoirState::deserialize(deserializer)?;
+        if state.keys.len() != state.pool.len() {
+            return Err(de::Error::custom(
+                "reservoir has a different number of keys and items",
+            ));
+        }
+        if state.pool.len() > state.capacity {
+            return Err(de::Error::custom(
+                "reservoir holds more items than its capacity",
+            ));
+        }
+        // Keys are `u^(1/weight)` for `u` in [0, 1), and the threshold is one of them or 0.
+        let is_key = |key: &f64| (0.0..=1.0).contains(key);
+        if !state.keys.iter().all(is_key) || !is_key(&state.threshold) {
+            return Err(de::Error::custom(
+                "reservoir has a key or threshold outside of 0 to 1",
+            ));
+        }
+        Ok(Reservoir {
+            capacity: state.capacity,
+            keys: state
+                .keys
+                .iter()
+                .enumerate()
+                .map(|(index, &key)| Reverse(SlotKey { key, index }))
+                .collect(),
+            pool_full: state.pool.len() == state.capacity,
+            pool: state.pool,
+            threshold: state.threshold,
+            skip_weight: None,
+            rng: G::new_source(),
+            num_adds: state.num_adds,
+            decay: state.decay,
+            decay_scale: 1.0,
+        })
+    }
+}
+
+impl<T: Serialize, G: RandomSource> Reservoir<T, G> {
+    /// Writes the reservoir to `w` as a single line of JSON.
+    pub fn save<W: Write>(&self, mut w: W) -> io::Result<()> {
+        serde_json::to_writer(&mut w, self)?;
+        writeln!(w)
+    }
+}
+
+impl<T: DeserializeOwned, G: RandomSource> Reservoir<T, G> {
+    /// Reads a reservoir written by `save` from `r`. Only the reservoir itself is consumed, so
+    /// several reservoirs saved one after another can be loaded back in turn.
+    pub fn load<R: Read>(r: R) -> io::Result<Reservoir<T, G>> {
+        match serde_json::Deserializer::from_reader(r).into_iter().next() {
+            Some(reservoir) => Ok(reservoir?),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "no reservoir to load",
+            )),
+        }
+    }
+}
+
+impl<T: Eq + Hash, G: RandomSource> Reservoir<T, G> {
+    /// The number of times each distinct item is in the pool, and the number of items in the
+    /// pool, which the counts are a share of.
+    pub fn to_counts(&self) -> (HashMap<&T, u64>, u64) {
+        let mut counts: HashMap<&T, u64> = HashMap::new();
         for item in &self.pool {
             let count = counts.entry(item).or_insert(0);
             *count += 1;
         }
-        if self.capacity == 0 {
-            HashMap::new()
-        } else {
-            let effective_size = min(self.pool.len() as u32, self.num_adds) as f32;
-            counts
-                .iter()
-                .map(|(k, v)| (*k, *v as f32 / effective_size))
-                .collect()
-        }
+        (counts, self.len() as u64)
+    }
+
+    /// The frequency of each distinct item in the pool. Frequencies are relative to the number of
+    /// items in the pool, so they sum to 1 even when a merge left the pool short of capacity.
+    pub fn to_histogram(&self) -> HashMap<&T, f32> {
+        let (counts, pool_size) = self.to_counts();
+        counts
+            .into_iter()
+            .map(|(k, v)| (k, v as f32 / pool_size as f32))
+            .collect()
     }
 }
 
@@ -105,6 +674,20 @@ mod tests {
         assert_eq!(HashMap::new(), r.to_histogram());
     }
 
+    #[test]
+    fn test_zero_capacity() {
+        let mut r: Reservoir<&str> = Reservoir::new(0);
+        r.add("hello");
+        r.add_weighted("world", 2.0);
+        r.add_all(["a", "b"]);
+        assert!(r.is_empty());
+        assert_eq!(4, r.num_adds());
+        assert_eq!(HashMap::new(), r.to_histogram());
+        r.clear();
+        r.add("hello");
+        assert!(r.is_empty());
+    }
+
     #[test]
     fn test_single_entry() {
         let mut r: Reservoir<&str> = Reservoir::new(15);
@@ -127,7 +710,7 @@ mod tests {
 
     #[test]
     fn test_many_entries_2_types() {
-        let mut r = Reservoir::new(100);
+        let mut r = Reservoir::with_seed(100, 1);
         for _ in 0..5000 {
             r.add("hello");
         }
@@ -143,8 +726,8 @@ mod tests {
 
     #[test]
     fn test_merge() {
-        let mut r1 = Reservoir::new(1000);
-        let mut r2 = Reservoir::new(1000);
+        let mut r1 = Reservoir::with_seed(1000, 1);
+        let mut r2 = Reservoir::with_seed(1000, 2);
         for _ in 0..1000 {
             r1.add("hello");
             r2.add("world");
@@ -161,4 +744,462 @@ mod tests {
         );
         assert!((hello_freq - world_freq).abs() < 0.1f32);
     }
+
+    #[test]
+    fn test_merge_with_empty() {
+        let mut r = Reservoir::with_seed(100, 1);
+        r.set_decay(0.001);
+        for i in 0..1000 {
+            r.add(i % 10);
+        }
+        let sorted = |r: &Reservoir<i32>| {
+            let mut pool: Vec<i32> = r.iter().copied().collect();
+            pool.sort();
+            pool
+        };
+        let mut summed = Reservoir::empty();
+        summed += &r;
+        for merged in [
+            Reservoir::merge(&Reservoir::empty(), &r),
+            Reservoir::merge(&r, &Reservoir::empty()),
+            summed,
+        ] {
+            assert_eq!(sorted(&r), sorted(&merged));
+            assert_eq!(r.capacity(), merged.capacity());
+            assert_eq!(r.num_adds(), merged.num_adds());
+            assert_eq!(r.decay(), merged.decay());
+            assert_eq!(r.known_keys_threshold(), merged.known_keys_threshold());
+        }
+        let empty: Reservoir<i32> = Reservoir::empty();
+        let merged = Reservoir::merge(&empty, &Reservoir::empty());
+        assert_eq!(0, merged.capacity());
+        assert!(merged.is_empty());
+    }
+
+    #[test]
+    fn test_lopsided_merge_frequencies_sum_to_one() {
+        let mut r1 = Reservoir::with_seed(100, 1);
+        let mut r2 = Reservoir::with_seed(100, 2);
+        for i in 0..100_000 {
+            r1.add(i % 7);
+        }
+        for i in 0..50 {
+            r2.add(i);
+        }
+        let r3 = Reservoir::merge(&r1, &r2);
+        assert!(r3.len() <= 100);
+        assert!(r3.num_adds > r3.len() as u64);
+        let sum: f32 = r3.to_histogram().values().sum();
+        assert!((1.0 - sum).abs() < 1e-5, "sum == {sum}");
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut r = Reservoir::with_seed(10, 1);
+        for i in 0..100 {
+            r.add(i);
+        }
+        let pool_capacity = r.pool.capacity();
+        r.clear();
+        assert_eq!(0, r.len());
+        assert_eq!(0, r.num_adds());
+        assert_eq!(HashMap::new(), r.to_histogram());
+        assert_eq!(10, r.capacity());
+        assert_eq!(pool_capacity, r.pool.capacity());
+        for i in 0..5 {
+            r.add(i);
+        }
+        assert_eq!(5, r.len());
+    }
+
+    #[test]
+    fn test_reset_matches_new_reservoir() {
+        let mut reused = Reservoir::with_seed(10, 1);
+        for i in 0..100 {
+            reused.add(i);
+        }
+        reused.reset(7);
+        let mut fresh = Reservoir::with_seed(10, 7);
+        for i in 0..1000 {
+            reused.add(i);
+            fresh.add(i);
+        }
+        assert_eq!(fresh.pool, reused.pool);
+    }
+
+    #[test]
+    fn test_add_all_matches_add() {
+        for (already_added, batch) in [(0, 5), (0, 10), (0, 25), (4, 3), (4, 20), (12, 30)] {
+            let mut one_by_one = Reservoir::with_seed(10, 3);
+            let mut batched = Reservoir::with_seed(10, 3);
+            for i in 0..already_added {
+                one_by_one.add(i);
+                batched.add(i);
+            }
+            for i in already_added..already_added + batch {
+                one_by_one.add(i);
+            }
+            batched.add_all(already_added..already_added + batch);
+            assert_eq!(one_by_one.pool, batched.pool);
+            assert_eq!(one_by_one.num_adds(), batched.num_adds());
+            assert_eq!(one_by_one.pool_full, batched.pool_full);
+        }
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut r = Reservoir::with_seed(10, 3);
+        r.extend(["a", "b"]);
+        r.extend(vec!["c"; 20]);
+        assert_eq!(22, r.num_adds());
+        assert_eq!(10, r.len());
+        assert!(r.to_histogram()[&"c"] >= 0.8);
+    }
+
+    #[test]
+    fn test_weighted() {
+        let mut r = Reservoir::with_seed(100, 42);
+        for _ in 0..5000 {
+            r.add_weighted("heavy", 9.0);
+            r.add_weighted("light", 1.0);
+        }
+        let h = r.to_histogram();
+        let heavy_freq = h.get(&"heavy").unwrap();
+        assert!(*heavy_freq > 0.8f32, "heavy_freq == {heavy_freq}");
+    }
+
+    #[test]
+    fn test_weighted_zero_weight_never_sampled() {
+        let mut r = Reservoir::new(10);
+        for _ in 0..100 {
+            r.add_weighted("ignored", 0.0);
+        }
+        r.add("kept");
+        let mut v = HashMap::new();
+        v.insert(&"kept", 1.0f32);
+        assert_eq!(v, r.to_histogram());
+    }
+
+    #[test]
+    fn test_merge_weighted() {
+        let mut r1 = Reservoir::with_seed(1000, 1);
+        let mut r2 = Reservoir::with_seed(1000, 2);
+        for _ in 0..5000 {
+            r1.add_weighted("heavy", 9.0);
+            r2.add_weighted("light", 1.0);
+        }
+        let r3 = Reservoir::merge(&r1, &r2);
+        assert_eq!(1000, r3.pool.len());
+        let h = r3.to_histogram();
+        let heavy_freq = h.get(&"heavy").unwrap();
+        assert!(*heavy_freq > 0.8f32, "heavy_freq == {heavy_freq}");
+    }
+
+    #[test]
+    fn test_same_seed_same_sample() {
+        let mut r1 = Reservoir::with_seed(10, 7);
+        let mut r2 = Reservoir::with_seed(10, 7);
+        for i in 0..1000 {
+            r1.add(i);
+            r2.add(i);
+        }
+        assert_eq!(r1.pool, r2.pool);
+    }
+
+    /// A SplitMix64 generator, standing in for a source other than `fastrand`.
+    #[derive(Clone)]
+    struct SplitMix(u64);
+
+    impl RandomSource for SplitMix {
+        fn new_source() -> Self {
+            SplitMix(0)
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            (z ^ (z >> 31)) % n
+        }
+
+        fn f32(&mut self) -> f32 {
+            self.below(1 << f32::MANTISSA_DIGITS) as f32 / (1 << f32::MANTISSA_DIGITS) as f32
+        }
+    }
+
+    #[test]
+    fn test_custom_random_source() {
+        let mut r1 = Reservoir::with_rng(10, SplitMix(7));
+        let mut r2 = Reservoir::with_rng(10, SplitMix(7));
+        for i in 0..1000 {
+            r1.add(i);
+            r2.add(i);
+        }
+        assert_eq!(r1.pool, r2.pool);
+        assert_eq!(10, r1.len());
+        let mut source = SplitMix(7);
+        assert!((0..1000).all(|_| source.below(10) < 10));
+        assert!((0..1000).all(|_| (0.0..1.0).contains(&source.f64())));
+        let merged = Reservoir::merge(&r1, &r2);
+        assert_eq!(2000, merged.num_adds());
+        let json = serde_json::to_string(&merged).unwrap();
+        let loaded: Reservoir<i32, SplitMix> = serde_json::from_str(&json).unwrap();
+        assert_eq!(merged.to_histogram(), loaded.to_histogram());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut r = Reservoir::with_seed(10, 3);
+        for i in 0..100 {
+            r.add(i.to_string());
+        }
+        let mut saved = Vec::new();
+        r.save(&mut saved).unwrap();
+        r.save(&mut saved).unwrap();
+        let mut src = saved.as_slice();
+        for _ in 0..2 {
+            let loaded: Reservoir<String> = Reservoir::load(&mut src).unwrap();
+            assert_eq!(r.capacity, loaded.capacity);
+            assert_eq!(r.pool, loaded.pool);
+            assert_eq!(r.pool_full, loaded.pool_full);
+            assert_eq!(r.num_adds, loaded.num_adds);
+            assert_eq!(
+                r.keys.clone().into_sorted_vec(),
+                loaded.keys.clone().into_sorted_vec()
+            );
+        }
+        assert!(Reservoir::<String>::load(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_load_rejects_inconsistent_state() {
+        let saved =
+            r#"{"capacity":1,"pool":["a","b"],"keys":[0.5,0.6],"pool_full":true,"num_adds":2}"#;
+        assert!(Reservoir::<String>::load(saved.as_bytes()).is_err());
+        for keys in ["[0.5,1.5]", "[0.5,-0.1]"] {
+            let saved = format!(
+                r#"{{"capacity":2,"pool":["a","b"],"keys":{keys},"pool_full":true,"num_adds":2}}"#
+            );
+            assert!(Reservoir::<String>::load(saved.as_bytes()).is_err());
+        }
+        let saved = r#"{"capacity":2,"pool":["a","b"],"keys":[0.5,0.6],"pool_full":true,
+            "threshold":2.0,"num_adds":2}"#;
+        assert!(Reservoir::<String>::load(saved.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_load_ignores_saved_pool_full() {
+        let saved = r#"{"capacity":2,"pool":["a"],"keys":[0.5],"pool_full":true,"num_adds":1}"#;
+        let mut loaded = Reservoir::<String>::load(saved.as_bytes()).unwrap();
+        assert!(!loaded.pool_full);
+        loaded.add("b".to_string());
+        assert_eq!(2, loaded.len());
+        let saved = r#"{"capacity":1,"pool":["a"],"keys":[0.5],"pool_full":false,"num_adds":1}"#;
+        let loaded = Reservoir::<String>::load(saved.as_bytes()).unwrap();
+        assert!(loaded.pool_full);
+    }
+
+    #[test]
+    fn test_num_adds_past_u32_max() {
+        // Pretend both reservoirs have already seen more items than fit in a u32.
+        let mut r1 = Reservoir::with_seed(10, 1);
+        let mut r2 = Reservoir::with_seed(10, 2);
+        r1.num_adds = u32::MAX as u64;
+        r2.num_adds = u32::MAX as u64;
+        for _ in 0..100 {
+            r1.add("hello");
+            r2.add("world");
+        }
+        assert_eq!(u32::MAX as u64 + 100, r1.num_adds);
+        let r3 = Reservoir::merge(&r1, &r2);
+        assert_eq!(2 * (u32::MAX as u64 + 100), r3.num_adds);
+        assert_eq!(10, r3.pool.len());
+        let h = r3.to_histogram();
+        let total: f32 = h.values().sum();
+        assert!((1.0f32 - total).abs() < 0.001f32, "total == {total}");
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut r = Reservoir::with_seed(3, 1);
+        assert!(r.is_empty());
+        assert_eq!(3, r.capacity());
+        for i in 0..5 {
+            r.add(i);
+        }
+        assert!(!r.is_empty());
+        assert_eq!(3, r.len());
+        assert_eq!(5, r.num_adds());
+        assert_eq!(3, r.iter().count());
+        assert!(r.iter().all(|i| (0..5).contains(i)));
+    }
+
+    #[test]
+    fn test_merge_many() {
+        let reservoirs: Vec<Reservoir<usize>> = (0..4)
+            .map(|i| {
+                let mut r = Reservoir::with_seed(1000, i as u64);
+                for _ in 0..1000 * (i + 1) {
+                    r.add(i);
+                }
+                r
+            })
+            .collect();
+        let merged = Reservoir::merge_many(&reservoirs);
+        assert_eq!(1000, merged.len());
+        assert_eq!(10000, merged.num_adds());
+        let h = merged.to_histogram();
+        for i in 0..4 {
+            let freq = h.get(&i).copied().unwrap_or(0.0);
+            let expected = (i + 1) as f32 / 10.0;
+            assert!((freq - expected).abs() < 0.05, "freq of {i} == {freq}");
+        }
+    }
+
+    #[test]
+    fn test_merge_many_empty() {
+        let merged = Reservoir::<u32>::merge_many([]);
+        assert_eq!(0, merged.capacity());
+        assert_eq!(0, merged.num_adds());
+    }
+
+    #[test]
+    fn test_merge_different_capacities() {
+        let mut r1 = Reservoir::with_seed(100, 1);
+        let mut r2 = Reservoir::with_seed(1000, 2);
+        for _ in 0..10000 {
+            r1.add("small");
+            r2.add("large");
+        }
+        let r3 = Reservoir::merge(&r1, &r2);
+        let h = r3.to_histogram();
+        let small_freq = h.get(&"small").unwrap();
+        assert!(
+            (small_freq - 0.5f32).abs() < 0.1f32,
+            "small_freq == {small_freq}"
+        );
+
+        // Later items fill the rest of the pool without undoing the balance.
+        let mut r3 = Reservoir::merge(&r2, &r1);
+        for _ in 0..20000 {
+            r3.add("later");
+        }
+        let h = r3.to_histogram();
+        let later_freq = h.get(&"later").unwrap();
+        assert!(
+            (later_freq - 0.5f32).abs() < 0.1f32,
+            "later_freq == {later_freq}"
+        );
+    }
+
+    #[test]
+    fn test_add_assign_and_sum_match_merge() {
+        for (capacity1, capacity2) in [(10, 10), (5, 10), (10, 5)] {
+            let mut r1 = Reservoir::with_seed(capacity1, 1);
+            let mut r2 = Reservoir::with_seed(capacity2, 2);
+            for i in 0..1000 {
+                r1.add(i);
+                r2.add(-i);
+            }
+            let sorted = |r: &Reservoir<i32>| {
+                let mut pool = r.pool.clone();
+                pool.sort();
+                pool
+            };
+            let merged = Reservoir::merge(&r1, &r2);
+            let mut sum = Reservoir::with_seed(0, 3);
+            sum += &r1;
+            sum += &r2;
+            assert_eq!(sorted(&merged), sorted(&sum));
+            assert_eq!(2000, sum.num_adds());
+            assert_eq!(merged.capacity(), sum.capacity());
+            let sum: Reservoir<i32> = [r1.map(|&i| i), r2.map(|&i| i)].into_iter().sum();
+            assert_eq!(sorted(&merged), sorted(&sum));
+            let sum = r1 + r2;
+            assert_eq!(sorted(&merged), sorted(&sum));
+            assert_eq!(2000, sum.num_adds());
+        }
+    }
+
+    #[test]
+    fn test_add_weighted_with_builds_only_sampled_items() {
+        let mut r = Reservoir::with_seed(10, 3);
+        let mut built = 0;
+        for i in 0..10_000 {
+            r.add_weighted_with(1.0, || {
+                built += 1;
+                i
+            });
+        }
+        assert_eq!(10, r.len());
+        assert_eq!(10_000, r.num_adds());
+        // About 10 * ln(10_000 / 10) ≈ 69 replacements are expected after the first 10 items.
+        assert!(built < 200, "built {built} items");
+    }
+
+    #[test]
+    fn test_uniform_after_pool_fills() {
+        let mut r = Reservoir::with_seed(1000, 3);
+        for i in 0..100000u32 {
+            r.add(i);
+        }
+        assert_eq!(1000, r.len());
+        let mean = r.iter().map(|&i| i as f64).sum::<f64>() / r.len() as f64;
+        assert!((mean - 50000.0).abs() < 3000.0, "mean == {mean}");
+        let late = r.iter().filter(|&&i| i >= 90000).count();
+        assert!((late as i32 - 100).abs() < 30, "late == {late}");
+    }
+
+    #[test]
+    fn test_decay_favours_recent_items() {
+        let mut r = Reservoir::with_seed(100, 3);
+        r.set_decay(0.0001);
+        // Enough adds for the keys to be rescaled several times.
+        for i in 0..100000u32 {
+            r.add(i);
+        }
+        assert_eq!(100, r.len());
+        // The age of a sampled item is about exponentially distributed, with a mean of 1 / decay.
+        let mean_age = r.iter().map(|&i| (99999 - i) as f64).sum::<f64>() / r.len() as f64;
+        assert!(
+            (mean_age - 10000.0).abs() < 3000.0,
+            "mean age == {mean_age}"
+        );
+        // About 1 - 1/e of them are from the last tenth of the stream, rather than a tenth.
+        let late = r.iter().filter(|&&i| i >= 90000).count();
+        assert!((late as i32 - 63).abs() < 15, "late == {late}");
+    }
+
+    #[test]
+    fn test_decayed_merge_and_save() {
+        let mut r1 = Reservoir::with_seed(100, 1);
+        let mut r2 = Reservoir::with_seed(100, 2);
+        r1.set_decay(0.01);
+        r2.set_decay(0.01);
+        for i in 0..10000u32 {
+            r1.add(i);
+        }
+        for i in 0..20000u32 {
+            r2.add(i + 100000);
+        }
+        // The streams are merged as if they ended together, so both ends are sampled about
+        // equally even though the second stream is longer.
+        let merged = Reservoir::merge(&r1, &r2);
+        assert_eq!(0.01, merged.decay());
+        let from_r1 = merged.iter().filter(|&&i| i < 100000).count();
+        assert!((from_r1 as i32 - 50).abs() < 20, "from_r1 == {from_r1}");
+        assert!(merged
+            .iter()
+            .all(|&i| (9000..10000).contains(&i) || i >= 119000));
+
+        let mut saved = Vec::new();
+        merged.save(&mut saved).unwrap();
+        let mut loaded: Reservoir<u32> = Reservoir::load(saved.as_slice()).unwrap();
+        assert_eq!(0.01, loaded.decay());
+        for i in 0..1000u32 {
+            loaded.add(i + 200000);
+        }
+        assert!(loaded.iter().all(|&i| i >= 200000));
+    }
 }
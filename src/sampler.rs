@@ -0,0 +1,145 @@
+use crate::sampling::{is_comment, SampleOptions, SampledFields};
+
+/// Samples records pushed to it one at a time, for embedding the sampling in a program rather
+/// than reading an input through `process_reader`. Records are split into fields and sampled as
+/// `process_reader` would, and the top-k values of any field can be queried at any point without
+/// finishing the sample.
+#[derive(Debug)]
+pub struct Sampler {
+    options: SampleOptions,
+    sampled: SampledFields,
+}
+
+impl Sampler {
+    /// Creates a sampler with an empty accumulator per field selected by `options`, seeded from
+    /// `seed` if it's given to make the sample reproducible.
+    pub fn new(options: SampleOptions, seed: Option<u64>) -> Sampler {
+        Sampler {
+            sampled: SampledFields::new(seed, &options),
+            options,
+        }
+    }
+
+    /// Samples a record, without its separator or line ending, splitting it into fields as
+    /// `process_reader` does. Comments and, with `skip_empty`, empty records are counted as
+    /// skipped. With `csv_input`, records aren't parsed as CSV; use `push_fields` instead.
+    pub fn push(&mut self, record: &str) {
+        let sampled = &mut self.sampled;
+        sampled.total_records += 1;
+        sampled.total_bytes += record.len() as u64;
+        if is_comment(record.as_bytes(), &self.options) {
+            sampled.skipped_line_count += 1;
+        } else if self.options.skip_empty && record.trim().is_empty() {
+            sampled.empty_line_count += 1;
+        } else {
+            sampled.add_record(record, &self.options);
+        }
+    }
+
+    /// Samples a record that has already been split into fields. The whole record, when it's
+    /// sampled, is the fields joined by the field separator, or by a space if there isn't one, and
+    /// its length is counted in `total_bytes` as `push` counts a record's.
+    pub fn push_fields(&mut self, fields: &[&str]) {
+        let delimiter = self.options.field_separator.as_deref().unwrap_or(" ");
+        let sampled = &mut self.sampled;
+        sampled.total_records += 1;
+        sampled.total_bytes += fields.iter().map(|field| field.len()).sum::<usize>() as u64
+            + (delimiter.len() * fields.len().saturating_sub(1)) as u64;
+        if fields
+            .first()
+            .is_some_and(|field| is_comment(field.as_bytes(), &self.options))
+        {
+            sampled.skipped_line_count += 1;
+        } else if self.options.skip_empty && fields.iter().all(|field| field.trim().is_empty()) {
+            sampled.empty_line_count += 1;
+        } else {
+            sampled.add_split_record(fields, delimiter, &self.options);
+        }
+    }
+
+    /// The `k` most frequent values sampled so far from the accumulator at `field`, with their
+    /// frequencies, ordered by descending frequency and then by value. Accumulators are ordered
+    /// as in `SampledFields`: the selected fields, then subfields, then whole records. Panics if
+    /// there's no accumulator at `field`.
+    pub fn top_k(&self, field: usize, k: usize) -> Vec<(String, f32)> {
+        let (counts, total) = self.sampled.accumulators[field].to_counts();
+        let mut counts: Vec<(&String, u64)> = counts.into_iter().collect();
+        counts.sort_unstable_by(|(value1, count1), (value2, count2)| {
+            count2.cmp(count1).then_with(|| value1.cmp(value2))
+        });
+        counts
+            .into_iter()
+            .take(k)
+            .map(|(value, count)| (value.clone(), (count as f64 / total as f64) as f32))
+            .collect()
+    }
+
+    /// The values sampled so far, with the counts of missing fields and skipped records.
+    pub fn sampled_fields(&self) -> &SampledFields {
+        &self.sampled
+    }
+
+    /// Finishes sampling, returning the values sampled, for example to be merged with samples
+    /// of other parts of the input.
+    pub fn into_sampled_fields(self) -> SampledFields {
+        self.sampled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::process_reader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_push_matches_process_reader() {
+        let options = SampleOptions {
+            fields: vec![1].into(),
+            include_whole_line: true,
+            skip_empty: true,
+            ..Default::default()
+        };
+        let input = "a x\nb y\n\nc x\nd\n";
+        let mut sampler = Sampler::new(options.clone(), None);
+        for record in input.lines() {
+            sampler.push(record);
+        }
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let sampled = sampler.sampled_fields();
+        assert_eq!(
+            pr.accumulators[0].to_histogram(),
+            sampled.accumulators[0].to_histogram()
+        );
+        assert_eq!(
+            pr.accumulators[1].to_histogram(),
+            sampled.accumulators[1].to_histogram()
+        );
+        assert_eq!(pr.missing_field_counts, sampled.missing_field_counts);
+        assert_eq!(1, sampled.empty_line_count);
+        assert_eq!(5, sampled.total_records);
+    }
+
+    #[test]
+    fn test_top_k_while_pushing() {
+        let options = SampleOptions {
+            fields: vec![0].into(),
+            ..Default::default()
+        };
+        let mut sampler = Sampler::new(options, Some(1));
+        sampler.push_fields(&["b", "1"]);
+        sampler.push_fields(&["a", "2"]);
+        assert_eq!(
+            vec![("a".to_string(), 0.5), ("b".to_string(), 0.5)],
+            sampler.top_k(0, 10)
+        );
+        sampler.push_fields(&["b", "3"]);
+        sampler.push_fields(&[]);
+        assert_eq!(vec![("b".to_string(), 2.0 / 3.0)], sampler.top_k(0, 1));
+        let sampled = sampler.into_sampled_fields();
+        assert_eq!(vec![1], sampled.missing_field_counts);
+        assert_eq!(4, sampled.total_records);
+        // Each of "b 1", "a 2" and "b 3", and nothing for the empty record.
+        assert_eq!(9, sampled.total_bytes);
+    }
+}
@@ -0,0 +1,2439 @@
+use crate::exact::ExactCounts;
+use crate::filesplits::read_until_capped;
+use crate::hyperloglog::HyperLogLog;
+use crate::interned::InternedReservoir;
+use crate::reservoir::Reservoir;
+use crate::spacesaving::SpaceSaving;
+use crate::unique::UniqueSample;
+use clap::{ArgAction, ValueEnum};
+use fastrand::Rng;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::{Deref, Range};
+use std::sync::{Arc, Mutex};
+
+/// Joins the values of the fields sampled together with `--composite`.
+pub const COMPOSITE_SEPARATOR: &str = "\t";
+
+/// The version of the format of state files, raised whenever it changes. Files written before
+/// it was recorded are version 1.
+const STATE_VERSION: u32 = 2;
+
+/// The most records too short for a field that `--verbose` prints for each field.
+const MAX_MISSING_FIELD_REPORTS: usize = 10;
+
+/// The most characters of a record that `--verbose` prints.
+const MAX_REPORTED_RECORD_CHARS: usize = 100;
+
+/// How records are terminated.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    /// Records end at `\n`, and a `\r` before it is removed.
+    Auto,
+    /// Records end at `\n`, and a `\r` before it is kept.
+    Lf,
+    /// Records end at `\r\n`; a `\n` on its own is part of the record.
+    Crlf,
+}
+
+/// Options controlling how records are read, split into fields and sampled. The command line
+/// flattens these into its own arguments.
+#[derive(clap::Args, Debug, Clone)]
+pub struct SampleOptions {
+    /// Reservoir sample size
+    #[arg(short = 'n', long = "num-samples", default_value = "1000")]
+    pub sample_size: usize,
+
+    #[command(flatten)]
+    pub fields: FieldIndexes,
+
+    /// Sample the selected fields together rather than separately, as a single value of the
+    /// fields joined by tabs, giving the joint frequency of each combination of values.
+    #[arg(long = "composite", requires = "fields")]
+    pub composite: bool,
+
+    /// Split a field again and sample one of its parts, given as `<field>:<separator>:<index>`:
+    /// `3:,:1` samples the second comma-separated part of field 3. Each subfield is sampled
+    /// after the `-f` fields, and is missing if the field or the part is.
+    #[arg(long = "subfield", action = ArgAction::Append, value_parser = parse_subfield)]
+    pub subfields: Vec<Subfield>,
+
+    /// Field separator, if unspecified then `char::is_whitespace` will be used. `\t`, `\0`, `\n`,
+    /// `\r` and `\\` stand for a tab, a NUL, a newline, a carriage return and a backslash.
+    #[arg(short = 's', long = "field-separator", value_parser = parse_field_separator)]
+    pub field_separator: Option<String>,
+
+    /// Regular expression matching field separators, as an alternative to `-s`.
+    #[arg(long = "regex-separator", conflicts_with = "field_separator", value_parser = Regex::new)]
+    pub regex_separator: Option<Regex>,
+
+    /// Split fields on any one of these characters, like `,;` for records separated by either a
+    /// comma or a semicolon, as a simpler alternative to `--regex-separator`. Takes the escapes
+    /// of `-s`.
+    #[arg(
+        long = "separators",
+        value_name = "CHARS",
+        value_parser = parse_field_separator,
+        conflicts_with_all = ["field_separator", "regex_separator", "csv_input"]
+    )]
+    pub separators: Option<String>,
+
+    /// Cut each record into fixed-width fields at these character positions rather than
+    /// splitting it on a separator, given as comma-separated `<start>:<end>` ranges like
+    /// `0:10,10:14`. Positions count characters, not bytes, from 0, and each range ends before
+    /// `end`. A field is missing from a record that ends before its range does. `--trim` drops
+    /// the padding of the fields.
+    #[arg(
+        long = "fixed-width",
+        value_name = "RANGES",
+        value_parser = parse_fixed_widths,
+        conflicts_with_all = ["field_separator", "regex_separator", "separators", "csv_input"]
+    )]
+    pub fixed_widths: Option<FixedWidths>,
+
+    /// Treat the first record as a header naming each field, and label the output with those
+    /// names. The header record is not sampled.
+    #[arg(long = "header")]
+    pub header: bool,
+
+    /// Sample this value for fields missing from a record, rather than counting them as missing.
+    #[arg(long = "null-as")]
+    pub null_as: Option<String>,
+
+    /// Count empty fields as missing rather than sampling them as empty values. With `--trim`,
+    /// fields of only whitespace are empty too.
+    #[arg(long = "empty-as-missing")]
+    pub empty_as_missing: bool,
+
+    /// Sample whole records as well as the fields selected with `-f` or `--subfield`, reported
+    /// after the fields.
+    #[arg(long = "include-whole-line")]
+    pub include_whole_line: bool,
+
+    /// Parse the input as CSV, so that quoted fields may contain the separator or newlines.
+    /// `-s` sets the delimiter, which must be a single byte and defaults to a comma. Each input
+    /// file is read on a single thread.
+    #[arg(
+        long = "csv-input",
+        conflicts_with_all = ["regex_separator", "parallel_stdin"]
+    )]
+    pub csv_input: bool,
+
+    /// Estimate the number of distinct values of each field across the whole input, rather than
+    /// just the sample, using a HyperLogLog sketch.
+    #[arg(long = "estimate-cardinality")]
+    pub estimate_cardinality: bool,
+
+    /// Count the records with each number of fields, whichever fields are sampled, to spot
+    /// malformed records in input that should have the same fields in every record.
+    #[arg(long = "field-count-histogram")]
+    pub field_count_histogram: bool,
+
+    /// Count every value instead of sampling, giving exact frequencies. Memory grows with the
+    /// number of distinct values in each field rather than with `-n`.
+    #[arg(long = "exact")]
+    pub exact: bool,
+
+    /// Track the most frequent values of each field in this many counters with the Space-Saving
+    /// algorithm, rather than sampling. The result is deterministic and memory is bounded, and
+    /// any value making up more than 1/m of a field is found. Counts are estimates, shown with
+    /// the most they may overestimate by.
+    #[arg(
+        long = "heavy-hitters",
+        value_name = "M",
+        conflicts_with = "exact",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub heavy_hitters: Option<usize>,
+
+    /// Weight each value's chance of being sampled: `length` by its length in bytes, or a field
+    /// index by the number in that field of the record. Records whose weight field is missing or
+    /// isn't a number are skipped and counted. Values with a weight of 0 or less are never
+    /// sampled, and frequencies are weighted too.
+    #[arg(
+        long = "weight-by",
+        value_parser = parse_weight_by,
+        conflicts_with_all = ["exact", "heavy_hitters"]
+    )]
+    pub weight_by: Option<WeightBy>,
+
+    /// Sample `-n` of the distinct values of each field, each as likely to be sampled as any
+    /// other however often it occurs, rather than sampling occurrences. Frequencies then describe
+    /// which values occur rather than how often: every sampled value has the same frequency.
+    /// Samples of separate inputs merge into the sample of their combined distinct values, so
+    /// a value seen in several of them is only held once.
+    #[arg(
+        long = "unique-sample",
+        conflicts_with_all = ["exact", "heavy_hitters", "weight_by"]
+    )]
+    pub unique_sample: bool,
+
+    /// Favour recent values: the weight of each value decays by a factor of `e^-LAMBDA` for
+    /// every later value of its field, so most of the sample comes from the last few multiples
+    /// of 1/LAMBDA values. Each input file is then read in order on a single thread, and the
+    /// samples of several files are merged as if the files ended together.
+    #[arg(
+        long = "decay",
+        value_name = "LAMBDA",
+        value_parser = parse_decay,
+        conflicts_with_all = ["exact", "heavy_hitters", "unique_sample", "parallel_stdin"]
+    )]
+    pub decay: Option<f64>,
+
+    /// Hold each distinct value of a sampled field once, however often it's in the sample, with
+    /// the sample itself holding small ids of the values. This takes far less memory for fields
+    /// with few distinct values, but more for fields where most sampled values are distinct.
+    #[arg(long = "intern", conflicts_with_all = ["exact", "heavy_hitters", "unique_sample"])]
+    pub intern: bool,
+
+    /// How records are terminated. With `crlf`, each input file is read on a single thread.
+    #[clap(value_enum, long = "line-ending", default_value = "auto")]
+    pub line_ending: LineEnding,
+
+    /// The byte ending each record: a single character, or an escape like those of `-s`.
+    /// `--line-ending` only applies when this is a newline.
+    #[arg(long = "record-separator", default_value = "\\n", value_parser = parse_record_separator)]
+    pub record_separator: u8,
+
+    /// Read paragraphs as records: runs of lines separated by blank lines, those of only
+    /// whitespace, with their lines joined by newlines. Files are split for parallel reading at
+    /// blank lines, so a paragraph is never divided between splits.
+    #[arg(
+        long = "paragraph-mode",
+        conflicts_with_all = ["csv_input", "record_separator", "parallel_stdin"]
+    )]
+    pub paragraph_mode: bool,
+
+    /// Replace invalid UTF-8 in records with U+FFFD rather than skipping those records.
+    #[arg(long = "lossy")]
+    pub lossy: bool,
+
+    /// Skip records that are empty or only whitespace, counting them rather than sampling them.
+    #[arg(long = "skip-empty")]
+    pub skip_empty: bool,
+
+    /// Truncate records longer than this many bytes, not counting their line ending, and count
+    /// them. The rest of a long record is read past without being held in memory. With
+    /// `--paragraph-mode` this applies to each line of a paragraph.
+    #[arg(
+        long = "max-line-bytes",
+        value_name = "N",
+        conflicts_with = "csv_input",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub max_line_bytes: Option<usize>,
+
+    /// Skip this many records at the start of each input file, or of stdin, before the header.
+    #[arg(long = "skip-lines", default_value_t = 0)]
+    pub skip_lines: u64,
+
+    /// Skip records starting with this character. With `--csv-input`, records whose first field
+    /// starts with it are skipped.
+    #[arg(long = "comment-char")]
+    pub comment_char: Option<char>,
+
+    /// Lowercase each value before sampling it, so values differing only in case are counted
+    /// together. The output shows the lowercased values.
+    #[arg(long = "lowercase")]
+    pub lowercase: bool,
+
+    /// Trim whitespace from both ends of each value before sampling it. The output shows the
+    /// trimmed values.
+    #[arg(long = "trim")]
+    pub trim: bool,
+
+    /// Print the first few records too short for each selected field to stderr, with the number
+    /// of fields they have, to help find where a file is malformed.
+    #[arg(long = "verbose")]
+    pub verbose: bool,
+
+    /// The records `--verbose` has printed, shared by every thread sampling the input.
+    #[arg(skip)]
+    pub missing_field_reports: MissingFieldReports,
+}
+
+impl Default for SampleOptions {
+    /// The options used when none are given on the command line: whole records are sampled into
+    /// a reservoir of 1000.
+    fn default() -> Self {
+        SampleOptions {
+            sample_size: 1000,
+            fields: FieldIndexes::default(),
+            composite: false,
+            subfields: Vec::new(),
+            field_separator: None,
+            regex_separator: None,
+            separators: None,
+            fixed_widths: None,
+            header: false,
+            null_as: None,
+            empty_as_missing: false,
+            include_whole_line: false,
+            csv_input: false,
+            estimate_cardinality: false,
+            field_count_histogram: false,
+            exact: false,
+            heavy_hitters: None,
+            weight_by: None,
+            unique_sample: false,
+            decay: None,
+            intern: false,
+            line_ending: LineEnding::Auto,
+            record_separator: b'\n',
+            paragraph_mode: false,
+            lossy: false,
+            skip_empty: false,
+            max_line_bytes: None,
+            skip_lines: 0,
+            comment_char: None,
+            lowercase: false,
+            trim: false,
+            verbose: false,
+            missing_field_reports: MissingFieldReports::default(),
+        }
+    }
+}
+
+impl SampleOptions {
+    /// The fields and subfields sampled and how, which a state file is saved with.
+    pub fn selection(&self) -> Selection {
+        Selection {
+            fields: self.fields.to_vec(),
+            subfields: self.subfields.clone(),
+            composite: self.composite,
+            include_whole_line: self.include_whole_line,
+        }
+    }
+
+    /// Whether whole records are sampled, as no fields or subfields were selected.
+    pub fn samples_whole_records(&self) -> bool {
+        self.fields.is_empty() && self.subfields.is_empty()
+    }
+
+    /// The number of accumulators sampling `-f` fields: one with `--composite`, otherwise one per
+    /// field. Those sampling `--subfield`s come after them.
+    pub fn num_field_accumulators(&self) -> usize {
+        match self.composite {
+            true => 1,
+            false => self.fields.len(),
+        }
+    }
+
+    /// The number of accumulators: those of the `-f` fields, then those of subfields, then with
+    /// `--include-whole-line` one of whole records. There is a single one when only whole records
+    /// are sampled.
+    pub fn num_accumulators(&self) -> usize {
+        match self.whole_record_index() {
+            Some(reservoir_index) => reservoir_index + 1,
+            None => self.num_field_accumulators() + self.subfields.len(),
+        }
+    }
+
+    /// The index of the accumulator sampling whole records, if any: the only one when no fields
+    /// are selected, or the last with `--include-whole-line`.
+    pub fn whole_record_index(&self) -> Option<usize> {
+        match self.samples_whole_records() {
+            true => Some(0),
+            false => self
+                .include_whole_line
+                .then(|| self.num_field_accumulators() + self.subfields.len()),
+        }
+    }
+
+    /// Describes what the accumulator at `reservoir_index` samples, for messages about it.
+    fn accumulator_name(&self, reservoir_index: usize) -> String {
+        let num_field_accumulators = self.num_field_accumulators();
+        if reservoir_index >= num_field_accumulators {
+            let subfield = &self.subfields[reservoir_index - num_field_accumulators];
+            format!("field {}.{}", subfield.field, subfield.index)
+        } else if self.composite {
+            let fields: Vec<String> = self.fields.iter().map(|field| field.to_string()).collect();
+            format!("one of fields {}", fields.join(", "))
+        } else {
+            format!("field {}", self.fields[reservoir_index])
+        }
+    }
+}
+
+/// How many records too short for each field `--verbose` has printed. Clones share the counts,
+/// so the threads sampling parts of the input print at most `MAX_MISSING_FIELD_REPORTS` for a
+/// field between them.
+#[derive(Debug, Clone, Default)]
+pub struct MissingFieldReports(Arc<Mutex<Vec<usize>>>);
+
+impl MissingFieldReports {
+    /// Prints `record`, which has `num_fields` fields and so is missing the value sampled into
+    /// `reservoir_index`, unless enough records have been printed for that field already.
+    fn report(
+        &self,
+        reservoir_index: usize,
+        record: &str,
+        num_fields: usize,
+        options: &SampleOptions,
+    ) {
+        let reported = {
+            let mut counts = self.0.lock().unwrap();
+            if counts.len() <= reservoir_index {
+                counts.resize(reservoir_index + 1, 0);
+            }
+            if counts[reservoir_index] == MAX_MISSING_FIELD_REPORTS {
+                return;
+            }
+            counts[reservoir_index] += 1;
+            counts[reservoir_index]
+        };
+        let mut shown: String = record.chars().take(MAX_REPORTED_RECORD_CHARS).collect();
+        if shown.len() < record.len() {
+            shown.push_str("...");
+        }
+        let last = match reported {
+            MAX_MISSING_FIELD_REPORTS => " (no more are shown for it)",
+            _ => "",
+        };
+        eprintln!(
+            "warning: {} is missing from a record with {num_fields} fields{last}: {shown:?}",
+            options.accumulator_name(reservoir_index)
+        );
+    }
+}
+
+/// A part of a field that is sampled on its own, given with `--subfield`: the field is split on
+/// `separator` and the part at `index` is sampled. Like field indexes, negative part indexes
+/// count back from the end.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subfield {
+    pub field: isize,
+    pub separator: String,
+    pub index: isize,
+}
+
+/// Writes the subfield as it's given to `--subfield`.
+impl fmt::Display for Subfield {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.field, self.separator, self.index)
+    }
+}
+
+/// Parses a `--subfield` given as `<field>:<separator>:<index>`. The separator may itself
+/// contain colons.
+fn parse_subfield(s: &str) -> Result<Subfield, String> {
+    let invalid = || format!("`{s}` isn't of the form <field>:<separator>:<index>");
+    let (field, rest) = s.split_once(':').ok_or_else(invalid)?;
+    let (separator, index) = rest.rsplit_once(':').ok_or_else(invalid)?;
+    if separator.is_empty() {
+        return Err(format!("`{s}` has an empty separator"));
+    }
+    Ok(Subfield {
+        field: field.parse().map_err(|_| invalid())?,
+        separator: separator.to_string(),
+        index: index.parse().map_err(|_| invalid())?,
+    })
+}
+
+/// What each value is weighted by when it's sampled, given with `--weight-by`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeightBy {
+    /// The length of the value in bytes.
+    Length,
+    /// The number in this field of the value's record.
+    Field(isize),
+}
+
+/// Parses `--weight-by`, either `length` or a field index.
+fn parse_weight_by(s: &str) -> Result<WeightBy, String> {
+    match s {
+        "length" => Ok(WeightBy::Length),
+        _ => s
+            .parse()
+            .map(WeightBy::Field)
+            .map_err(|_| format!("`{s}` isn't `length` or a field index")),
+    }
+}
+
+/// Parses `--decay`, which must be a positive number.
+fn parse_decay(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(decay) if decay > 0.0 && decay.is_finite() => Ok(decay),
+        _ => Err(format!("`{s}` isn't a positive number")),
+    }
+}
+
+/// The character ranges of the fields of fixed-width records, given with `--fixed-width`, in
+/// order of where they end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedWidths(Vec<Range<usize>>);
+
+impl Deref for FixedWidths {
+    type Target = Vec<Range<usize>>;
+
+    fn deref(&self) -> &Vec<Range<usize>> {
+        &self.0
+    }
+}
+
+/// Parses `--fixed-width` ranges. They must be given in order, each ending no earlier than the
+/// one before, so that a record too short for one of them is too short for every later one.
+fn parse_fixed_widths(s: &str) -> Result<FixedWidths, String> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for part in s.split(',') {
+        let range = part
+            .split_once(':')
+            .and_then(|(start, end)| Some(start.parse().ok()?..end.parse().ok()?))
+            .ok_or_else(|| format!("`{part}` isn't a range like `0:10`"))?;
+        if range.is_empty() {
+            return Err(format!("`{part}` must end after it starts"));
+        }
+        if ranges.last().is_some_and(|last| last.end > range.end) {
+            return Err(format!("`{part}` ends before the range before it"));
+        }
+        ranges.push(range);
+    }
+    Ok(FixedWidths(ranges))
+}
+
+/// The indexes of the fields to sample, given with `-f`. Each `-f` takes an index, an inclusive
+/// range of indexes like `0-4`, or a comma-separated list of them like `0-2,7`, and they are
+/// expanded in order into a single list.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldIndexes(Vec<isize>);
+
+impl From<Vec<isize>> for FieldIndexes {
+    fn from(indexes: Vec<isize>) -> Self {
+        FieldIndexes(indexes)
+    }
+}
+
+impl Deref for FieldIndexes {
+    type Target = Vec<isize>;
+
+    fn deref(&self) -> &Vec<isize> {
+        &self.0
+    }
+}
+
+impl clap::FromArgMatches for FieldIndexes {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        let indexes = matches
+            .get_many::<Vec<isize>>("fields")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .copied()
+            .collect();
+        Ok(FieldIndexes(indexes))
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
+        if matches.contains_id("fields") {
+            *self = FieldIndexes::from_arg_matches(matches)?;
+        }
+        Ok(())
+    }
+}
+
+impl clap::Args for FieldIndexes {
+    fn augment_args(cmd: clap::Command) -> clap::Command {
+        cmd.arg(
+            clap::Arg::new("fields")
+                .short('f')
+                .long("field-index")
+                .value_name("FIELDS")
+                .action(ArgAction::Append)
+                .allow_negative_numbers(true)
+                .value_parser(parse_field_indexes)
+                .help(
+                    "Fields to sample, indexed from 0. Negative indexes count back from the end of \
+                     each record, so -1 is the last field. Takes an index, an inclusive range \
+                     like 0-4, or a comma-separated list of them like 0-2,7",
+                ),
+        )
+    }
+
+    fn augment_args_for_update(cmd: clap::Command) -> clap::Command {
+        FieldIndexes::augment_args(cmd)
+    }
+}
+
+/// Parses the value of one `-f`: comma-separated indexes or inclusive ranges of indexes. A range
+/// may count back from the end, like `-3--1`, but can't mix the two ends.
+fn parse_field_indexes(s: &str) -> Result<Vec<isize>, String> {
+    let mut indexes = Vec::new();
+    for part in s.split(',') {
+        let parse = |index: &str| {
+            index
+                .parse::<isize>()
+                .map_err(|_| format!("`{part}` isn't a field index or a range of them"))
+        };
+        // A leading `-` is the sign of the start of the range, not the separator.
+        match part.get(1..).and_then(|rest| rest.find('-')) {
+            None => indexes.push(parse(part)?),
+            Some(i) => {
+                let (start, end) = (parse(&part[..i + 1])?, parse(&part[i + 2..])?);
+                if start > end || (start < 0) != (end < 0) {
+                    return Err(format!(
+                        "`{part}` must go from a lower to a higher index, counting from the same end"
+                    ));
+                }
+                indexes.extend(start..=end);
+            }
+        }
+    }
+    Ok(indexes)
+}
+
+/// Decodes the escape sequences `\\0`, `\\t`, `\\n`, `\\r` and `\\\\` in a separator given on the
+/// command line, where control characters are awkward to type. Other backslashes are kept.
+fn unescape_separator(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.clone().next() {
+            Some('0') => unescaped.push('\0'),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            _ => {
+                unescaped.push('\\');
+                continue;
+            }
+        }
+        chars.next();
+    }
+    unescaped
+}
+
+/// Parses the `--field-separator`, which may use the escapes of `unescape_separator` but can't
+/// be empty.
+fn parse_field_separator(s: &str) -> Result<String, String> {
+    match unescape_separator(s) {
+        separator if separator.is_empty() => Err("the field separator can't be empty".to_string()),
+        separator => Ok(separator),
+    }
+}
+
+/// Parses the byte given to `--record-separator`, which may use the escapes of
+/// `unescape_separator`.
+fn parse_record_separator(s: &str) -> Result<u8, String> {
+    match unescape_separator(s).as_bytes() {
+        &[byte] => Ok(byte),
+        _ => Err(format!(
+            "`{s}` isn't a single byte, or one of \\0, \\t, \\n, \\r and \\\\"
+        )),
+    }
+}
+
+/// Collects the values of one field: a reservoir sample of them, with `--exact` a count of every
+/// one, with `--heavy-hitters` the approximate counts of the most frequent ones, or with
+/// `--unique-sample` a sample of the distinct ones. With `--intern`, a reservoir sample holds each
+/// distinct value once.
+#[derive(Debug)]
+pub enum Accumulator {
+    Sample(Reservoir<String>),
+    Interned(InternedReservoir),
+    Exact(ExactCounts<String>),
+    HeavyHitters(SpaceSaving<String>),
+    Unique(UniqueSample<String>),
+}
+
+impl Accumulator {
+    /// Adds a value, copying it only if it's stored: when it enters the sample, or the first time
+    /// it's counted. The weight only applies to a sample.
+    fn add(&mut self, value: &str, weight: f64) {
+        match self {
+            Accumulator::Sample(reservoir) => {
+                reservoir.add_weighted_with(weight, || value.to_string())
+            }
+            Accumulator::Interned(reservoir) => reservoir.add_weighted(value, weight),
+            Accumulator::Exact(counts) => counts.add_borrowed(value),
+            Accumulator::HeavyHitters(counters) => counters.add_borrowed(value),
+            Accumulator::Unique(sample) => sample.add_borrowed(value),
+        }
+    }
+
+    /// Merges two accumulators of the same kind.
+    pub fn merge(a1: &Accumulator, a2: &Accumulator) -> Accumulator {
+        Accumulator::merge_many([a1, a2])
+    }
+
+    /// Merges any number of accumulators of the same kind in a single pass. Samples are merged
+    /// into an interned sample if any of them is interned, as they are once loaded from a state
+    /// file.
+    pub fn merge_many<'a, I: IntoIterator<Item = &'a Accumulator>>(accumulators: I) -> Accumulator {
+        let accumulators: Vec<&Accumulator> = accumulators.into_iter().collect();
+        if accumulators
+            .iter()
+            .any(|a| matches!(a, Accumulator::Interned(_)))
+        {
+            let converted: Vec<Option<InternedReservoir>> = accumulators
+                .iter()
+                .map(|a| match a {
+                    Accumulator::Sample(reservoir) => {
+                        Some(InternedReservoir::from_reservoir(reservoir))
+                    }
+                    _ => None,
+                })
+                .collect();
+            return Accumulator::Interned(InternedReservoir::merge_many(
+                accumulators
+                    .iter()
+                    .zip(&converted)
+                    .map(|(a, converted)| match (a, converted) {
+                        (Accumulator::Interned(reservoir), _) => reservoir,
+                        (_, Some(reservoir)) => reservoir,
+                        _ => panic!("can't merge a sample with another kind of accumulator"),
+                    }),
+            ));
+        }
+        match accumulators.first() {
+            Some(Accumulator::Exact(_)) => Accumulator::Exact(ExactCounts::merge_many(
+                accumulators.iter().map(|a| match a {
+                    Accumulator::Exact(counts) => counts,
+                    _ => panic!("can't merge exact counts with another kind of accumulator"),
+                }),
+            )),
+            Some(Accumulator::HeavyHitters(_)) => {
+                Accumulator::HeavyHitters(SpaceSaving::merge_many(accumulators.iter().map(|a| {
+                    match a {
+                        Accumulator::HeavyHitters(counters) => counters,
+                        _ => panic!("can't merge heavy hitters with another kind of accumulator"),
+                    }
+                })))
+            }
+            Some(Accumulator::Unique(_)) => Accumulator::Unique(UniqueSample::merge_many(
+                accumulators.iter().map(|a| match a {
+                    Accumulator::Unique(sample) => sample,
+                    _ => panic!("can't merge a unique sample with another kind of accumulator"),
+                }),
+            )),
+            _ => Accumulator::Sample(Reservoir::merge_many(accumulators.iter().map(
+                |a| match a {
+                    Accumulator::Sample(reservoir) => reservoir,
+                    _ => panic!("can't merge a sample with another kind of accumulator"),
+                },
+            ))),
+        }
+    }
+
+    /// The count of each value, and the total the counts are a share of: the pool size of a
+    /// sample, or the number of values added for exact counts and heavy hitters. Unlike the
+    /// frequencies of `to_histogram`, the counts are exact, so they rank values without rounding.
+    pub fn to_counts(&self) -> (HashMap<&String, u64>, u64) {
+        match self {
+            Accumulator::Sample(reservoir) => reservoir.to_counts(),
+            Accumulator::Interned(reservoir) => reservoir.to_counts(),
+            Accumulator::Exact(counts) => counts.to_counts(),
+            Accumulator::HeavyHitters(counters) => counters.to_counts(),
+            Accumulator::Unique(sample) => sample.to_counts(),
+        }
+    }
+
+    pub fn to_histogram(&self) -> HashMap<&String, f32> {
+        match self {
+            Accumulator::Sample(reservoir) => reservoir.to_histogram(),
+            Accumulator::Interned(reservoir) => reservoir.to_histogram(),
+            Accumulator::Exact(counts) => counts.to_histogram(),
+            Accumulator::HeavyHitters(counters) => counters.to_histogram(),
+            Accumulator::Unique(sample) => sample.to_histogram(),
+        }
+    }
+
+    /// The number of values added, including those that weren't sampled.
+    pub fn num_adds(&self) -> u64 {
+        match self {
+            Accumulator::Sample(reservoir) => reservoir.num_adds(),
+            Accumulator::Interned(reservoir) => reservoir.num_adds(),
+            Accumulator::Exact(counts) => counts.total(),
+            Accumulator::HeavyHitters(counters) => counters.total(),
+            Accumulator::Unique(sample) => sample.num_adds(),
+        }
+    }
+
+    /// The standard error of a frequency taken from this accumulator's histogram, `sqrt(p(1-p)/n)`
+    /// for a sample of `n` values. Exact counts and heavy hitters have no sampling error, so it is
+    /// 0 for them. It is 0 for a unique sample too, whose frequencies aren't estimates of anything.
+    pub fn standard_error(&self, freq: f64) -> f64 {
+        match self {
+            Accumulator::Sample(reservoir) if !reservoir.is_empty() => {
+                (freq * (1.0 - freq) / reservoir.len() as f64).sqrt()
+            }
+            Accumulator::Interned(reservoir) if !reservoir.is_empty() => {
+                (freq * (1.0 - freq) / reservoir.len() as f64).sqrt()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Iterates over the collected values with the number of times each was collected.
+    pub fn value_counts(&self) -> Box<dyn Iterator<Item = (&String, u64)> + '_> {
+        match self {
+            Accumulator::Sample(reservoir) => Box::new(reservoir.iter().map(|value| (value, 1))),
+            Accumulator::Interned(reservoir) => Box::new(reservoir.iter().map(|value| (value, 1))),
+            Accumulator::Exact(counts) => Box::new(counts.iter()),
+            Accumulator::HeavyHitters(counters) => Box::new(counters.iter()),
+            Accumulator::Unique(sample) => Box::new(sample.iter().map(|value| (value, 1))),
+        }
+    }
+}
+
+/// The values sampled from each selected field of the input, or from whole records when no fields
+/// are selected, along with counts of the records and fields that couldn't be sampled.
+#[derive(Debug)]
+pub struct SampledFields {
+    /// The accumulators built from reading the input data, one per field
+    pub accumulators: Vec<Accumulator>,
+
+    /// The number of fields in the input data that could not be totally processed
+    /// (for example because the record wasn't long enough), counted separately per field.
+    pub missing_field_counts: Vec<u64>,
+
+    /// The names of every field in the input data, read from its first record when `--header`
+    /// is given.
+    pub header: Option<Vec<String>>,
+
+    /// Sketches of every value seen, one per field, kept when `--estimate-cardinality` is given.
+    pub sketches: Option<Vec<HyperLogLog>>,
+
+    /// The number of records with each number of fields, indexed by the number, kept when
+    /// `--field-count-histogram` is given. Records skipped before they're split aren't counted.
+    pub field_counts: Option<Vec<u64>>,
+
+    /// The number of records skipped because they weren't valid UTF-8. Always 0 with `--lossy`.
+    pub invalid_line_count: u64,
+
+    /// The number of records skipped by `--skip-lines` or `--comment-char`, which aren't sampled
+    /// or counted as missing.
+    pub skipped_line_count: u64,
+
+    /// The number of records skipped because their `--weight-by` field was missing or wasn't a
+    /// number.
+    pub invalid_weight_count: u64,
+
+    /// The number of records skipped by `--skip-empty` for being empty or only whitespace.
+    pub empty_line_count: u64,
+
+    /// The number of records truncated for being longer than `--max-line-bytes`.
+    pub truncated_line_count: u64,
+
+    /// The number of records read, including those that were skipped or weren't valid UTF-8, but
+    /// not the header.
+    pub total_records: u64,
+
+    /// The number of bytes in the records read by `process_reader`, including their separators.
+    /// Records skipped by `--skip-lines` and the header aren't counted.
+    pub total_bytes: u64,
+
+    /// The number of parts of the input that were sampled separately and merged, such as the
+    /// splits of a file. Not saved in a state file.
+    pub num_splits: u64,
+}
+
+impl SampledFields {
+    /// Creates one empty reservoir per field, and one for whole records if no fields were
+    /// specified or with `--include-whole-line`.
+    pub fn new(seed: Option<u64>, options: &SampleOptions) -> SampledFields {
+        let num_reservoirs = options.num_accumulators();
+        SampledFields {
+            accumulators: new_accumulators(num_reservoirs, seed, options),
+            missing_field_counts: vec![0; num_reservoirs],
+            header: None,
+            sketches: options
+                .estimate_cardinality
+                .then(|| vec![HyperLogLog::new(); num_reservoirs]),
+            field_counts: options.field_count_histogram.then(Vec::new),
+            invalid_line_count: 0,
+            skipped_line_count: 0,
+            invalid_weight_count: 0,
+            empty_line_count: 0,
+            truncated_line_count: 0,
+            total_records: 0,
+            total_bytes: 0,
+            num_splits: 0,
+        }
+    }
+
+    /// Adds a value to the accumulator at `reservoir_index`, and to its sketch if there is one.
+    /// Samples a value with the weight of its record, or with `--weight-by length`, the weight of
+    /// its length.
+    fn sample(
+        &mut self,
+        reservoir_index: usize,
+        value: &str,
+        record_weight: f64,
+        options: &SampleOptions,
+    ) {
+        if let Some(sketches) = &mut self.sketches {
+            sketches[reservoir_index].add(value.as_bytes());
+        }
+        let weight = match options.weight_by {
+            Some(WeightBy::Length) => value.len() as f64,
+            _ => record_weight,
+        };
+        self.accumulators[reservoir_index].add(value, weight);
+    }
+
+    /// Samples a record, either whole or split into fields.
+    pub fn add_record(&mut self, record: &str, options: &SampleOptions) {
+        if !options.samples_whole_records() {
+            let fields = split_record(record, options);
+            self.count_fields(fields.len());
+            self.add_fields(record, &fields, options);
+        } else if matches!(options.weight_by, Some(WeightBy::Field(_)))
+            || options.field_count_histogram
+        {
+            let fields = split_record(record, options);
+            self.count_fields(fields.len());
+            self.add_whole_record(record, &fields, options);
+        } else {
+            self.add_whole_record(record, &[], options);
+        }
+    }
+
+    /// Counts a record with `num_fields` fields, with `--field-count-histogram`.
+    fn count_fields(&mut self, num_fields: usize) {
+        if let Some(field_counts) = &mut self.field_counts {
+            if field_counts.len() <= num_fields {
+                field_counts.resize(num_fields + 1, 0);
+            }
+            field_counts[num_fields] += 1;
+        }
+    }
+
+    /// Samples a record that has already been split into `fields`. The whole record, when it's
+    /// sampled or printed by `--verbose`, is the fields joined by `delimiter`.
+    pub(crate) fn add_split_record(
+        &mut self,
+        fields: &[&str],
+        delimiter: &str,
+        options: &SampleOptions,
+    ) {
+        self.count_fields(fields.len());
+        if options.samples_whole_records() {
+            self.add_whole_record(&fields.join(delimiter), fields, options);
+        } else if options.include_whole_line || options.verbose {
+            self.add_fields(&fields.join(delimiter), fields, options);
+        } else {
+            self.add_fields("", fields, options);
+        }
+    }
+
+    /// Samples a whole record into the only reservoir, as no fields were specified. Its fields
+    /// are only needed for `--weight-by` a field.
+    fn add_whole_record(&mut self, record: &str, fields: &[&str], options: &SampleOptions) {
+        if let Some(weight) = self.record_weight(fields, options) {
+            self.sample(0, &normalize(record, options), weight, options);
+        }
+    }
+
+    /// The weight of a record's values: the number in its `--weight-by` field, or 1 if the
+    /// values aren't weighted by a field. `None` if the weight field is missing or isn't a
+    /// number, counting the record as skipped.
+    fn record_weight(&mut self, fields: &[&str], options: &SampleOptions) -> Option<f64> {
+        let Some(WeightBy::Field(field_index)) = options.weight_by else {
+            return Some(1.0);
+        };
+        let weight = resolve_field_index(field_index, fields.len())
+            .and_then(|field_index| fields[field_index].trim().parse::<f64>().ok())
+            .filter(|weight| weight.is_finite());
+        if weight.is_none() {
+            self.invalid_weight_count += 1;
+        }
+        weight
+    }
+
+    /// Samples the selected fields of a record that has already been split into fields, and with
+    /// `--include-whole-line` the whole `record`.
+    fn add_fields(&mut self, record: &str, fields: &[&str], options: &SampleOptions) {
+        let Some(weight) = self.record_weight(fields, options) else {
+            return;
+        };
+        if options.composite {
+            self.add_composite(record, fields, weight, options);
+        } else {
+            for (reservoir_index, field_index) in options.fields.iter().enumerate() {
+                let value = field_value(fields, *field_index, options);
+                self.sample_or_count_missing(
+                    reservoir_index,
+                    value,
+                    weight,
+                    record,
+                    fields,
+                    options,
+                );
+            }
+        }
+        let first_subfield_index = options.num_field_accumulators();
+        for (i, subfield) in options.subfields.iter().enumerate() {
+            let value = resolve_field_index(subfield.field, fields.len()).and_then(|field_index| {
+                let parts: Vec<&str> = fields[field_index].split(&subfield.separator).collect();
+                field_value(&parts, subfield.index, options)
+            });
+            self.sample_or_count_missing(
+                first_subfield_index + i,
+                value,
+                weight,
+                record,
+                fields,
+                options,
+            );
+        }
+        if let Some(reservoir_index) = options.whole_record_index() {
+            self.sample(
+                reservoir_index,
+                &normalize(record, options),
+                weight,
+                options,
+            );
+        }
+    }
+
+    /// Samples a value into the accumulator at `reservoir_index`. If the value is missing,
+    /// `--null-as` is sampled instead if it's given, otherwise the value is counted as missing,
+    /// and with `--verbose` the record it's missing from may be printed.
+    fn sample_or_count_missing(
+        &mut self,
+        reservoir_index: usize,
+        value: Option<Cow<str>>,
+        weight: f64,
+        record: &str,
+        fields: &[&str],
+        options: &SampleOptions,
+    ) {
+        match (value, &options.null_as) {
+            (Some(value), _) => self.sample(reservoir_index, &value, weight, options),
+            (None, Some(null_value)) => self.sample(reservoir_index, null_value, weight, options),
+            (None, None) => self.count_missing(reservoir_index, record, fields, options),
+        }
+    }
+
+    /// Counts a value missing from `record`, printing the record with `--verbose`.
+    fn count_missing(
+        &mut self,
+        reservoir_index: usize,
+        record: &str,
+        fields: &[&str],
+        options: &SampleOptions,
+    ) {
+        self.missing_field_counts[reservoir_index] += 1;
+        if options.verbose {
+            options
+                .missing_field_reports
+                .report(reservoir_index, record, fields.len(), options);
+        }
+    }
+
+    /// Samples the selected fields of a record as one value, joined by `COMPOSITE_SEPARATOR`. The
+    /// record counts as missing if any of them is missing, unless `--null-as` is given.
+    fn add_composite(
+        &mut self,
+        record: &str,
+        fields: &[&str],
+        weight: f64,
+        options: &SampleOptions,
+    ) {
+        let mut value = String::new();
+        for (i, field_index) in options.fields.iter().enumerate() {
+            if i > 0 {
+                value.push_str(COMPOSITE_SEPARATOR);
+            }
+            match (field_value(fields, *field_index, options), &options.null_as) {
+                (Some(field), _) => value.push_str(&field),
+                (None, Some(null_value)) => value.push_str(null_value),
+                (None, None) => {
+                    self.count_missing(0, record, fields, options);
+                    return;
+                }
+            }
+        }
+        self.sample(0, &value, weight, options);
+    }
+
+    /// Merges two `SampledFields`, creating a new struct with the combined results.
+    pub fn merge(pr1: &SampledFields, pr2: &SampledFields) -> SampledFields {
+        SampledFields::merge_many([pr1, pr2])
+    }
+
+    /// Merges the results of sampling several parts of the input, such as the output of parallel
+    /// calls to `process_reader`, in a single pass. Panics if `parts` is empty.
+    pub fn merge_many<'a, I: IntoIterator<Item = &'a SampledFields>>(parts: I) -> SampledFields {
+        let parts: Vec<&SampledFields> = parts.into_iter().collect();
+        let num_fields = parts[0].accumulators.len();
+        let accumulators = (0..num_fields)
+            .map(|i| Accumulator::merge_many(parts.iter().map(|pr| &pr.accumulators[i])))
+            .collect();
+        let missing_field_counts = (0..num_fields)
+            .map(|i| parts.iter().map(|pr| pr.missing_field_counts[i]).sum())
+            .collect();
+        let sketches =
+            parts
+                .iter()
+                .filter_map(|pr| pr.sketches.clone())
+                .reduce(|sketches1, sketches2| {
+                    sketches1
+                        .iter()
+                        .zip(sketches2.iter())
+                        .map(|(h1, h2)| HyperLogLog::merge(h1, h2))
+                        .collect()
+                });
+        let field_counts = parts.iter().filter_map(|pr| pr.field_counts.as_ref()).fold(
+            None,
+            |merged: Option<Vec<u64>>, counts| {
+                let mut merged = merged.unwrap_or_default();
+                if merged.len() < counts.len() {
+                    merged.resize(counts.len(), 0);
+                }
+                merged.iter_mut().zip(counts).for_each(|(m, c)| *m += c);
+                Some(merged)
+            },
+        );
+        SampledFields {
+            accumulators,
+            missing_field_counts,
+            header: parts.iter().find_map(|pr| pr.header.clone()),
+            sketches,
+            field_counts,
+            invalid_line_count: parts.iter().map(|pr| pr.invalid_line_count).sum(),
+            skipped_line_count: parts.iter().map(|pr| pr.skipped_line_count).sum(),
+            invalid_weight_count: parts.iter().map(|pr| pr.invalid_weight_count).sum(),
+            empty_line_count: parts.iter().map(|pr| pr.empty_line_count).sum(),
+            truncated_line_count: parts.iter().map(|pr| pr.truncated_line_count).sum(),
+            total_records: parts.iter().map(|pr| pr.total_records).sum(),
+            total_bytes: parts.iter().map(|pr| pr.total_bytes).sum(),
+            num_splits: parts.iter().map(|pr| pr.num_splits).sum(),
+        }
+    }
+
+    /// Writes the sample of `selection` to `w`: a line of JSON describing the selection, followed
+    /// by one line per accumulator.
+    pub fn save<W: Write>(&self, selection: &Selection, mut w: W) -> io::Result<()> {
+        let Some(first) = self.accumulators.first() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no accumulators to save",
+            ));
+        };
+        let header = StateHeader {
+            version: STATE_VERSION,
+            selection: selection.clone(),
+            missing_field_counts: self.missing_field_counts.clone(),
+            header: self.header.clone(),
+            sketches: self.sketches.clone(),
+            field_counts: self.field_counts.clone(),
+            exact: matches!(first, Accumulator::Exact(_)),
+            heavy_hitters: matches!(first, Accumulator::HeavyHitters(_)),
+            unique: matches!(first, Accumulator::Unique(_)),
+            invalid_line_count: self.invalid_line_count,
+            skipped_line_count: self.skipped_line_count,
+            invalid_weight_count: self.invalid_weight_count,
+            empty_line_count: self.empty_line_count,
+            truncated_line_count: self.truncated_line_count,
+            total_records: self.total_records,
+            total_bytes: self.total_bytes,
+        };
+        serde_json::to_writer(&mut w, &header)?;
+        writeln!(w)?;
+        for accumulator in &self.accumulators {
+            match accumulator {
+                Accumulator::Sample(reservoir) => reservoir.save(&mut w)?,
+                Accumulator::Interned(reservoir) => reservoir.save(&mut w)?,
+                Accumulator::Exact(counts) => {
+                    serde_json::to_writer(&mut w, counts)?;
+                    writeln!(w)?;
+                }
+                Accumulator::HeavyHitters(counters) => {
+                    serde_json::to_writer(&mut w, counters)?;
+                    writeln!(w)?;
+                }
+                Accumulator::Unique(sample) => {
+                    serde_json::to_writer(&mut w, sample)?;
+                    writeln!(w)?;
+                }
+            }
+        }
+        w.flush()
+    }
+
+    /// Reads a sample written by `save`, returning the selection it's a sample of with it.
+    pub fn load<R: Read>(mut r: R) -> io::Result<(Selection, SampledFields)> {
+        let header: StateHeader = read_json(&mut r, "state file is empty")?;
+        if header.version > STATE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "state file is version {}, newer than the version {STATE_VERSION} this \
+                     version of rs-tool reads",
+                    header.version
+                ),
+            ));
+        }
+        if header.missing_field_counts.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "state file has no accumulators",
+            ));
+        }
+        let accumulators = header
+            .missing_field_counts
+            .iter()
+            .map(
+                |_| match (header.exact, header.heavy_hitters, header.unique) {
+                    (true, _, _) => read_json(&mut r, "no counts to load").map(Accumulator::Exact),
+                    (_, true, _) => {
+                        read_json(&mut r, "no heavy hitters to load").map(Accumulator::HeavyHitters)
+                    }
+                    (_, _, true) => {
+                        read_json(&mut r, "no unique sample to load").map(Accumulator::Unique)
+                    }
+                    _ => Reservoir::load(&mut r).map(Accumulator::Sample),
+                },
+            )
+            .collect::<io::Result<_>>()?;
+        let sampled = SampledFields {
+            accumulators,
+            missing_field_counts: header.missing_field_counts,
+            header: header.header,
+            sketches: header.sketches,
+            field_counts: header.field_counts,
+            invalid_line_count: header.invalid_line_count,
+            skipped_line_count: header.skipped_line_count,
+            invalid_weight_count: header.invalid_weight_count,
+            empty_line_count: header.empty_line_count,
+            truncated_line_count: header.truncated_line_count,
+            total_records: header.total_records,
+            total_bytes: header.total_bytes,
+            num_splits: 0,
+        };
+        Ok((header.selection, sampled))
+    }
+}
+
+/// The first line of a state file.
+#[derive(Serialize, Deserialize)]
+struct StateHeader {
+    /// The version of the format, `STATE_VERSION` when written.
+    #[serde(default = "first_state_version")]
+    version: u32,
+    #[serde(flatten)]
+    selection: Selection,
+    missing_field_counts: Vec<u64>,
+    #[serde(default)]
+    header: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sketches: Option<Vec<HyperLogLog>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    field_counts: Option<Vec<u64>>,
+    /// Whether the state holds exact counts rather than samples.
+    #[serde(default)]
+    exact: bool,
+    /// Whether the state holds heavy hitters rather than samples.
+    #[serde(default)]
+    heavy_hitters: bool,
+    /// Whether the state holds unique samples rather than samples.
+    #[serde(default)]
+    unique: bool,
+    #[serde(default)]
+    invalid_line_count: u64,
+    #[serde(default)]
+    skipped_line_count: u64,
+    #[serde(default)]
+    invalid_weight_count: u64,
+    #[serde(default)]
+    empty_line_count: u64,
+    #[serde(default)]
+    truncated_line_count: u64,
+    #[serde(default)]
+    total_records: u64,
+    #[serde(default)]
+    total_bytes: u64,
+}
+
+/// What a sample is of: the fields and subfields selected, whether the fields are sampled together
+/// with `--composite`, and whether whole records are sampled too with `--include-whole-line`.
+/// Samples can only be merged with others of the same selection, since their accumulators sample
+/// different values otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Selection {
+    pub fields: Vec<isize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subfields: Vec<Subfield>,
+    #[serde(default)]
+    pub composite: bool,
+    #[serde(default)]
+    pub include_whole_line: bool,
+}
+
+/// Describes the selection by the options that make it, like `-f 0,2 --composite`.
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut options = Vec::new();
+        if !self.fields.is_empty() {
+            let fields: Vec<String> = self.fields.iter().map(|field| field.to_string()).collect();
+            options.push(format!("-f {}", fields.join(",")));
+        }
+        options.extend(self.subfields.iter().map(|s| format!("--subfield {s}")));
+        if self.composite {
+            options.push("--composite".to_string());
+        }
+        if self.include_whole_line {
+            options.push("--include-whole-line".to_string());
+        }
+        match options.is_empty() {
+            true => write!(f, "whole records"),
+            false => write!(f, "{}", options.join(" ")),
+        }
+    }
+}
+
+/// The version of state files written before the version was recorded.
+fn first_state_version() -> u32 {
+    1
+}
+
+/// Reads the next JSON value from `r`, failing with `eof_message` if there isn't one.
+fn read_json<T: DeserializeOwned, R: Read>(r: R, eof_message: &str) -> io::Result<T> {
+    match serde_json::Deserializer::from_reader(r).into_iter().next() {
+        Some(value) => Ok(value?),
+        None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, eof_message)),
+    }
+}
+
+/// Creates `count` empty accumulators: reservoirs, exact counts with `--exact`, heavy hitters
+/// with `--heavy-hitters`, or unique samples with `--unique-sample`, interned with `--intern`. If
+/// `seed` is given, each reservoir gets its own seed derived from it, and with `--decay` each
+/// decays.
+fn new_accumulators(count: usize, seed: Option<u64>, options: &SampleOptions) -> Vec<Accumulator> {
+    let mut seeds = seed.map(Rng::with_seed);
+    (0..count)
+        .map(|_| match (&mut seeds, options.heavy_hitters) {
+            (_, Some(capacity)) => Accumulator::HeavyHitters(SpaceSaving::new(capacity)),
+            _ if options.exact => Accumulator::Exact(ExactCounts::new()),
+            _ if options.unique_sample => {
+                Accumulator::Unique(UniqueSample::new(options.sample_size))
+            }
+            (seeds, None) if options.intern => {
+                let mut reservoir = match seeds {
+                    Some(rng) => InternedReservoir::with_seed(options.sample_size, rng.u64(..)),
+                    None => InternedReservoir::new(options.sample_size),
+                };
+                if let Some(decay) = options.decay {
+                    reservoir.set_decay(decay);
+                }
+                Accumulator::Interned(reservoir)
+            }
+            (seeds, None) => {
+                let mut reservoir = match seeds {
+                    Some(rng) => Reservoir::with_seed(options.sample_size, rng.u64(..)),
+                    None => Reservoir::new(options.sample_size),
+                };
+                if let Some(decay) = options.decay {
+                    reservoir.set_decay(decay);
+                }
+                Accumulator::Sample(reservoir)
+            }
+        })
+        .collect()
+}
+
+/// Applies `--trim` and `--lowercase` to a value, only copying it if it has to be lowercased.
+fn normalize<'a>(value: &'a str, options: &SampleOptions) -> Cow<'a, str> {
+    let value = if options.trim { value.trim() } else { value };
+    if options.lowercase {
+        Cow::Owned(value.to_lowercase())
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// The normalized value of a field of a record, or `None` if the record is too short to have it.
+/// With `--empty-as-missing`, a field that is empty once normalized is also `None`.
+fn field_value<'a>(
+    fields: &[&'a str],
+    field_index: isize,
+    options: &SampleOptions,
+) -> Option<Cow<'a, str>> {
+    let field = fields[resolve_field_index(field_index, fields.len())?];
+    Some(normalize(field, options)).filter(|value| !(options.empty_as_missing && value.is_empty()))
+}
+
+/// Splits a record into fields with the configured field separator.
+fn split_record<'a>(record: &'a str, options: &SampleOptions) -> Vec<&'a str> {
+    if let Some(fixed_widths) = &options.fixed_widths {
+        return split_fixed_width(record, fixed_widths);
+    }
+    if let Some(regex) = &options.regex_separator {
+        return regex.split(record).collect();
+    }
+    if let Some(separators) = &options.separators {
+        return record.split(|c| separators.contains(c)).collect();
+    }
+    match &options.field_separator {
+        None => record.split_whitespace().collect(),
+        Some(separator) => record.split(separator).collect(),
+    }
+}
+
+/// Cuts a record into the fields at the character ranges of `--fixed-width`, leaving out those
+/// that end past the end of the record.
+fn split_fixed_width<'a>(record: &'a str, fixed_widths: &FixedWidths) -> Vec<&'a str> {
+    let max_end = fixed_widths.last().map_or(0, |range| range.end);
+    // The byte offset of each character position, up to the end of the last range.
+    let offsets: Vec<usize> = record
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain([record.len()])
+        .take(max_end + 1)
+        .collect();
+    fixed_widths
+        .iter()
+        .map_while(|range| Some(&record[*offsets.get(range.start)?..*offsets.get(range.end)?]))
+        .collect()
+}
+
+/// Resolves a field index, as given in `SampleOptions::fields`, against a record with `num_fields`
+/// fields, counting negative indexes back from the end. Returns `None` if the field is out of
+/// range.
+pub fn resolve_field_index(field_index: isize, num_fields: usize) -> Option<usize> {
+    let resolved = if field_index < 0 {
+        num_fields.checked_sub(field_index.unsigned_abs())?
+    } else {
+        field_index as usize
+    };
+    (resolved < num_fields).then_some(resolved)
+}
+
+/// Reads the header record from `reader` and returns the names of its fields.
+pub(crate) fn read_header<T: BufRead>(
+    mut reader: T,
+    options: &SampleOptions,
+) -> io::Result<Vec<String>> {
+    let mut record = Vec::new();
+    read_record(&mut reader, &mut record, options)?;
+    let record = String::from_utf8_lossy(&record);
+    Ok(split_record(&record, options)
+        .into_iter()
+        .map(String::from)
+        .collect())
+}
+
+/// Build one or more reservoirs from the whole of `reader`, first skipping `--skip-lines` records
+/// and reading its header when `--header` is given.
+pub fn process_whole<T: BufRead>(
+    mut reader: T,
+    record_limit: Option<u64>,
+    seed: Option<u64>,
+    options: &SampleOptions,
+) -> io::Result<SampledFields> {
+    let (skipped, header) = read_preamble(&mut reader, options)?;
+    let mut result = process_reader(reader, None, record_limit, seed, options)?;
+    result.header = header;
+    result.skipped_line_count += skipped;
+    result.total_records += skipped;
+    Ok(result)
+}
+
+/// Skips the first `--skip-lines` records of `reader`, then reads its header when `--header` is
+/// given, returning the number of records skipped and the names of the header's fields.
+pub fn read_preamble<T: BufRead>(
+    reader: &mut T,
+    options: &SampleOptions,
+) -> io::Result<(u64, Option<Vec<String>>)> {
+    if options.csv_input {
+        return read_csv_preamble(reader, options);
+    }
+    let skipped = skip_lines(reader, options)?;
+    let header = options
+        .header
+        .then(|| read_header(reader, options))
+        .transpose()?;
+    Ok((skipped, header))
+}
+
+/// Skips the first `--skip-lines` records of `reader` and reads its header for `--csv-input`,
+/// parsing them as CSV like the records after them, so that quoted fields may span lines. The
+/// CSV reader is given a byte at a time, so that it takes none of the records after them.
+fn read_csv_preamble<T: BufRead>(
+    reader: &mut T,
+    options: &SampleOptions,
+) -> io::Result<(u64, Option<Vec<String>>)> {
+    let mut reader = csv_reader_builder(options)?
+        .buffer_capacity(1)
+        .from_reader(reader);
+    let mut record = csv::ByteRecord::new();
+    let mut skipped = 0;
+    while skipped < options.skip_lines && reader.read_byte_record(&mut record)? {
+        skipped += 1;
+    }
+    if !options.header {
+        return Ok((skipped, None));
+    }
+    reader.read_byte_record(&mut record)?;
+    let header = record
+        .iter()
+        .map(|field| String::from_utf8_lossy(field).into_owned())
+        .collect();
+    Ok((skipped, Some(header)))
+}
+
+/// Reads the rest of the record that a chunk of the input ends partway through onto the end of
+/// `chunk`, so that the chunk holds whole records and can be sampled on its own, returning the
+/// number of bytes read. A record longer than `--max-line-bytes` is cut short, but kept longer
+/// than the limit so that it's still counted as truncated when the chunk is sampled.
+pub fn read_rest_of_record<T: BufRead>(
+    reader: &mut T,
+    chunk: &mut Vec<u8>,
+    options: &SampleOptions,
+) -> io::Result<usize> {
+    let max = options.max_line_bytes.map_or(usize::MAX, |max| max + 1);
+    let separator = options.record_separator;
+    let (mut bytes_read, _) = read_until_capped(reader, separator, chunk, max)?;
+    if options.line_ending == LineEnding::Crlf && separator == b'\n' {
+        while !chunk.is_empty() && !chunk.ends_with(b"\r\n") {
+            match read_until_capped(reader, b'\n', chunk, max)?.0 {
+                0 => break,
+                n => bytes_read += n,
+            }
+        }
+    }
+    Ok(bytes_read)
+}
+
+/// Skips the first `--skip-lines` records of `reader`, returning the number skipped.
+pub(crate) fn skip_lines<T: BufRead>(reader: &mut T, options: &SampleOptions) -> io::Result<u64> {
+    let mut skipped = 0;
+    while skipped < options.skip_lines && read_record(reader, &mut Vec::new(), options)?.0 > 0 {
+        skipped += 1;
+    }
+    Ok(skipped)
+}
+
+/// Returns true if `record` starts with the `--comment-char`.
+pub(crate) fn is_comment(record: &[u8], options: &SampleOptions) -> bool {
+    options
+        .comment_char
+        .is_some_and(|c| record.starts_with(c.encode_utf8(&mut [0; 4]).as_bytes()))
+}
+
+/// Reads the bytes of one record from `reader` into `record`, without its separator or line
+/// ending, returning the number of bytes read or 0 at the end of the input, and whether the
+/// record was truncated by `--max-line-bytes`.
+fn read_record<T: BufRead>(
+    reader: &mut T,
+    record: &mut Vec<u8>,
+    options: &SampleOptions,
+) -> io::Result<(usize, bool)> {
+    if options.paragraph_mode {
+        return read_paragraph(reader, record, options);
+    }
+    read_line(reader, record, options)
+}
+
+/// Reads the lines of the next paragraph into `record` for `--paragraph-mode`, joined by
+/// newlines, skipping the blank lines before it. Returns 0 once only blank lines are left.
+fn read_paragraph<T: BufRead>(
+    reader: &mut T,
+    record: &mut Vec<u8>,
+    options: &SampleOptions,
+) -> io::Result<(usize, bool)> {
+    let mut bytes_read = 0;
+    let mut truncated = false;
+    let mut num_lines = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let (n, line_truncated) = read_line(reader, &mut line, options)?;
+        if n == 0 {
+            break;
+        }
+        bytes_read += n;
+        truncated |= line_truncated;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            if num_lines > 0 {
+                break;
+            }
+            continue;
+        }
+        if num_lines > 0 {
+            record.push(b'\n');
+        }
+        record.extend_from_slice(&line);
+        num_lines += 1;
+    }
+    Ok(if num_lines > 0 {
+        (bytes_read, truncated)
+    } else {
+        (0, false)
+    })
+}
+
+/// Reads one line, or one record ended by the `--record-separator`, into `record`, keeping at
+/// most `--max-line-bytes` of it. A truncated record is cut back to the end of its last whole
+/// UTF-8 character.
+fn read_line<T: BufRead>(
+    reader: &mut T,
+    record: &mut Vec<u8>,
+    options: &SampleOptions,
+) -> io::Result<(usize, bool)> {
+    let separator = options.record_separator;
+    let max = options.max_line_bytes.unwrap_or(usize::MAX);
+    let (mut bytes_read, mut truncated) = read_until_capped(reader, separator, record, max)?;
+    if separator != b'\n' {
+        if record.last() == Some(&separator) {
+            record.pop();
+        }
+    } else {
+        if options.line_ending == LineEnding::Crlf {
+            while bytes_read > 0 && !record.ends_with(b"\r\n") {
+                let room = max.saturating_sub(record.len());
+                match read_until_capped(reader, b'\n', record, room)? {
+                    (0, _) => break,
+                    (n, passed_over) => {
+                        bytes_read += n;
+                        truncated |= passed_over;
+                    }
+                }
+            }
+        }
+        if record.ends_with(b"\n") {
+            record.pop();
+            if options.line_ending != LineEnding::Lf && record.ends_with(b"\r") {
+                record.pop();
+            }
+        }
+    }
+    if truncated {
+        if let Err(e) = std::str::from_utf8(record) {
+            if e.error_len().is_none() {
+                record.truncate(e.valid_up_to());
+            }
+        }
+    }
+    Ok((bytes_read, truncated))
+}
+
+/// Decodes a record as UTF-8. Invalid UTF-8 is replaced with U+FFFD when `--lossy` is given,
+/// otherwise the record is rejected with `None`.
+fn decode_record(record: Vec<u8>, options: &SampleOptions) -> Option<String> {
+    match String::from_utf8(record) {
+        Ok(record) => Some(record),
+        Err(e) if options.lossy => Some(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+        Err(_) => None,
+    }
+}
+
+/// Build one or more reservoirs by reading line-separated records from a buffered reader.
+///
+/// This function is meant to be used with 2 sources:
+/// - stdin, in which case this function should consume the whole stream and `read_limit` should
+///   not be specified
+/// - a predetermined chunk of a file, in which case `reader` should be `seek`ed to the starting
+///   point and `read_limit` should indicate the end of the chunk.
+///
+/// With `--csv-input` the whole of `reader` is parsed as CSV and `read_limit` is ignored.
+///
+/// At most `record_limit` records are read, if it's given.
+///
+/// If `seed` is given the reservoirs are seeded from it, making the sample reproducible.
+pub fn process_reader<T: BufRead>(
+    mut reader: T,
+    read_limit: Option<u64>,
+    record_limit: Option<u64>,
+    seed: Option<u64>,
+    options: &SampleOptions,
+) -> io::Result<SampledFields> {
+    if options.csv_input {
+        return process_csv_reader(reader, record_limit, seed, options);
+    }
+    let mut read_count: u64 = 0;
+    let mut record_count: u64 = 0;
+    let mut result = SampledFields::new(seed, options);
+    result.num_splits = 1;
+    while record_limit.is_none_or(|limit| record_count < limit) {
+        // A record belongs to the split it starts in, so stop at the first one starting at or
+        // past the limit. `read_count` counts the separators too, to match the split offsets.
+        if read_limit.is_some_and(|limit| read_count >= limit) {
+            break;
+        }
+        record_count += 1;
+        let mut record = Vec::new();
+        let (bytes_read, truncated) = read_record(&mut reader, &mut record, options)?;
+        if bytes_read == 0 {
+            break;
+        }
+        if truncated {
+            result.truncated_line_count += 1;
+        }
+        read_count += bytes_read as u64;
+        result.total_records += 1;
+        result.total_bytes += bytes_read as u64;
+        if is_comment(&record, options) {
+            result.skipped_line_count += 1;
+            continue;
+        }
+        match decode_record(record, options) {
+            Some(record) if options.skip_empty && record.trim().is_empty() => {
+                result.empty_line_count += 1
+            }
+            Some(record) => result.add_record(&record, options),
+            None => result.invalid_line_count += 1,
+        }
+    }
+    Ok(result)
+}
+
+/// Configures a CSV reader for `--csv-input`, using `-s` as the delimiter if it's given, which
+/// must then be a single byte.
+fn csv_reader_builder(options: &SampleOptions) -> io::Result<csv::ReaderBuilder> {
+    let mut builder = csv::ReaderBuilder::new();
+    builder.has_headers(false).flexible(true);
+    if let Some(separator) = &options.field_separator {
+        let &[delimiter] = separator.as_bytes() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the field separator {separator:?} isn't a single byte, as CSV needs"),
+            ));
+        };
+        builder.delimiter(delimiter);
+    }
+    if options.record_separator != b'\n' {
+        builder.terminator(csv::Terminator::Any(options.record_separator));
+    }
+    Ok(builder)
+}
+
+/// Build one or more reservoirs by parsing CSV records from a buffered reader. Quoted fields may
+/// contain the delimiter or span several lines. When no fields are selected, each record is
+/// sampled with its fields rejoined by the delimiter. At most `record_limit` records are read, if
+/// it's given.
+fn process_csv_reader<T: BufRead>(
+    reader: T,
+    record_limit: Option<u64>,
+    seed: Option<u64>,
+    options: &SampleOptions,
+) -> io::Result<SampledFields> {
+    let mut result = SampledFields::new(seed, options);
+    result.num_splits = 1;
+    let mut reader = csv_reader_builder(options)?.from_reader(reader);
+    let mut record = csv::ByteRecord::new();
+    while record_limit.is_none_or(|limit| result.total_records < limit)
+        && reader.read_byte_record(&mut record)?
+    {
+        result.total_records += 1;
+        result.total_bytes = reader.position().byte();
+        if record
+            .get(0)
+            .is_some_and(|field| is_comment(field, options))
+        {
+            result.skipped_line_count += 1;
+            continue;
+        }
+        let fields: Option<Vec<String>> = record
+            .iter()
+            .map(|field| decode_record(field.to_vec(), options))
+            .collect();
+        let Some(record) = fields else {
+            result.invalid_line_count += 1;
+            continue;
+        };
+        if options.skip_empty && matches!(record.as_slice(), [field] if field.trim().is_empty()) {
+            result.empty_line_count += 1;
+            continue;
+        }
+        let fields: Vec<&str> = record.iter().map(String::as_str).collect();
+        let delimiter = options.field_separator.as_deref().unwrap_or(",");
+        result.add_split_record(&fields, delimiter, options);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn test_resolve_field_index() {
+        assert_eq!(Some(0), resolve_field_index(0, 3));
+        assert_eq!(Some(2), resolve_field_index(2, 3));
+        assert_eq!(None, resolve_field_index(3, 3));
+        assert_eq!(Some(2), resolve_field_index(-1, 3));
+        assert_eq!(Some(0), resolve_field_index(-3, 3));
+        assert_eq!(None, resolve_field_index(-4, 3));
+        assert_eq!(None, resolve_field_index(-1, 0));
+    }
+
+    #[test]
+    fn test_csv_input_quoted_fields() {
+        let options = SampleOptions {
+            fields: vec![1].into(),
+            csv_input: true,
+            ..Default::default()
+        };
+        let input = "x,\"a, b\"\ny,\"multi\nline\"\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&0.5f32), h.get(&"a, b".to_string()));
+        assert_eq!(Some(&0.5f32), h.get(&"multi\nline".to_string()));
+    }
+
+    #[test]
+    fn test_csv_input_multiline_header() {
+        let options = SampleOptions {
+            fields: vec![1].into(),
+            csv_input: true,
+            header: true,
+            skip_lines: 1,
+            ..Default::default()
+        };
+        let input = "\"skipped\nrecord\"\n\"id\",\"long\nname\"\n1,a\n2,b\n";
+        let pr = process_whole(Cursor::new(input), None, None, &options).unwrap();
+        assert_eq!(
+            Some(vec!["id".to_string(), "long\nname".to_string()]),
+            pr.header
+        );
+        assert_eq!(1, pr.skipped_line_count);
+        assert_eq!(3, pr.total_records);
+        assert_eq!(vec![0], pr.missing_field_counts);
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&0.5f32), h.get(&"a".to_string()));
+        assert_eq!(Some(&0.5f32), h.get(&"b".to_string()));
+    }
+
+    #[test]
+    fn test_csv_input_rejects_multibyte_separator() {
+        for separator in ["", "::", "é"] {
+            let options = SampleOptions {
+                csv_input: true,
+                field_separator: Some(separator.to_string()),
+                ..Default::default()
+            };
+            let err = process_reader(Cursor::new("a\n"), None, None, None, &options).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_crlf_stripped() {
+        let options = SampleOptions {
+            fields: vec![1].into(),
+            ..Default::default()
+        };
+        let input = "a 200\r\nb 404\r\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert!(h.keys().all(|val| !val.contains('\r')), "{h:?}");
+        assert_eq!(Some(&0.5f32), h.get(&"200".to_string()));
+    }
+
+    #[test]
+    fn test_crlf_records_keep_bare_newlines() {
+        let options = SampleOptions {
+            line_ending: LineEnding::Crlf,
+            ..Default::default()
+        };
+        let input = "a\nb\r\nc\r\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&0.5f32), h.get(&"a\nb".to_string()));
+        assert_eq!(Some(&0.5f32), h.get(&"c".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_utf8_skipped_and_counted() {
+        let options = SampleOptions::default();
+        let input = b"a\n\xff\xfe\na\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(1, pr.invalid_line_count);
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&1.0f32), h.get(&"a".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_utf8_lossy() {
+        let options = SampleOptions {
+            lossy: true,
+            ..Default::default()
+        };
+        let input = b"a\n\xffb\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(0, pr.invalid_line_count);
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&0.5f32), h.get(&"\u{fffd}b".to_string()));
+    }
+
+    /// A reader that fails after returning `data`.
+    struct FailingReader<'a> {
+        data: &'a [u8],
+    }
+
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.data.is_empty() {
+                return Err(io::Error::other("read failed"));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_read_error_returned() {
+        let reader = BufReader::new(FailingReader { data: b"a\nb\n" });
+        let e = process_reader(reader, None, None, None, &SampleOptions::default()).unwrap_err();
+        assert_eq!("read failed", e.to_string());
+    }
+
+    #[test]
+    fn test_record_limit() {
+        let options = SampleOptions::default();
+        let pr = process_reader(Cursor::new("a\nb\nc\n"), None, Some(2), None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(2, h.len());
+        assert!(!h.contains_key(&"c".to_string()));
+    }
+
+    #[test]
+    fn test_skip_lines_and_comments() {
+        let options = SampleOptions {
+            skip_lines: 2,
+            comment_char: Some('#'),
+            ..Default::default()
+        };
+        let input = "meta 1\nmeta 2\na\n# comment\nb\n";
+        let pr = process_whole(Cursor::new(input), None, None, &options).unwrap();
+        assert_eq!(3, pr.skipped_line_count);
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&0.5f32), h.get(&"a".to_string()));
+        assert_eq!(Some(&0.5f32), h.get(&"b".to_string()));
+    }
+
+    #[test]
+    fn test_skip_empty() {
+        let input = "a\n\n \t\nb\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &Default::default()).unwrap();
+        assert_eq!(0, pr.empty_line_count);
+        assert_eq!(4, pr.accumulators[0].num_adds());
+
+        let options = SampleOptions {
+            skip_empty: true,
+            ..Default::default()
+        };
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(2, pr.empty_line_count);
+        assert_eq!(4, pr.total_records);
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&0.5f32), h.get(&"a".to_string()));
+        assert_eq!(Some(&0.5f32), h.get(&"b".to_string()));
+        let merged = SampledFields::merge(&pr, &pr);
+        assert_eq!(4, merged.empty_line_count);
+    }
+
+    #[test]
+    fn test_verbose_reports_are_capped() {
+        let options = SampleOptions {
+            fields: FieldIndexes(vec![1, 2]),
+            verbose: true,
+            ..Default::default()
+        };
+        let input = "a b c\na b\n".repeat(8);
+        // Parts of the input sampled separately share the cap through the cloned options.
+        for _ in 0..2 {
+            let pr =
+                process_reader(Cursor::new(&input), None, None, None, &options.clone()).unwrap();
+            assert_eq!(vec![0, 8], pr.missing_field_counts);
+        }
+        let counts = options.missing_field_reports.0.lock().unwrap().clone();
+        assert_eq!(vec![0, MAX_MISSING_FIELD_REPORTS], counts);
+    }
+
+    #[test]
+    fn test_decay() {
+        let options = SampleOptions {
+            sample_size: 10,
+            decay: Some(0.01),
+            ..Default::default()
+        };
+        let input: String = (0..10000).map(|i| format!("{i}\n")).collect();
+        let pr = process_reader(Cursor::new(input), None, None, Some(1), &options).unwrap();
+        let Accumulator::Sample(reservoir) = &pr.accumulators[0] else {
+            panic!("expected a sample");
+        };
+        assert_eq!(0.01, reservoir.decay());
+        assert!(reservoir
+            .iter()
+            .all(|value| value.parse::<u32>().unwrap() >= 9000));
+        assert_eq!(Ok(0.5), parse_decay("0.5"));
+        assert!(parse_decay("0").is_err());
+        assert!(parse_decay("inf").is_err());
+    }
+
+    #[test]
+    fn test_splits_read_each_record_once() {
+        let input: String = (0..1000)
+            .map(|i| format!("{}\n", "x".repeat(i % 37)))
+            .collect();
+        let options = SampleOptions::default();
+        for split_size in [1, 50, 64, 1000, 100000] {
+            let splits = crate::get_splits(Cursor::new(&input), split_size, b'\n').unwrap();
+            let parts: Vec<SampledFields> = splits
+                .iter()
+                .map(|split| {
+                    let mut reader = Cursor::new(&input);
+                    reader.set_position(split.start);
+                    let limit = split.end - split.start;
+                    process_reader(reader, Some(limit), None, None, &options).unwrap()
+                })
+                .collect();
+            let merged = SampledFields::merge_many(&parts);
+            assert_eq!(1000, merged.total_records, "split size {split_size}");
+            assert_eq!(input.len() as u64, merged.total_bytes);
+        }
+    }
+
+    #[test]
+    fn test_max_line_bytes() {
+        let options = SampleOptions {
+            max_line_bytes: Some(4),
+            ..Default::default()
+        };
+        let input = "abc\nabcdefgh\nabcd\nab\u{e9}\u{e9}\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(2, pr.truncated_line_count);
+        assert_eq!(0, pr.invalid_line_count);
+        assert_eq!(input.len() as u64, pr.total_bytes);
+        let h = pr.accumulators[0].to_histogram();
+        // The cut-off character is dropped rather than leaving invalid UTF-8.
+        let mut values: Vec<&str> = h.keys().map(|value| value.as_str()).collect();
+        values.sort();
+        assert_eq!(vec!["abc", "abcd", "ab\u{e9}"], values);
+
+        let options = SampleOptions {
+            max_line_bytes: Some(2),
+            line_ending: LineEnding::Crlf,
+            ..Default::default()
+        };
+        let input = "abc\ndef\r\nxy\r\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(2, pr.total_records);
+        assert_eq!(1, pr.truncated_line_count);
+    }
+
+    #[test]
+    fn test_lowercase_and_trim() {
+        let options = SampleOptions {
+            fields: vec![0].into(),
+            field_separator: Some(",".to_string()),
+            lowercase: true,
+            trim: true,
+            ..Default::default()
+        };
+        let input = "GET,1\nget,2\n get ,3\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(1, h.len());
+        assert_eq!(Some(&1.0f32), h.get(&"get".to_string()));
+    }
+
+    #[test]
+    fn test_lowercase_collapses_case() {
+        let options = SampleOptions {
+            lowercase: true,
+            ..Default::default()
+        };
+        let input = "Get\nGET\nget\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(1, h.len());
+        assert_eq!(Some(&1.0f32), h.get(&"get".to_string()));
+    }
+
+    #[test]
+    fn test_include_whole_line() {
+        let options = SampleOptions {
+            fields: vec![1].into(),
+            include_whole_line: true,
+            ..Default::default()
+        };
+        assert_eq!(Some(1), options.whole_record_index());
+        let input = "a x\nb x\nc\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(2, pr.accumulators.len());
+        assert_eq!(vec![1, 0], pr.missing_field_counts);
+        assert_eq!(
+            Some(&1.0f32),
+            pr.accumulators[0].to_histogram().get(&"x".to_string())
+        );
+        let h = pr.accumulators[1].to_histogram();
+        assert_eq!(3, h.len());
+        assert_eq!(Some(&(1.0f32 / 3.0)), h.get(&"c".to_string()));
+    }
+
+    #[test]
+    fn test_record_separator() {
+        let options = SampleOptions {
+            record_separator: b'\0',
+            ..Default::default()
+        };
+        let input = "a\nb\0c\0a\nb\0";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&(2.0f32 / 3.0)), h.get(&"a\nb".to_string()));
+        assert_eq!(Some(&(1.0f32 / 3.0)), h.get(&"c".to_string()));
+    }
+
+    #[test]
+    fn test_paragraph_mode() {
+        let options = SampleOptions {
+            paragraph_mode: true,
+            ..Default::default()
+        };
+        let input = "\na\nb\n\n\nc\r\n \t\r\na\nb\n\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&(2.0f32 / 3.0)), h.get(&"a\nb".to_string()));
+        assert_eq!(Some(&(1.0f32 / 3.0)), h.get(&"c".to_string()));
+        assert_eq!(3, pr.total_records);
+    }
+
+    #[test]
+    fn test_parse_record_separator() {
+        assert_eq!(Ok(b'\0'), parse_record_separator("\\0"));
+        assert_eq!(Ok(b';'), parse_record_separator(";"));
+        assert!(parse_record_separator("ab").is_err());
+        assert_eq!(Ok(b'\\'), parse_record_separator("\\\\"));
+        assert_eq!(Ok(b'\\'), parse_record_separator("\\"));
+    }
+
+    #[test]
+    fn test_separators() {
+        let options = SampleOptions {
+            fields: vec![1].into(),
+            separators: Some(",;".to_string()),
+            ..Default::default()
+        };
+        let input = "a,b;c\nd;b,e\nf,g\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(Some(&(2.0f32 / 3.0)), h.get(&"b".to_string()));
+        assert_eq!(Some(&(1.0f32 / 3.0)), h.get(&"g".to_string()));
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        let options = SampleOptions {
+            fields: vec![0, 1, 2].into(),
+            fixed_widths: Some(parse_fixed_widths("0:3,3:5,5:8").unwrap()),
+            ..Default::default()
+        };
+        let input = "abcde123\nxyzé\nabcdefgh\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(vec![0, 1, 1], pr.missing_field_counts);
+        assert_eq!(
+            Some(&1.0f32),
+            pr.accumulators[1].to_histogram().get(&"de".to_string())
+        );
+        // Positions count characters, so the two-byte `é` is a single position.
+        assert_eq!(
+            vec!["xyz", "éf"],
+            split_fixed_width("xyzéf", options.fixed_widths.as_ref().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_fixed_widths() {
+        assert_eq!(
+            Ok(FixedWidths(vec![0..10, 10..14, 12..20])),
+            parse_fixed_widths("0:10,10:14,12:20")
+        );
+        assert!(parse_fixed_widths("0-10").is_err());
+        assert!(parse_fixed_widths("5:5").is_err());
+        assert!(parse_fixed_widths("0:10,2:4").is_err());
+    }
+
+    #[test]
+    fn test_parse_field_separator() {
+        assert_eq!(Ok("\t".to_string()), parse_field_separator("\\t"));
+        assert_eq!(Ok("\0|".to_string()), parse_field_separator("\\0|"));
+        assert_eq!(Ok("a\\b\\".to_string()), parse_field_separator("a\\b\\"));
+        assert_eq!(Ok("\\t".to_string()), parse_field_separator("\\\\t"));
+        assert_eq!(Ok(", ".to_string()), parse_field_separator(", "));
+        assert!(parse_field_separator("").is_err());
+    }
+
+    #[test]
+    fn test_composite() {
+        let options = SampleOptions {
+            fields: vec![0, -1].into(),
+            composite: true,
+            ..Default::default()
+        };
+        let input = "GET /a 200\nGET /b 404\nPOST /a 200\nGET /c 200\nHEAD\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(1, pr.accumulators.len());
+        let h = pr.accumulators[0].to_histogram();
+        assert_eq!(4, h.len());
+        assert_eq!(Some(&0.4f32), h.get(&"GET\t200".to_string()));
+        assert_eq!(Some(&0.2f32), h.get(&"GET\t404".to_string()));
+        assert_eq!(Some(&0.2f32), h.get(&"POST\t200".to_string()));
+        assert_eq!(Some(&0.2f32), h.get(&"HEAD\tHEAD".to_string()));
+        assert_eq!(vec![0], pr.missing_field_counts);
+
+        let options = SampleOptions {
+            fields: vec![0, 2].into(),
+            ..options
+        };
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(vec![1], pr.missing_field_counts);
+        assert_eq!(4, pr.accumulators[0].num_adds());
+    }
+
+    #[test]
+    fn test_standard_error() {
+        let mut reservoir = Reservoir::new(100);
+        for value in ["a", "a", "a", "b"] {
+            reservoir.add(value.to_string());
+        }
+        let sample = Accumulator::Sample(reservoir);
+        assert_eq!((0.75f64 * 0.25 / 4.0).sqrt(), sample.standard_error(0.75));
+        assert_eq!(0.0, sample.standard_error(1.0));
+        assert_eq!(
+            0.0,
+            Accumulator::Sample(Reservoir::new(10)).standard_error(0.0)
+        );
+        assert_eq!(
+            0.0,
+            Accumulator::Exact(ExactCounts::new()).standard_error(0.5)
+        );
+    }
+
+    #[test]
+    fn test_parse_field_indexes() {
+        assert_eq!(Ok(vec![3]), parse_field_indexes("3"));
+        assert_eq!(Ok(vec![-1]), parse_field_indexes("-1"));
+        assert_eq!(Ok(vec![0, 1, 2, 3, 4]), parse_field_indexes("0-4"));
+        assert_eq!(Ok(vec![2, 5]), parse_field_indexes("2,5"));
+        assert_eq!(Ok(vec![0, 1, 2, 7]), parse_field_indexes("0-2,7"));
+        assert_eq!(Ok(vec![-3, -2, -1]), parse_field_indexes("-3--1"));
+        assert_eq!(Ok(vec![4]), parse_field_indexes("4-4"));
+        for invalid in ["", "a", "1,", "4-2", "0--1", "1-", "-2-1"] {
+            assert!(parse_field_indexes(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_empty_as_missing() {
+        let options = SampleOptions {
+            fields: vec![1].into(),
+            field_separator: Some(",".to_string()),
+            ..Default::default()
+        };
+        let input = "a,,c\na,b,c\na\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(vec![1], pr.missing_field_counts);
+        assert_eq!(
+            Some(&0.5f32),
+            pr.accumulators[0].to_histogram().get(&String::new())
+        );
+
+        let options = SampleOptions {
+            empty_as_missing: true,
+            ..options
+        };
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(vec![2], pr.missing_field_counts);
+        assert_eq!(1, pr.accumulators[0].num_adds());
+
+        let options = SampleOptions {
+            null_as: Some("-".to_string()),
+            ..options
+        };
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(
+            Some(&(2.0f32 / 3.0)),
+            pr.accumulators[0].to_histogram().get(&"-".to_string())
+        );
+    }
+
+    #[test]
+    fn test_subfields() {
+        let options = SampleOptions {
+            fields: vec![0].into(),
+            subfields: vec![
+                parse_subfield("1:,:1").unwrap(),
+                parse_subfield("-1:::-1").unwrap(),
+            ],
+            field_separator: Some("|".to_string()),
+            ..Default::default()
+        };
+        let input = "a|x,y|k:v\nb|z|k\nc\n";
+        let pr = process_reader(Cursor::new(input), None, None, None, &options).unwrap();
+        assert_eq!(3, pr.accumulators.len());
+        assert_eq!(vec![0, 2, 0], pr.missing_field_counts);
+        let h = pr.accumulators[1].to_histogram();
+        assert_eq!(1, h.len());
+        assert_eq!(Some(&1.0f32), h.get(&"y".to_string()));
+        let h = pr.accumulators[2].to_histogram();
+        assert_eq!(Some(&(1.0f32 / 3.0)), h.get(&"v".to_string()));
+        assert_eq!(Some(&(1.0f32 / 3.0)), h.get(&"k".to_string()));
+        assert_eq!(Some(&(1.0f32 / 3.0)), h.get(&"c".to_string()));
+    }
+
+    #[test]
+    fn test_intern() {
+        let input = "a x\nb y\na z\nc x\n".repeat(1000);
+        let sample = |intern| {
+            let options = SampleOptions {
+                sample_size: 50,
+                fields: vec![0, 1].into(),
+                intern,
+                ..Default::default()
+            };
+            process_reader(Cursor::new(&input), None, None, Some(3), &options).unwrap()
+        };
+        let (plain, interned) = (sample(false), sample(true));
+        assert!(matches!(interned.accumulators[0], Accumulator::Interned(_)));
+        for (p, i) in plain.accumulators.iter().zip(&interned.accumulators) {
+            assert_eq!(p.to_histogram(), i.to_histogram());
+            assert_eq!(p.num_adds(), i.num_adds());
+        }
+
+        // A saved interned sample loads as a plain one, which merges with an interned one.
+        let mut saved = Vec::new();
+        let selection = Selection {
+            fields: vec![0, 1],
+            ..Default::default()
+        };
+        interned.save(&selection, &mut saved).unwrap();
+        let (_, loaded) = SampledFields::load(saved.as_slice()).unwrap();
+        assert!(matches!(loaded.accumulators[0], Accumulator::Sample(_)));
+        let merged = Accumulator::merge(&loaded.accumulators[1], &interned.accumulators[1]);
+        assert!(matches!(merged, Accumulator::Interned(_)));
+        assert_eq!(8000, merged.num_adds());
+    }
+
+    #[test]
+    fn test_field_count_histogram() {
+        let options = SampleOptions {
+            fields: vec![0].into(),
+            field_count_histogram: true,
+            skip_empty: true,
+            ..Default::default()
+        };
+        let pr1 =
+            process_reader(Cursor::new("a b\nc\n\nd e\n"), None, None, None, &options).unwrap();
+        assert_eq!(Some(vec![0, 1, 2]), pr1.field_counts);
+        let pr2 = process_reader(Cursor::new("a b c d\n"), None, None, None, &options).unwrap();
+        let merged = SampledFields::merge(&pr1, &pr2);
+        assert_eq!(Some(vec![0, 1, 2, 0, 1]), merged.field_counts);
+
+        let mut saved = Vec::new();
+        merged.save(&options.selection(), &mut saved).unwrap();
+        let (_, loaded) = SampledFields::load(saved.as_slice()).unwrap();
+        assert_eq!(merged.field_counts, loaded.field_counts);
+
+        let unsplit =
+            process_reader(Cursor::new("a b\n"), None, None, None, &Default::default()).unwrap();
+        assert_eq!(None, unsplit.field_counts);
+    }
+
+    #[test]
+    fn test_state_version() {
+        let mut pr =
+            process_reader(Cursor::new("a\n"), None, None, None, &Default::default()).unwrap();
+        let mut saved = Vec::new();
+        pr.save(&Selection::default(), &mut saved).unwrap();
+        let saved = String::from_utf8(saved).unwrap();
+        let version = format!("\"version\":{STATE_VERSION},");
+        assert!(saved.contains(&version));
+
+        // Files from before the version was recorded still load, but not those of later versions.
+        let unversioned = saved.replacen(&version, "", 1);
+        assert!(SampledFields::load(unversioned.as_bytes()).is_ok());
+        let newer = saved.replacen(&version, &format!("\"version\":{},", STATE_VERSION + 1), 1);
+        let err = SampledFields::load(newer.as_bytes()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // Neither saving nor loading a state without accumulators panics.
+        let empty = r#"{"fields":[],"missing_field_counts":[]}"#;
+        let err = SampledFields::load(empty.as_bytes()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        pr.accumulators.clear();
+        let err = pr.save(&Selection::default(), Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_weight_by_length() {
+        let options = SampleOptions {
+            sample_size: 100,
+            weight_by: Some(WeightBy::Length),
+            ..Default::default()
+        };
+        let input = "a\naaaaaaaaa\n\n".repeat(5000);
+        let pr = process_reader(Cursor::new(input), None, None, Some(3), &options).unwrap();
+        let h = pr.accumulators[0].to_histogram();
+        let long = h.get(&"aaaaaaaaa".to_string()).unwrap();
+        assert!(*long > 0.8, "long == {long}");
+        // Empty values have no length, so they're never sampled.
+        assert_eq!(None, h.get(&String::new()));
+    }
+
+    #[test]
+    fn test_weight_by_field() {
+        let options = SampleOptions {
+            sample_size: 100,
+            fields: vec![0].into(),
+            weight_by: Some(WeightBy::Field(1)),
+            field_separator: Some(",".to_string()),
+            ..Default::default()
+        };
+        let input = "heavy,9\nlight,1\n".repeat(5000) + "bad,x\nshort\n";
+        let pr = process_reader(Cursor::new(input), None, None, Some(3), &options).unwrap();
+        assert_eq!(2, pr.invalid_weight_count);
+        let h = pr.accumulators[0].to_histogram();
+        let heavy = h.get(&"heavy".to_string()).unwrap();
+        assert!(*heavy > 0.8, "heavy == {heavy}");
+        assert_eq!(None, h.get(&"bad".to_string()));
+
+        let whole = SampleOptions {
+            fields: FieldIndexes::default(),
+            ..options
+        };
+        let pr = process_reader(Cursor::new("a,2\nb,?\n"), None, None, None, &whole).unwrap();
+        assert_eq!(1, pr.invalid_weight_count);
+        assert_eq!(
+            vec!["a,2"],
+            pr.accumulators[0]
+                .to_histogram()
+                .into_keys()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_weight_by() {
+        assert_eq!(Ok(WeightBy::Length), parse_weight_by("length"));
+        assert_eq!(Ok(WeightBy::Field(-2)), parse_weight_by("-2"));
+        assert!(parse_weight_by("size").is_err());
+    }
+
+    #[test]
+    fn test_parse_subfield() {
+        let subfield = parse_subfield("3:, :-1").unwrap();
+        assert_eq!(
+            (3, ", ", -1),
+            (subfield.field, subfield.separator.as_str(), subfield.index)
+        );
+        assert_eq!(":", parse_subfield("0:::2").unwrap().separator);
+        for invalid in ["3", "3:,", "3::1", "a:,:1", "3:,:b"] {
+            assert!(parse_subfield(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::cmp::{max, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+/// One tracked item and its estimated count. The item was added at most `count` times, and at
+/// least `count - error` times.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Counter<T> {
+    item: T,
+    count: u64,
+    error: u64,
+}
+
+/// The approximate counts of the most frequent items, kept with the Space-Saving algorithm of
+/// Metwally, Agrawal and El Abbadi in a fixed number of counters. Once every counter is in use, a
+/// new item takes over the counter with the smallest count, inheriting that count as its error.
+///
+/// Unlike a `Reservoir`, the result is deterministic, and any item added more than
+/// `total / capacity` times is guaranteed to be tracked.
+#[derive(Debug)]
+pub struct SpaceSaving<T> {
+    capacity: usize,
+    counters: Vec<Counter<T>>,
+    /// The index in `counters` of each tracked item.
+    index: HashMap<T, usize>,
+    /// Min-heap over the counts of `counters`, with one entry per counter. Counts only grow, so an
+    /// entry may hold a stale count below the counter's, which is refreshed when it's popped.
+    by_count: BinaryHeap<Reverse<(u64, usize)>>,
+    total: u64,
+}
+
+impl<T: Eq + Hash + Clone> SpaceSaving<T> {
+    pub fn new(capacity: usize) -> SpaceSaving<T> {
+        SpaceSaving {
+            capacity,
+            counters: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+            by_count: BinaryHeap::with_capacity(capacity),
+            total: 0,
+        }
+    }
+
+    pub fn add(&mut self, item: T) {
+        self.add_borrowed(&item)
+    }
+
+    /// Adds a borrowed item, only making an owned copy of it when it takes a counter.
+    pub fn add_borrowed<Q>(&mut self, item: &Q)
+    where
+        T: Borrow<Q>,
+        Q: ToOwned<Owned = T> + Eq + Hash + ?Sized,
+    {
+        self.total += 1;
+        if let Some(&i) = self.index.get(item) {
+            self.counters[i].count += 1;
+            return;
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        let counter = if self.counters.len() < self.capacity {
+            self.counters.push(Counter {
+                item: item.to_owned(),
+                count: 1,
+                error: 0,
+            });
+            self.counters.len() - 1
+        } else {
+            let i = self.pop_smallest();
+            let smallest = self.counters[i].count;
+            self.index.remove::<T>(&self.counters[i].item);
+            self.counters[i] = Counter {
+                item: item.to_owned(),
+                count: smallest + 1,
+                error: smallest,
+            };
+            i
+        };
+        self.index
+            .insert(self.counters[counter].item.clone(), counter);
+        self.by_count
+            .push(Reverse((self.counters[counter].count, counter)));
+    }
+
+    /// Removes the heap entry of the counter with the smallest count and returns its index.
+    fn pop_smallest(&mut self) -> usize {
+        loop {
+            let Reverse((count, i)) = self.by_count.pop().expect("no counters");
+            if count == self.counters[i].count {
+                return i;
+            }
+            self.by_count.push(Reverse((self.counters[i].count, i)));
+        }
+    }
+
+    /// The count every untracked item is below: the smallest count once every counter is in use,
+    /// and otherwise 0, since every item added so far is tracked.
+    fn floor(&self) -> u64 {
+        match self.counters.len() {
+            len if len > 0 && len == self.capacity => {
+                self.counters.iter().map(|c| c.count).min().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Builds a summary from counters, keeping the `capacity` with the largest counts.
+    fn from_counters(capacity: usize, mut counters: Vec<Counter<T>>, total: u64) -> SpaceSaving<T> {
+        if counters.len() > capacity {
+            counters.select_nth_unstable_by(capacity, |c1, c2| c2.count.cmp(&c1.count));
+            counters.truncate(capacity);
+        }
+        SpaceSaving {
+            capacity,
+            index: counters
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (c.item.clone(), i))
+                .collect(),
+            by_count: counters
+                .iter()
+                .enumerate()
+                .map(|(i, c)| Reverse((c.count, i)))
+                .collect(),
+            counters,
+            total,
+        }
+    }
+
+    /// Combines two summaries, as if every item had been added to a single one.
+    pub fn merge(s1: &SpaceSaving<T>, s2: &SpaceSaving<T>) -> SpaceSaving<T> {
+        SpaceSaving::merge_many([s1, s2])
+    }
+
+    /// Combines any number of summaries in a single pass. An item a summary doesn't track may have
+    /// been added to it as many times as its `floor`, so that is added to both the item's count
+    /// and its error, keeping the bounds of every count valid. The merged summary has the largest
+    /// capacity of those merged. Items are kept in the order they're first found in, so that the
+    /// items left out on a tie in their counts depend only on the order of `summaries`.
+    pub fn merge_many<'a, I>(summaries: I) -> SpaceSaving<T>
+    where
+        I: IntoIterator<Item = &'a SpaceSaving<T>>,
+        T: 'a,
+    {
+        let mut capacity = 0;
+        let mut total = 0;
+        let mut floors = 0;
+        // The summed count and error of each item over the summaries tracking it, with the sum of
+        // the floors of those summaries, and the index of each item's sums.
+        let mut sums: Vec<(&T, u64, u64, u64)> = Vec::new();
+        let mut index: HashMap<&T, usize> = HashMap::new();
+        for s in summaries {
+            capacity = max(capacity, s.capacity);
+            total += s.total;
+            let floor = s.floor();
+            floors += floor;
+            for c in &s.counters {
+                let i = *index.entry(&c.item).or_insert_with(|| {
+                    sums.push((&c.item, 0, 0, 0));
+                    sums.len() - 1
+                });
+                sums[i].1 += c.count;
+                sums[i].2 += c.error;
+                sums[i].3 += floor;
+            }
+        }
+        let counters = sums
+            .into_iter()
+            .map(|(item, count, error, tracked_floors)| Counter {
+                item: item.clone(),
+                count: count + floors - tracked_floors,
+                error: error + floors - tracked_floors,
+            })
+            .collect();
+        SpaceSaving::from_counters(capacity, counters, total)
+    }
+}
+
+impl<T: Eq + Hash> SpaceSaving<T> {
+    /// The number of items added, including those that aren't tracked.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The estimated count of a tracked item and the most it may exceed the true count by, or
+    /// `None` if the item isn't tracked.
+    pub fn estimate<Q>(&self, item: &Q) -> Option<(u64, u64)>
+    where
+        T: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let counter = &self.counters[*self.index.get(item)?];
+        Some((counter.count, counter.error))
+    }
+
+    /// Iterates over each tracked item and its estimated count.
+    pub fn iter(&self) -> impl Iterator<Item = (&T, u64)> {
+        self.counters.iter().map(|c| (&c.item, c.count))
+    }
+
+    /// The estimated count of each tracked item, and the number of items added, which the counts
+    /// are a share of.
+    pub fn to_counts(&self) -> (HashMap<&T, u64>, u64) {
+        (self.iter().collect(), self.total)
+    }
+
+    /// The estimated frequency of each tracked item among every item added. Untracked items
+    /// aren't included, so the frequencies may sum to less than 1.
+    pub fn to_histogram(&self) -> HashMap<&T, f32> {
+        self.counters
+            .iter()
+            .map(|c| (&c.item, c.count as f32 / self.total as f32))
+            .collect()
+    }
+}
+
+/// The saved form of a `SpaceSaving`, without the index and heap rebuilt when it's loaded.
+#[derive(Serialize, Deserialize)]
+struct SpaceSavingState<C> {
+    capacity: usize,
+    counters: C,
+    total: u64,
+}
+
+impl<T: Serialize> Serialize for SpaceSaving<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SpaceSavingState {
+            capacity: self.capacity,
+            counters: &self.counters,
+            total: self.total,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Eq + Hash + Clone> Deserialize<'de> for SpaceSaving<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state: SpaceSavingState<Vec<Counter<T>>> = SpaceSavingState::deserialize(deserializer)?;
+        if state.counters.len() > state.capacity {
+            return Err(de::Error::custom(
+                "heavy hitters hold more counters than their capacity",
+            ));
+        }
+        Ok(SpaceSaving::from_counters(
+            state.capacity,
+            state.counters,
+            state.total,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracks_every_item_below_capacity() {
+        let mut s = SpaceSaving::new(3);
+        for item in ["a", "b", "a", "c", "a"] {
+            s.add(item);
+        }
+        assert_eq!(Some((3, 0)), s.estimate("a"));
+        assert_eq!(Some((1, 0)), s.estimate("c"));
+        assert_eq!(5, s.total());
+        assert_eq!(Some(&0.6f32), s.to_histogram().get(&"a"));
+    }
+
+    #[test]
+    fn test_evicts_smallest_count() {
+        let mut s = SpaceSaving::new(2);
+        for item in ["a", "a", "a", "b", "c"] {
+            s.add(item);
+        }
+        // "c" took over the counter of "b", inheriting its count of 1 as its error.
+        assert_eq!(None, s.estimate("b"));
+        assert_eq!(Some((2, 1)), s.estimate("c"));
+        assert_eq!(Some((3, 0)), s.estimate("a"));
+    }
+
+    #[test]
+    fn test_finds_heavy_hitters_in_skewed_data() {
+        let mut s: SpaceSaving<String> = SpaceSaving::new(10);
+        for i in 0..100_000u32 {
+            match i % 4 {
+                0 => s.add_borrowed("heavy"),
+                _ => s.add(i.to_string()),
+            }
+        }
+        let (count, error) = s.estimate("heavy").unwrap();
+        assert!(count - error <= 25_000 && 25_000 <= count);
+        for (item, count) in s.iter() {
+            let (_, error) = s.estimate(item).unwrap();
+            assert!(error <= s.total() / 10, "{item}: {count} ±{error}");
+        }
+    }
+
+    #[test]
+    fn test_merge_keeps_bounds() {
+        let mut s1 = SpaceSaving::new(2);
+        let mut s2 = SpaceSaving::new(2);
+        for item in ["a", "a", "a", "b", "c"] {
+            s1.add(item);
+        }
+        for item in ["b", "b", "d", "d", "d"] {
+            s2.add(item);
+        }
+        let merged = SpaceSaving::merge(&s1, &s2);
+        assert_eq!(10, merged.total());
+        assert_eq!(2, merged.iter().count());
+        // "a" is untracked by s2, which may have seen it twice, its smallest count.
+        assert_eq!(Some((5, 2)), merged.estimate("a"));
+        assert_eq!(Some((5, 2)), merged.estimate("d"));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut s = SpaceSaving::new(2);
+        for item in ["a", "a", "b", "c"] {
+            s.add(item.to_string());
+        }
+        let json = serde_json::to_string(&s).unwrap();
+        let mut loaded: SpaceSaving<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(s.estimate("c"), loaded.estimate("c"));
+        loaded.add("d".to_string());
+        assert_eq!(Some((3, 2)), loaded.estimate("d"));
+        assert_eq!(5, loaded.total());
+    }
+}
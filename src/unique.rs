@@ -0,0 +1,269 @@
+use crate::hyperloglog::hash;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::cmp::{max, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+/// A uniform sample of the distinct items added, however often each was added. Every item is
+/// keyed by a hash of its bytes, and the sample holds the `capacity` distinct items with the
+/// largest keys, so each item is held once and a frequent item is no more likely to be sampled
+/// than a rare one.
+///
+/// Unlike a `Reservoir`, the sample describes which values occur rather than how often: each
+/// sampled item has the same frequency in `to_histogram`. Since an item's key only depends on
+/// the item, samples of different streams are merged by keeping the items with the largest keys
+/// across them, giving the sample of the distinct items of every stream, and sampling the same
+/// stream twice gives the same sample.
+#[derive(Debug)]
+pub struct UniqueSample<T> {
+    capacity: usize,
+    /// The items in the sample with their keys.
+    items: Vec<(T, u64)>,
+    /// The index in `items` of each item in the sample.
+    index: HashMap<T, usize>,
+    /// Min-heap over the keys of `items`.
+    by_key: BinaryHeap<Reverse<(u64, usize)>>,
+    num_adds: u64,
+}
+
+impl<T: Eq + Hash + Clone + AsRef<[u8]>> UniqueSample<T> {
+    pub fn new(capacity: usize) -> UniqueSample<T> {
+        UniqueSample {
+            capacity,
+            items: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+            by_key: BinaryHeap::with_capacity(capacity),
+            num_adds: 0,
+        }
+    }
+
+    pub fn add(&mut self, item: T) {
+        self.add_borrowed(&item)
+    }
+
+    /// Adds a borrowed item, only making an owned copy of it when it enters the sample.
+    pub fn add_borrowed<Q>(&mut self, item: &Q)
+    where
+        T: Borrow<Q>,
+        Q: ToOwned<Owned = T> + AsRef<[u8]> + Eq + Hash + ?Sized,
+    {
+        self.num_adds += 1;
+        let key = hash(item.as_ref());
+        if self.index.contains_key(item) {
+            return;
+        }
+        self.insert(key, || item.to_owned());
+    }
+
+    /// Places the item with `key` in the sample if there is room, or if `key` is larger than the
+    /// smallest key in the sample. The item isn't in the sample already.
+    fn insert<F: FnOnce() -> T>(&mut self, key: u64, make_item: F) {
+        let i = if self.items.len() < self.capacity {
+            self.items.push((make_item(), key));
+            self.items.len() - 1
+        } else {
+            match self.by_key.peek() {
+                Some(&Reverse((smallest, i))) if key > smallest => {
+                    self.by_key.pop();
+                    self.index.remove::<T>(&self.items[i].0);
+                    self.items[i] = (make_item(), key);
+                    i
+                }
+                _ => return,
+            }
+        };
+        self.index.insert(self.items[i].0.clone(), i);
+        self.by_key.push(Reverse((key, i)));
+    }
+
+    /// Combines two samples, as if every item had been added to a single one.
+    pub fn merge(s1: &UniqueSample<T>, s2: &UniqueSample<T>) -> UniqueSample<T> {
+        UniqueSample::merge_many([s1, s2])
+    }
+
+    /// Combines any number of samples in a single pass, keeping the distinct items with the
+    /// largest keys across all of them. The merged sample has the largest capacity of those
+    /// merged.
+    pub fn merge_many<'a, I>(samples: I) -> UniqueSample<T>
+    where
+        I: IntoIterator<Item = &'a UniqueSample<T>>,
+        T: 'a,
+    {
+        let samples: Vec<&UniqueSample<T>> = samples.into_iter().collect();
+        let capacity = samples.iter().map(|s| s.capacity).fold(0, max);
+        let mut merged = UniqueSample::new(capacity);
+        for s in &samples {
+            merged.num_adds += s.num_adds;
+            for (item, key) in &s.items {
+                if !merged.index.contains_key(item) {
+                    merged.insert(*key, || item.clone());
+                }
+            }
+        }
+        merged
+    }
+}
+
+impl<T: Eq + Hash> UniqueSample<T> {
+    /// The number of items added, including repeats and those that weren't sampled.
+    pub fn num_adds(&self) -> u64 {
+        self.num_adds
+    }
+
+    /// The number of distinct items in the sample, which is at most `capacity`.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Iterates over the items in the sample, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter().map(|(item, _)| item)
+    }
+
+    /// A count of 1 for each item in the sample, and the number of items in the sample.
+    pub fn to_counts(&self) -> (HashMap<&T, u64>, u64) {
+        (
+            self.iter().map(|item| (item, 1)).collect(),
+            self.items.len() as u64,
+        )
+    }
+
+    /// The frequency of each item among the sampled distinct items, which is the same for all of
+    /// them.
+    pub fn to_histogram(&self) -> HashMap<&T, f32> {
+        let freq = 1.0 / self.items.len() as f32;
+        self.iter().map(|item| (item, freq)).collect()
+    }
+}
+
+/// The saved form of a `UniqueSample`, without the index and heap rebuilt when it's loaded.
+#[derive(Serialize, Deserialize)]
+struct UniqueSampleState<I> {
+    capacity: usize,
+    items: I,
+    num_adds: u64,
+}
+
+impl<T: Serialize> Serialize for UniqueSample<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        UniqueSampleState {
+            capacity: self.capacity,
+            items: &self.items,
+            num_adds: self.num_adds,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for UniqueSample<T>
+where
+    T: Deserialize<'de> + Eq + Hash + Clone + AsRef<[u8]>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state: UniqueSampleState<Vec<(T, u64)>> = UniqueSampleState::deserialize(deserializer)?;
+        if state.items.len() > state.capacity {
+            return Err(de::Error::custom(
+                "unique sample holds more items than its capacity",
+            ));
+        }
+        let mut sample = UniqueSample::new(state.capacity);
+        for (item, key) in state.items {
+            if !sample.index.contains_key(&item) {
+                sample.insert(key, || item);
+            }
+        }
+        sample.num_adds = state.num_adds;
+        Ok(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holds_each_item_once() {
+        let mut s = UniqueSample::new(10);
+        for item in ["a", "a", "a", "b", "a", "c"] {
+            s.add(item.to_string());
+        }
+        assert_eq!(3, s.len());
+        assert_eq!(6, s.num_adds());
+        assert_eq!(
+            Some(&(1.0f32 / 3.0)),
+            s.to_histogram().get(&"a".to_string())
+        );
+    }
+
+    #[test]
+    fn test_frequency_doesnt_matter() {
+        // One value makes up 90% of the input, but is as likely as any other to be sampled.
+        let mut s: UniqueSample<String> = UniqueSample::new(10);
+        let mut heavy_sampled = 0;
+        for round in 0..200 {
+            s = UniqueSample::new(10);
+            let heavy = format!("heavy-{round}");
+            for i in 0..1000 {
+                match i % 10 {
+                    0 => s.add(format!("{round}-{i}")),
+                    _ => s.add_borrowed(heavy.as_str()),
+                }
+            }
+            heavy_sampled += s.to_histogram().contains_key(&heavy) as u32;
+        }
+        // The heavy value is one of 101 distinct values, so it's sampled about 10% of the time.
+        assert!(
+            (5..50).contains(&heavy_sampled),
+            "heavy value sampled {heavy_sampled} times"
+        );
+        assert_eq!(10, s.len());
+    }
+
+    #[test]
+    fn test_merge_matches_single_sample() {
+        let mut s1 = UniqueSample::new(5);
+        let mut s2 = UniqueSample::new(5);
+        let mut whole = UniqueSample::new(5);
+        for i in 0..100 {
+            let item = (i % 40).to_string();
+            match i % 3 {
+                0 => s1.add(item.clone()),
+                _ => s2.add(item.clone()),
+            }
+            whole.add(item);
+        }
+        let merged = UniqueSample::merge(&s1, &s2);
+        let sorted = |s: &UniqueSample<String>| {
+            let mut items: Vec<String> = s.iter().cloned().collect();
+            items.sort();
+            items
+        };
+        assert_eq!(sorted(&whole), sorted(&merged));
+        assert_eq!(100, merged.num_adds());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut s = UniqueSample::new(3);
+        for i in 0..20 {
+            s.add(i.to_string());
+        }
+        let json = serde_json::to_string(&s).unwrap();
+        let mut loaded: UniqueSample<String> = serde_json::from_str(&json).unwrap();
+        for i in 20..40 {
+            s.add(i.to_string());
+            loaded.add(i.to_string());
+        }
+        let mut items: Vec<&String> = s.iter().collect();
+        let mut loaded_items: Vec<&String> = loaded.iter().collect();
+        items.sort();
+        loaded_items.sort();
+        assert_eq!(items, loaded_items);
+    }
+}